@@ -9,4 +9,59 @@ pub struct Ban {
     pub set_time: DateTime<Utc>,
 }
 
-impl Channel {}
\ No newline at end of file
+impl Channel {
+    // Ban-like list for a mode letter: +b bans, +e exceptions, +I invite exceptions
+    fn list_mut(&mut self, mode: char) -> Option<&mut Vec<Ban>> {
+        match mode {
+            'b' => Some(&mut self.bans),
+            'e' => Some(&mut self.excepts),
+            'I' => Some(&mut self.invex),
+            _ => None,
+        }
+    }
+
+    pub fn get_list(&self, mode: char) -> &[Ban] {
+        match mode {
+            'b' => &self.bans,
+            'e' => &self.excepts,
+            'I' => &self.invex,
+            _ => &[],
+        }
+    }
+
+    pub fn add_list_entry(&mut self, mode: char, mask: String, set_by: String) -> bool {
+        let Some(list) = self.list_mut(mode) else {
+            return false;
+        };
+        if list.iter().any(|entry| entry.mask.eq_ignore_ascii_case(&mask)) {
+            return false;
+        }
+
+        list.push(Ban {
+            mask,
+            set_by,
+            set_time: Utc::now(),
+        });
+        true
+    }
+
+    // Empties every ban-like list, returning the removed masks with their mode
+    pub fn clear_lists(&mut self) -> Vec<(char, String)> {
+        let mut removed = Vec::new();
+        for mode in ['b', 'e', 'I'] {
+            if let Some(list) = self.list_mut(mode) {
+                removed.extend(list.drain(..).map(|entry| (mode, entry.mask)));
+            }
+        }
+        removed
+    }
+
+    pub fn remove_list_entry(&mut self, mode: char, mask: &str) -> bool {
+        let Some(list) = self.list_mut(mode) else {
+            return false;
+        };
+        let len = list.len();
+        list.retain(|entry| !entry.mask.eq_ignore_ascii_case(mask));
+        list.len() != len
+    }
+}
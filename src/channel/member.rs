@@ -20,10 +20,60 @@ impl Channel {
     pub fn remove_member(&mut self, client_id: u32) {
         debug!("Removing client {} from channel {}", client_id, self.name);
         self.members.remove(&client_id);
+        self.operators.remove(&client_id);
+        self.voices.remove(&client_id);
         debug!("Channel {} now has {} members", self.name, self.members.len());
     }
 
     pub fn get_members(&self) -> &HashSet<u32> {
         &self.members
     }
+
+    pub fn set_operator(&mut self, client_id: u32, is_op: bool) {
+        if is_op {
+            self.operators.insert(client_id);
+        } else {
+            self.operators.remove(&client_id);
+        }
+    }
+
+    pub fn is_operator(&self, client_id: u32) -> bool {
+        self.operators.contains(&client_id)
+    }
+
+    pub fn set_voice(&mut self, client_id: u32, is_voiced: bool) {
+        if is_voiced {
+            self.voices.insert(client_id);
+        } else {
+            self.voices.remove(&client_id);
+        }
+    }
+
+    pub fn is_voiced(&self, client_id: u32) -> bool {
+        self.voices.contains(&client_id)
+    }
+
+    // Removes every op and voice, returning the affected members with their status mode
+    pub fn clear_statuses(&mut self) -> Vec<(u32, char)> {
+        let mut removed: Vec<(u32, char)> = self.operators.drain().map(|id| (id, 'o')).collect();
+        removed.extend(self.voices.drain().map(|id| (id, 'v')));
+        removed.sort_unstable();
+
+        // Creator status is also recorded as a mode parameter
+        self.modes.remove(&'o');
+        self.mode_params.remove(&'o');
+        removed
+    }
+
+    // SJOIN status prefix for a member
+    pub fn member_prefix(&self, client_id: u32) -> String {
+        let mut prefix = String::new();
+        if self.is_operator(client_id) {
+            prefix.push('@');
+        }
+        if self.is_voiced(client_id) {
+            prefix.push('+');
+        }
+        prefix
+    }
 }
\ No newline at end of file
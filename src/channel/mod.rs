@@ -9,6 +9,8 @@ use tracing::debug;
 use crate::channel::list::Ban;
 use crate::client::Client;
 
+pub use mode::format_mode_changes;
+
 #[cfg(test)]
 mod tests;
 mod mode;
@@ -27,6 +29,8 @@ pub struct Channel {
     mode_params: HashMap<char, String>, // For modes that take parameters like +k (key)
     created_at: u64,
     bans: Vec<Ban>,
+    excepts: Vec<Ban>,
+    invex: Vec<Ban>,
     operators: HashSet<u32>,
     voices: HashSet<u32>,
 }
@@ -44,6 +48,8 @@ impl Channel {
             mode_params: HashMap::new(),
             created_at: crate::ts6::generate_ts(),
             bans: Vec::new(),
+            excepts: Vec::new(),
+            invex: Vec::new(),
             operators: HashSet::new(),
             voices: HashSet::new(),
         };
@@ -54,5 +60,14 @@ impl Channel {
 
         channel
     }
+
+    pub fn created_at(&self) -> u64 {
+        self.created_at
+    }
+
+    pub fn set_created_at(&mut self, ts: u64) {
+        debug!("Channel {} TS changed from {} to {}", self.name, self.created_at, ts);
+        self.created_at = ts;
+    }
 }
 
@@ -1,5 +1,32 @@
 use crate::channel::Channel;
 
+// Simple modes that carry a parameter when set
+pub fn mode_takes_param(mode: char) -> bool {
+    matches!(mode, 'k' | 'l' | 'f' | 'j')
+}
+
+// Builds MODE parameters (mode string followed by mode parameters) from a list of changes
+pub fn format_mode_changes(changes: &[(char, Option<String>, bool)]) -> Vec<String> {
+    let mut mode_str = String::new();
+    let mut params = Vec::new();
+    let mut current = None;
+
+    for (mode, param, adding) in changes {
+        if current != Some(*adding) {
+            current = Some(*adding);
+            mode_str.push(if *adding { '+' } else { '-' });
+        }
+        mode_str.push(*mode);
+        if let Some(param) = param {
+            params.push(param.clone());
+        }
+    }
+
+    let mut result = vec![mode_str];
+    result.extend(params);
+    result
+}
+
 #[derive(Clone)]
 pub struct ChannelModes {
     pub(crate) invite_only: bool,
@@ -33,6 +60,65 @@ impl Channel {
         modes
     }
 
+    // Simple channel modes as sent in SJOIN: the mode string followed by parameters for +k and +l
+    pub fn get_simple_modes(&self) -> Vec<String> {
+        let mut modes: Vec<char> = self.modes.iter()
+            .copied()
+            .filter(|mode| !matches!(mode, 'o' | 'v' | 'b' | 'e' | 'I' | 'q'))
+            .collect();
+        modes.sort_unstable();
+
+        let mut result = vec![format!("+{}", modes.iter().collect::<String>())];
+        for mode in modes {
+            if let Some(param) = self.mode_params.get(&mode) {
+                result.push(param.clone());
+            }
+        }
+        result
+    }
+
+    // Removes every simple mode, returning what was removed (with the key, which -k needs)
+    pub fn clear_simple_modes(&mut self) -> Vec<(char, Option<String>)> {
+        let mut removed: Vec<char> = self.modes.iter()
+            .copied()
+            .filter(|mode| !matches!(mode, 'o' | 'v'))
+            .collect();
+        removed.sort_unstable();
+
+        removed.into_iter()
+            .map(|mode| {
+                let param = self.mode_params.get(&mode).filter(|_| mode == 'k').cloned();
+                self.set_mode(mode, None, false);
+                (mode, param)
+            })
+            .collect()
+    }
+
+    // Sets the simple modes in a mode string such as "+ntk" with its parameters, returning those that changed
+    pub fn apply_simple_modes(&mut self, modes: &str, params: &[String]) -> Vec<(char, Option<String>)> {
+        let mut params = params.iter();
+        let mut changed = Vec::new();
+
+        for mode in modes.chars().filter(|&c| c != '+') {
+            let param = if mode_takes_param(mode) {
+                match params.next() {
+                    Some(param) => Some(param.clone()),
+                    None => continue,
+                }
+            } else {
+                None
+            };
+
+            if self.modes.contains(&mode) && self.mode_params.get(&mode) == param.as_ref() {
+                continue;
+            }
+            self.set_mode(mode, param.clone(), true);
+            changed.push((mode, param));
+        }
+
+        changed
+    }
+
     pub fn set_mode(&mut self, mode: char, param: Option<String>, adding: bool) {
         if adding {
             self.modes.insert(mode);
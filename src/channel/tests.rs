@@ -65,11 +65,15 @@ mod tests {
         // Second client joins
         client2.join("#test").await.unwrap();
 
-        // Verify both clients are in channel
+        // Verify both clients are in channel, and the joining client sees its JOIN once
         let mut found_nick1 = false;
         let mut found_nick2 = false;
+        let mut joins = 0;
         loop {
             let msg = client2.read_message().await.unwrap();
+            if msg.contains(" JOIN ") {
+                joins += 1;
+            }
             if msg.contains("353") { // RPL_NAMREPLY
                 if msg.contains("@nick1") {
                     found_nick1 = true;
@@ -84,6 +88,10 @@ mod tests {
         }
         assert!(found_nick1, "First client should still be in channel");
         assert!(found_nick2, "Second client should be in channel");
+        assert_eq!(joins, 1, "Joining client should get a single JOIN");
+
+        // Existing members are told about the join
+        client1.expect_line(":nick2!user2@").await.unwrap();
     }
 
     #[tokio::test]
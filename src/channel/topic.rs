@@ -15,6 +15,19 @@ impl Channel {
         self.topic_time = Utc::now();
     }
 
+    // Sets a topic learned from another server, keeping the time it was set; an empty topic clears it
+    pub fn set_topic_at(&mut self, topic: String, setter: String, time: DateTime<Utc>) {
+        debug!("Setting topic for channel {} from the network to: {}", self.name, topic);
+        self.topic = Some(topic).filter(|topic| !topic.is_empty());
+        self.topic_setter = Some(setter);
+        self.topic_time = time;
+    }
+
+    // The topicTS sent to other servers
+    pub fn topic_ts(&self) -> i64 {
+        self.topic_time.timestamp()
+    }
+
     pub fn get_topic_details(&self) -> (Option<String>, Option<String>, DateTime<Utc>) {
         (self.topic.clone(), self.topic_setter.clone(), self.topic_time)
     }
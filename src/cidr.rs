@@ -0,0 +1,184 @@
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// An IPv4 or IPv6 network in CIDR notation. A bare address is a network of one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct IpNet {
+    addr: IpAddr, // Network address, with the host bits cleared
+    prefix: u8,
+}
+
+impl IpNet {
+    pub fn new(addr: IpAddr, prefix: u8) -> Option<Self> {
+        let addr = addr.to_canonical();
+        if prefix > max_prefix(&addr) {
+            return None;
+        }
+        let addr = match addr {
+            IpAddr::V4(v4) => IpAddr::V4(Ipv4Addr::from(u32::from(v4) & mask(prefix, 32) as u32)),
+            IpAddr::V6(v6) => IpAddr::V6(Ipv6Addr::from(u128::from(v6) & mask(prefix, 128))),
+        };
+        Some(Self { addr, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        IpNet::new(ip, self.prefix).is_some_and(|net| net.addr == self.addr)
+    }
+}
+
+fn max_prefix(addr: &IpAddr) -> u8 {
+    if addr.is_ipv4() { 32 } else { 128 }
+}
+
+// The top `prefix` bits set out of `width`
+fn mask(prefix: u8, width: u8) -> u128 {
+    match prefix {
+        0 => 0,
+        _ => (u128::MAX << (128 - prefix)) >> (128 - width),
+    }
+}
+
+// The address as bits from the most significant, and how many there are
+fn bits(addr: IpAddr) -> (u128, u8) {
+    match addr.to_canonical() {
+        IpAddr::V4(v4) => ((u32::from(v4) as u128) << 96, 32),
+        IpAddr::V6(v6) => (u128::from(v6), 128),
+    }
+}
+
+impl From<IpAddr> for IpNet {
+    fn from(addr: IpAddr) -> Self {
+        let addr = addr.to_canonical();
+        Self { addr, prefix: max_prefix(&addr) }
+    }
+}
+
+impl FromStr for IpNet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.parse().map_err(|_| format!("Invalid address {}", s))?;
+        match prefix {
+            Some(prefix) => {
+                let prefix = prefix.parse().map_err(|_| format!("Invalid prefix length in {}", s))?;
+                IpNet::new(addr, prefix).ok_or_else(|| format!("Prefix length out of range in {}", s))
+            }
+            None => Ok(IpNet::from(addr)),
+        }
+    }
+}
+
+impl fmt::Display for IpNet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.prefix == max_prefix(&self.addr) {
+            write!(f, "{}", self.addr)
+        } else {
+            write!(f, "{}/{}", self.addr, self.prefix)
+        }
+    }
+}
+
+impl Serialize for IpNet {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for IpNet {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Entries keyed by network, found by address in time bounded by the address length
+/// rather than the number of entries.
+#[derive(Debug)]
+pub struct CidrTable<T> {
+    v4: Node<T>,
+    v6: Node<T>,
+}
+
+#[derive(Debug)]
+struct Node<T> {
+    children: [Option<Box<Node<T>>>; 2],
+    value: Option<T>,
+}
+
+impl<T> Default for Node<T> {
+    fn default() -> Self {
+        Self { children: [None, None], value: None }
+    }
+}
+
+impl<T> Default for CidrTable<T> {
+    fn default() -> Self {
+        Self { v4: Node::default(), v6: Node::default() }
+    }
+}
+
+impl<T> CidrTable<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn root(&self, addr: IpAddr) -> &Node<T> {
+        if addr.is_ipv4() { &self.v4 } else { &self.v6 }
+    }
+
+    // Adds an entry for a network, returning the one it replaces
+    pub fn insert(&mut self, net: IpNet, value: T) -> Option<T> {
+        let (bits, _) = bits(net.addr);
+        let mut node = if net.addr.is_ipv4() { &mut self.v4 } else { &mut self.v6 };
+        for i in 0..net.prefix {
+            let bit = (bits >> (127 - i)) as usize & 1;
+            node = node.children[bit].get_or_insert_with(Box::default);
+        }
+        node.value.replace(value)
+    }
+
+    // Removes the entry for exactly this network; empty branches are left for later inserts
+    pub fn remove(&mut self, net: &IpNet) -> Option<T> {
+        let (bits, _) = bits(net.addr);
+        let mut node = if net.addr.is_ipv4() { &mut self.v4 } else { &mut self.v6 };
+        for i in 0..net.prefix {
+            let bit = (bits >> (127 - i)) as usize & 1;
+            node = node.children[bit].as_deref_mut()?;
+        }
+        node.value.take()
+    }
+
+    // Entries for every network containing the address, most specific first
+    pub fn matches(&self, ip: IpAddr) -> Vec<&T> {
+        let (bits, width) = bits(ip);
+        let mut node = self.root(ip.to_canonical());
+        let mut found: Vec<&T> = node.value.iter().collect();
+        for i in 0..width {
+            let bit = (bits >> (127 - i)) as usize & 1;
+            let Some(child) = node.children[bit].as_deref() else {
+                break;
+            };
+            node = child;
+            found.extend(node.value.iter());
+        }
+        found.reverse();
+        found
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        let mut values = Vec::new();
+        let mut stack = vec![&self.v4, &self.v6];
+        while let Some(node) = stack.pop() {
+            values.extend(node.value.iter());
+            stack.extend(node.children.iter().flatten().map(|child| child.as_ref()));
+        }
+        values.into_iter()
+    }
+}
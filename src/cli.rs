@@ -13,6 +13,10 @@ pub struct Cli {
     #[arg(short, long)]
     pub generate_config: bool,
 
+    /// Hash an operator password for an O-line and exit
+    #[arg(long, value_name = "PASSWORD")]
+    pub mkpasswd: Option<String>,
+
     /// Set the log level (error, warn, info, debug, trace)
     #[arg(short, long, default_value = "info")]
     pub log_level: String,
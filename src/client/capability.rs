@@ -17,7 +17,7 @@ impl Client {
         match message.params[0].as_str() {
             "LS" => {
                 // Send supported capabilities immediately and flush
-                let caps = "server-time extended-join multi-prefix message-tags";
+                let caps = "server-time extended-join multi-prefix message-tags batch";
                 self.write_raw(format!("CAP * LS :{}\r\n", caps).as_bytes()).await?;
                 self.write_raw(b"").await?; // Empty write to force flush
                 self.cap_negotiating = true;
@@ -26,6 +26,27 @@ impl Client {
                 if message.params.len() < 2 {
                     return Err(IrcError::Protocol("No capabilities requested".into()));
                 }
+                // A request is accepted or rejected as a whole, so a client is never
+                // told it has a capability this server doesn't implement
+                let requested: Result<Vec<(bool, Capability)>, ()> = message.params[1].split_whitespace()
+                    .map(|cap| match cap.strip_prefix('-') {
+                        Some(cap) => cap.parse().map(|cap| (false, cap)),
+                        None => cap.parse().map(|cap| (true, cap)),
+                    })
+                    .collect();
+                let Ok(requested) = requested else {
+                    warn!("Rejecting capability request: {}", message.params[1]);
+                    self.write_raw(format!("CAP * NAK :{}\r\n", message.params[1]).as_bytes()).await?;
+                    return Ok(());
+                };
+                for (enable, cap) in requested {
+                    if enable {
+                        self.enabled_capabilities.insert(cap);
+                    } else {
+                        self.enabled_capabilities.remove(&cap);
+                    }
+                }
+
                 // Send ACK immediately and flush
                 self.write_raw(format!("CAP * ACK :{}\r\n", message.params[1]).as_bytes()).await?;
                 self.write_raw(b"").await?; // Empty write to force flush
@@ -40,4 +61,8 @@ impl Client {
 
         Ok(())
     }
+
+    pub fn has_capability(&self, capability: Capability) -> bool {
+        self.enabled_capabilities.contains(&capability)
+    }
 } 
\ No newline at end of file
@@ -1,5 +1,10 @@
+use std::sync::Arc;
+
+use tokio::sync::RwLock;
 use tracing::{debug, warn};
 
+use crate::channel::Channel;
+
 use crate::client::Client;
 use crate::error::{IrcError, IrcResult};
 use crate::ts6::TS6Message;
@@ -13,8 +18,13 @@ impl Client {
         let channel_name = &message.params[0];
         debug!("Client {} attempting to join channel {}", self.id, channel_name);
 
+        // ERR_BADCHANNAME for reserved channels
+        if let Some(resv) = self.server.find_resv(channel_name).await {
+            return self.send_numeric(479, &[channel_name, &format!("Cannot join channel: {}", resv.reason)]).await;
+        }
+
         // Get channel info with minimal lock time
-        let (is_first, topic_info, member_list) = {
+        let (is_first, topic_info, member_list, channel_ts, channel_modes) = {
             let channel = self.server.get_or_create_channel(channel_name).await;
             let mut channel = channel.write().await;
 
@@ -33,9 +43,10 @@ impl Client {
 
             if is_first {
                 channel.set_mode('o', Some(self.get_nickname().unwrap().to_string()), true);
+                channel.set_operator(self.id, true);
             }
 
-            (is_first, topic_info, member_list)
+            (is_first, topic_info, member_list, channel.created_at(), channel.get_simple_modes())
         };
 
         // Send JOIN confirmation
@@ -48,8 +59,9 @@ impl Client {
         // First send directly to joining client
         self.send_message(&join_msg).await?;
 
-        // Then broadcast to ALL other members (no skip)
-        self.server.broadcast_to_channel(channel_name, &join_msg, None).await?;
+        // Then to the other members; the joining client already has its copy, and
+        // broadcasting to it would lock the client this handler is running under
+        self.server.broadcast_to_channel(channel_name, &join_msg, Some(self.id)).await?;
 
         // Send operator status if first user
         if is_first {
@@ -65,6 +77,23 @@ impl Client {
             self.server.broadcast_to_channel(channel_name, &mode_msg, Some(self.id)).await?;
         }
 
+        // A new channel is created on other servers with SJOIN, an existing one is joined with JOIN
+        let server_join = if is_first {
+            // :sid SJOIN ts channel modes [mode params...] :@uid
+            let mut params = vec![channel_ts.to_string(), channel_name.to_string()];
+            params.extend(channel_modes);
+            params.push(format!("@{}", self.uid));
+            TS6Message::with_source(self.server.config.server.sid.clone(), "SJOIN".to_string(), params)
+        } else {
+            // :uid JOIN ts channel +
+            TS6Message::with_source(
+                self.uid.clone(),
+                "JOIN".to_string(),
+                vec![channel_ts.to_string(), channel_name.to_string(), "+".to_string()],
+            )
+        };
+        self.server.send_to_servers(&server_join, None).await;
+
         // Send topic
         if let Some((topic, setter, time)) = topic_info {
             self.send_numeric(332, &[channel_name, &topic]).await?;
@@ -78,10 +107,17 @@ impl Client {
         // Get member info without channel lock
         let mut members = Vec::new();
         for id in member_list {
-            if let Some(client) = self.server.get_client(id).await {
-                let client = client.lock().await;
-                let nick = client.get_nickname().unwrap().to_string();
-                let modes = if is_first && id == self.id { vec!['o'] } else { Vec::new() };
+            if id == self.id {
+                let nick = self.get_nickname().unwrap().to_string();
+                let modes = if is_first { vec!['o'] } else { Vec::new() };
+                members.push((nick, modes));
+            } else if let Some(client) = self.server.get_client(id).await {
+                let nick = client.lock().await.get_nickname().unwrap().to_string();
+                let is_op = match self.server.get_channel(channel_name).await {
+                    Some(channel) => channel.read().await.is_operator(id),
+                    None => false,
+                };
+                let modes = if is_op { vec!['o'] } else { Vec::new() };
                 members.push((nick, modes));
             }
         }
@@ -120,6 +156,13 @@ impl Client {
         // Remove client from channel
         self.server.remove_from_channel(channel_name, self.id).await?;
 
+        let server_part = TS6Message::with_source(
+            self.uid.clone(),
+            "PART".to_string(),
+            vec![channel_name.to_string(), part_message.to_string()],
+        );
+        self.server.send_to_servers(&server_part, None).await;
+
         // Send PART message to parting client
         self.send_message(&part_msg).await?;
 
@@ -174,32 +217,50 @@ impl Client {
             let mut mode_params = message.params.iter().skip(2);
             let mut adding = true;
             let mut changes = Vec::new();
-
-            let mut channel = channel.write().await;
+            let mut statuses = Vec::new(); // Op and voice changes, by member
 
             for c in modes.chars() {
                 match c {
                     '+' => adding = true,
                     '-' => adding = false,
-                    'n' | 't' | 'm' | 'i' | 's' => {
-                        channel.set_mode(c, None, adding);
-                        changes.push((c, None, adding));
-                    }
+                    'n' | 't' | 'm' | 'i' | 's' => changes.push((c, None, adding)),
                     'k' => {
                         if adding {
                             if let Some(key) = mode_params.next() {
-                                channel.set_mode(c, Some(key.to_string()), true);
                                 changes.push((c, Some(key), true));
                             }
                         } else {
-                            channel.set_mode(c, None, false);
                             changes.push((c, None, false));
                         }
                     }
+                    'o' | 'v' => {
+                        let Some(nick) = mode_params.next() else {
+                            continue;
+                        };
+                        if let Some(id) = self.status_target(&channel, target, nick).await? {
+                            statuses.push((id, c, adding));
+                            changes.push((c, Some(nick), adding));
+                        }
+                    }
                     _ => continue,
                 }
             }
 
+            {
+                let mut channel = channel.write().await;
+                for (mode, param, adding) in &changes {
+                    if !matches!(mode, 'o' | 'v') {
+                        channel.set_mode(*mode, param.map(|param| param.to_string()), *adding);
+                    }
+                }
+                for &(id, mode, adding) in &statuses {
+                    match mode {
+                        'o' => channel.set_operator(id, adding),
+                        _ => channel.set_voice(id, adding),
+                    }
+                }
+            }
+
             // Broadcast mode changes
             if !changes.is_empty() {
                 let mut mode_str = String::new();
@@ -229,8 +290,8 @@ impl Client {
                         .collect(),
                 );
 
-                // Send to channel members
-                self.server.broadcast_to_channel(target, &mode_msg, None).await?;
+                // Send to the other channel members
+                self.server.broadcast_to_channel(target, &mode_msg, Some(self.id)).await?;
 
                 // Send immediate response back to the client that sent the mode command
                 let response = format!(":{} MODE {} {}", self.server_name, target, mode_str);
@@ -243,4 +304,25 @@ impl Client {
             self.handle_user_mode(message).await
         }
     }
+
+    // The member an op or voice change applies to, once the change is allowed;
+    // errors are sent to the client and give None
+    async fn status_target(&self, channel: &Arc<RwLock<Channel>>, channel_name: &str, nick: &str) -> IrcResult<Option<u32>> {
+        if !channel.read().await.is_operator(self.id) {
+            // ERR_CHANOPRIVSNEEDED (482)
+            self.send_numeric(482, &[channel_name, "You're not channel operator"]).await?;
+            return Ok(None);
+        }
+        let Some(id) = self.server.find_client_id_by_nick(nick).await else {
+            // ERR_NOSUCHNICK (401)
+            self.send_numeric(401, &[nick, "No such nick/channel"]).await?;
+            return Ok(None);
+        };
+        if !channel.read().await.get_members().contains(&id) {
+            // ERR_USERNOTINCHANNEL (441)
+            self.send_numeric(441, &[nick, channel_name, "They aren't on that channel"]).await?;
+            return Ok(None);
+        }
+        Ok(Some(id))
+    }
 } 
\ No newline at end of file
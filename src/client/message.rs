@@ -2,6 +2,7 @@ use tracing::{debug, warn};
 
 use crate::client::Client;
 use crate::error::{IrcError, IrcResult};
+use crate::server::Privilege;
 use crate::ts6::TS6Message;
 
 impl Client {
@@ -25,6 +26,12 @@ impl Client {
     pub(crate) async fn handle_message(&mut self, message: TS6Message) -> IrcResult<()> {
         debug!("Handling message: {:?}", message);
 
+        // Oper-only commands need the privileges they declare
+        let privileges = Privilege::for_command(&message.command, &message.params);
+        if !privileges.is_empty() && !self.cap_negotiating && !self.check_privileges(&privileges).await? {
+            return Ok(());
+        }
+
         match message.command.as_str() {
             // CAP must be handled first
             "CAP" => {
@@ -80,6 +87,23 @@ impl Client {
             "VERSION" => self.handle_version(message).await,
             "ADMIN" => self.handle_admin(message).await,
             "INFO" => self.handle_info(message).await,
+            "TIME" => self.handle_time(message).await,
+            "OPER" => self.handle_oper(message).await,
+            "KILL" => self.handle_kill(message).await,
+            "KLINE" => self.handle_kline(message).await,
+            "UNKLINE" => self.handle_unkline(message).await,
+            "DLINE" => self.handle_dline(message).await,
+            "UNDLINE" => self.handle_undline(message).await,
+            "GLINE" => self.handle_gline(message).await,
+            "UNGLINE" => self.handle_ungline(message).await,
+            "RESV" => self.handle_resv(message).await,
+            "UNRESV" => self.handle_unresv(message).await,
+            "STATS" => self.handle_stats(message).await,
+            "WALLOPS" => self.handle_wallops(message).await,
+            "CONNECT" => self.handle_connect(message).await,
+            "SQUIT" => self.handle_squit(message).await,
+            "LINKS" => self.handle_links(message).await,
+            "MAP" => self.handle_map(message).await,
             "WHO" => self.handle_who(message).await,
             cmd => {
                 warn!("Unknown command from client {}: {}", self.id, cmd);
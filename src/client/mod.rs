@@ -11,7 +11,7 @@ use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpStream;
 use tokio::sync::broadcast;
 use tokio::sync::mpsc::{self, UnboundedSender};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify};
 use tokio::task::JoinHandle;
 use tracing::{debug, error, info, warn};
 
@@ -22,7 +22,7 @@ use crate::channel::Channel;
 use crate::config::{HostmaskConfig, ServerConfig};
 use crate::error::{IrcError, IrcResult};
 use crate::ircv3::Capability;
-use crate::server::Server;
+use crate::server::{Privilege, Server};
 use crate::ts6::{parser::parse_message, TS6Message};
 
 mod registration;
@@ -38,19 +38,24 @@ mod channel;
 mod query;
 mod server;
 mod user;
+mod oper;
+mod xline;
 
 // Static counter for client IDs
 static NEXT_CLIENT_ID: AtomicU32 = AtomicU32::new(1);
 
-fn generate_client_id() -> u32 {
+pub(crate) fn generate_client_id() -> u32 {
     NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed)
 }
 
 pub struct Client {
     id: u32,
+    uid: String, // TS6 UID: our SID followed by six alphanumerics
     nickname: Option<String>,
+    nick_ts: u64,
     username: Option<String>,
     hostname: String,
+    realhost: String,
     ip_addr: IpAddr,
     registered: bool,
     cap_negotiating: bool,
@@ -70,6 +75,12 @@ pub struct Client {
     modes: HashSet<char>,
     ping_interval: Duration,
     ping_timeout: Duration,
+    disconnect: Arc<Notify>,     // Signals the connection handler to close, e.g. on KILL
+    quit_reason: Option<String>,
+    killed: bool,                // A KILL already removed us from the network, so no QUIT is sent
+    signon: i64,
+    last_active: Instant,        // Last PRIVMSG or NOTICE, for WHOIS idle times
+    privileges: HashSet<String>, // Granted by the O-line used with OPER
 }
 
 impl Client {
@@ -137,14 +148,26 @@ impl Client {
                     }
                 }
             }
+            // Whatever was sent before the client went away, such as its closing ERROR, still goes out
+            while let Ok(msg) = rx.try_recv().or_else(|_| sendq_rx.try_recv()) {
+                if writer.write_all(&msg).await.is_err() {
+                    break;
+                }
+            }
+            writer.flush().await.ok();
             debug!("Writer task: Channel closed, exiting");
         });
 
+        let id = generate_client_id();
+
         let mut client = Self {
-            id: generate_client_id(),
+            id,
+            uid: crate::ts6::generate_uid(&server.config.server.sid, id),
             nickname: None,
+            nick_ts: crate::ts6::generate_ts(),
             username: None,
             hostname: addr.ip().to_string(),
+            realhost: addr.ip().to_string(),
             ip_addr: addr.ip(),
             registered: false,
             cap_negotiating: false,
@@ -164,6 +187,12 @@ impl Client {
             modes: HashSet::new(),
             ping_interval,
             ping_timeout,
+            disconnect: Arc::new(Notify::new()),
+            quit_reason: None,
+            killed: false,
+            signon: Utc::now().timestamp(),
+            last_active: Instant::now(),
+            privileges: HashSet::new(),
         };
 
         client
@@ -220,10 +249,66 @@ impl Client {
         &self.hostname
     }
 
+    pub fn get_realhost(&self) -> &str {
+        &self.realhost
+    }
+
     pub fn id(&self) -> u32 {
         self.id
     }
 
+    pub fn uid(&self) -> &str {
+        &self.uid
+    }
+
+    pub fn nick_ts(&self) -> u64 {
+        self.nick_ts
+    }
+
+    // The EUID introducing this client to other servers, sent from our SID
+    pub(crate) fn euid(&self, sid: &str) -> TS6Message {
+        // EUID nick hopcount nickTS umodes username host ip uid realhost account :gecos
+        TS6Message::with_source(
+            sid.to_string(),
+            "EUID".to_string(),
+            vec![
+                self.nickname.clone().unwrap_or_default(),
+                "1".to_string(),
+                self.nick_ts.to_string(),
+                format!("+{}", self.get_modes()),
+                self.username.clone().unwrap_or_default(),
+                self.hostname.clone(),
+                crate::ts6::format_ip(self.ip_addr),
+                self.uid.clone(),
+                self.get_realhost().to_string(),
+                self.get_account().cloned().unwrap_or_else(|| "*".to_string()),
+                self.realname.clone().unwrap_or_default(),
+            ],
+        )
+    }
+
+    pub fn get_modes(&self) -> String {
+        let mut modes: Vec<char> = self.modes.iter().copied().collect();
+        modes.sort_unstable();
+        modes.into_iter().collect()
+    }
+
+    pub fn is_oper(&self) -> bool {
+        self.modes.contains(&'o')
+    }
+
+    pub fn has_privilege(&self, privilege: Privilege) -> bool {
+        self.is_oper() && self.privileges.contains(privilege.name())
+    }
+
+    pub fn signon_time(&self) -> i64 {
+        self.signon
+    }
+
+    pub fn idle_time(&self) -> u64 {
+        self.last_active.elapsed().as_secs()
+    }
+
     pub fn get_account(&self) -> Option<&String> {
         self.account.as_ref()
     }
@@ -240,6 +325,14 @@ impl Client {
             timer.abort();
         }
 
+        let reason = self.quit_reason.clone().unwrap_or_else(|| "Connection closed".to_string());
+
+        // Tell the network, unless a KILL already has
+        if self.registered && !self.killed {
+            let quit = TS6Message::with_source(self.uid.clone(), "QUIT".to_string(), vec![reason.clone()]);
+            self.server.send_to_servers(&quit, None).await;
+        }
+
         // Remove from all channels
         if let Some(ref nick) = self.nickname {
             let channels = self.server.get_client_channels(self.id).await;
@@ -247,7 +340,7 @@ impl Client {
                 let quit_msg = TS6Message::with_source(
                     self.get_prefix(),
                     "QUIT".to_string(),
-                    vec!["Connection closed".to_string()],
+                    vec![reason.clone()],
                 );
                 self.server.broadcast_to_channel(&channel, &quit_msg, Some(self.id)).await.ok();
                 self.server.remove_from_channel(&channel, self.id).await.ok();
@@ -263,32 +356,79 @@ impl Client {
         debug!("Cleanup complete for client {}", self.id);
     }
 
-    pub async fn handle_connection_with_reader(&mut self, mut reader: OwnedReadHalf) -> IrcResult<()> {
-        let mut lines = BufReader::new(reader).lines();
-
-        while let Some(line) = lines.next_line().await? {
-            debug!("Received line from client {}: {}", self.id, line);
-
-            // Parse the message - add & to borrow the line
-            if let Ok(message) = parse_message(&line) {
-                // Process the message
-                self.handle_message(message).await?;
-            } else {
-                warn!("Failed to parse message from client {}: {}", self.id, line);
-                // Optionally send an error to the client
-                self.send_numeric(421, &["Unknown command"]).await?;
+    pub async fn handle_connection_with_reader(client: &Arc<Mutex<Client>>, reader: BufReader<OwnedReadHalf>) -> IrcResult<()> {
+        let mut lines = reader.lines();
+        let disconnect = Arc::clone(&client.lock().await.disconnect);
+
+        loop {
+            tokio::select! {
+                line = lines.next_line() => {
+                    let Some(line) = line? else {
+                        break;
+                    };
+                    // Only hold the lock for one line so the server can reach this client in between
+                    client.lock().await.handle_line(&line).await?;
+                }
+                _ = disconnect.notified() => break,
             }
         }
 
         Ok(())
     }
 
+    // Closes the connection from the server side; the connection handler then cleans up as usual
+    pub(crate) async fn disconnect(&mut self, reason: &str) {
+        info!("Disconnecting client {}: {}", self.id, reason);
+        self.send_error(&format!("Closing Link: {} ({})", self.get_hostname(), reason)).await.ok();
+        self.quit_reason = Some(reason.to_string());
+        self.disconnect.notify_one();
+    }
+
+    // Disconnects a client the network has been sent a KILL for
+    pub(crate) async fn disconnect_killed(&mut self, reason: &str) {
+        self.killed = true;
+        self.disconnect(reason).await;
+    }
+
+    pub(crate) async fn handle_line(&mut self, line: &str) -> IrcResult<()> {
+        debug!("Received line from client {}: {}", self.id, line);
+
+        // Parse the message - add & to borrow the line
+        if let Ok(message) = parse_message(line) {
+            // Process the message
+            self.handle_message(message).await
+        } else {
+            warn!("Failed to parse message from client {}: {}", self.id, line);
+            // Optionally send an error to the client
+            self.send_numeric(421, &["Unknown command"]).await
+        }
+    }
+
     pub fn set_nickname(&mut self, nickname: String) -> IrcResult<()> {
         debug!("Setting nickname for client {} to {}", self.id, nickname);
         self.nickname = Some(nickname);
+        self.nick_ts = crate::ts6::generate_ts();
         Ok(())
     }
 
+    // Changes the nickname on behalf of the network, keeping the given nick TS
+    pub(crate) fn set_nickname_ts(&mut self, nickname: String, nick_ts: u64) {
+        debug!("Nickname for client {} changed to {} by the network", self.id, nickname);
+        self.nickname = Some(nickname);
+        self.nick_ts = nick_ts;
+    }
+
+    // Whether the given nickname refers to this client
+    pub(crate) fn is_nick(&self, nickname: &str) -> bool {
+        self.nickname.as_ref()
+            .is_some_and(|nick| nick.to_lowercase() == nickname.to_lowercase())
+    }
+
+    pub(crate) fn set_account(&mut self, account: Option<String>) {
+        debug!("Account for client {} set to {:?}", self.id, account);
+        self.account = account;
+    }
+
     pub fn set_hostname(&mut self, hostname: String) {
         debug!("Setting hostname for client {} to {}", self.id, hostname);
         self.hostname = hostname;
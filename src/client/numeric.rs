@@ -1,5 +1,6 @@
 use crate::client::Client;
 use crate::error::IrcResult;
+use crate::server::Numeric;
 use crate::ts6::TS6Message;
 
 impl Client {
@@ -20,4 +21,12 @@ impl Client {
         message.source = Some(self.server_name.clone());
         self.send_message(&message).await
     }
-}
\ No newline at end of file
+
+    pub(crate) async fn send_numerics(&self, numerics: Vec<Numeric>) -> IrcResult<()> {
+        for (numeric, params) in numerics {
+            let params: Vec<&str> = params.iter().map(String::as_str).collect();
+            self.send_numeric(numeric, &params).await?;
+        }
+        Ok(())
+    }
+}
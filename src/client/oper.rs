@@ -0,0 +1,140 @@
+use std::sync::Arc;
+
+use tracing::info;
+
+use crate::error::IrcResult;
+use crate::server::{verify_password, User};
+use crate::ts6::TS6Message;
+
+use super::*;
+
+impl Client {
+    pub(crate) async fn handle_oper(&mut self, message: TS6Message) -> IrcResult<()> {
+        // OPER name password
+        if message.params.len() < 2 {
+            // ERR_NEEDMOREPARAMS (461)
+            return self.send_numeric(461, &["OPER", "Not enough parameters"]).await;
+        }
+        let name = &message.params[0];
+        let password = message.params[1].clone();
+
+        let olines = self.server.find_olines(name, &self.get_mask()).await;
+        if olines.is_empty() {
+            // ERR_NOOPERHOST (491)
+            return self.send_numeric(491, &["No O-lines for your host"]).await;
+        }
+
+        // Hashing is slow by design, so it runs off the async workers
+        let oline = tokio::task::spawn_blocking(move || {
+            olines.into_iter().find(|oline| verify_password(oline, &password))
+        }).await.ok().flatten();
+        let Some(oline) = oline else {
+            info!("Failed OPER attempt as {} by {}", name, self.get_mask());
+            // ERR_PASSWDMISMATCH (464)
+            return self.send_numeric(464, &["Password incorrect"]).await;
+        };
+
+        info!("{} is now an operator as {}", self.get_mask(), oline.name);
+        self.privileges = self.server.resolve_privileges(&oline);
+        self.modes.insert('o');
+
+        let nickname = self.get_nickname().cloned().unwrap_or_default();
+        let mode = TS6Message::with_source(nickname.clone(), "MODE".to_string(), vec![nickname, "+o".to_string()]);
+        self.send_message(&mode).await?;
+        self.propagate_umode("+o").await;
+        // RPL_YOUREOPER (381)
+        self.send_numeric(381, &["You are now an IRC operator"]).await
+    }
+
+    pub(crate) async fn handle_kill(&mut self, message: TS6Message) -> IrcResult<()> {
+        // KILL nick [:reason]
+        let Some(target) = message.params.first().cloned() else {
+            // ERR_NEEDMOREPARAMS (461)
+            return self.send_numeric(461, &["KILL", "Not enough parameters"]).await;
+        };
+        let nickname = self.get_nickname().cloned().unwrap_or_default();
+        let reason = message.params.get(1).cloned().unwrap_or_else(|| nickname.clone());
+
+        let Some(id) = self.server.find_client_id_by_nick(&target).await else {
+            // ERR_NOSUCHNICK (401)
+            return self.send_numeric(401, &[&target, "No such nick/channel"]).await;
+        };
+        let remote = id != self.id() && matches!(self.server.get_user(id).await, Some(User::Remote(_)));
+        if remote && !self.check_privileges(&[Privilege::RemoteKill]).await? {
+            return Ok(());
+        }
+
+        let path = format!(
+            "{}!{}!{}!{} ({})",
+            self.server.config.server.name,
+            self.get_hostname(),
+            self.get_username().cloned().unwrap_or_default(),
+            nickname,
+            reason
+        );
+        let killer = self.get_prefix();
+        let source = self.uid.clone();
+        let server = Arc::clone(&self.server);
+        let Some(prefix) = server.kill_user(id, &killer, &source, &path, None, Some(self)).await else {
+            return Ok(());
+        };
+
+        // The notice reaches every oper, ourselves included, so it can't be sent while we are locked
+        tokio::spawn(async move {
+            let notice = format!("Received KILL message for {}. From {} Path: {}", prefix, nickname, path);
+            server.send_server_notice(&server.config.server.name, &notice).await;
+        });
+        Ok(())
+    }
+
+    pub(crate) async fn handle_wallops(&mut self, message: TS6Message) -> IrcResult<()> {
+        // WALLOPS :text
+        let Some(text) = message.params.first().cloned() else {
+            // ERR_NEEDMOREPARAMS (461)
+            return self.send_numeric(461, &["WALLOPS", "Not enough parameters"]).await;
+        };
+        let wallops = TS6Message::with_source(self.uid.clone(), "WALLOPS".to_string(), vec![text.clone()]);
+        self.server.send_to_servers(&wallops, None).await;
+
+        // Local users with +w, ourselves included, can't be reached while we are locked
+        let prefix = self.get_prefix();
+        let server = Arc::clone(&self.server);
+        tokio::spawn(async move {
+            server.send_wallops(&prefix, &text).await;
+        });
+        Ok(())
+    }
+
+    // Sends 481 or 723 unless the client has every privilege given; false means it was refused
+    pub(crate) async fn check_privileges(&self, privileges: &[Privilege]) -> IrcResult<bool> {
+        if !self.is_oper() {
+            // ERR_NOPRIVILEGES (481)
+            self.send_numeric(481, &["Permission Denied - You're not an IRC operator"]).await?;
+            return Ok(false);
+        }
+        if let Some(missing) = privileges.iter().find(|&&privilege| !self.has_privilege(privilege)) {
+            // ERR_NOPRIVS (723)
+            self.send_numeric(723, &[missing.name(), "Insufficient oper privileges."]).await?;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    // Drops operator status and its privileges
+    pub(crate) async fn deoper(&mut self) {
+        if self.modes.remove(&'o') {
+            self.privileges.clear();
+            self.propagate_umode("-o").await;
+        }
+    }
+
+    // Tells the network about a change to our user modes
+    async fn propagate_umode(&self, change: &str) {
+        let mode = TS6Message::with_source(
+            self.uid.clone(),
+            "MODE".to_string(),
+            vec![self.uid.clone(), change.to_string()],
+        );
+        self.server.send_to_servers(&mode, None).await;
+    }
+}
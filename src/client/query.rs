@@ -1,8 +1,11 @@
+use std::time::Instant;
+
 use regex::Regex;
 use tracing::{debug, warn};
 
 use crate::client::Client;
 use crate::error::{IrcError, IrcResult};
+use crate::server::{User, WhoisInfo};
 use crate::ts6::TS6Message;
 
 impl Client {
@@ -11,27 +14,22 @@ impl Client {
             return Err(IrcError::Protocol("No nickname given".into()));
         }
 
-        let target = &message.params[0];
+        // WHOIS server nick asks the named server, or the server a nick is on
+        if message.params.len() > 1 && self.hunt(&message, 0).await? {
+            return Ok(());
+        }
+
+        let target = &message.params[message.params.len() - 1];
         debug!("Processing WHOIS for target: {}", target);
 
-        if let Some(info) = self.server.find_client_info(target).await {
-            // RPL_WHOISUSER (311)
-            self.send_numeric(311, &[
-                target,
-                &info.username,
-                &info.hostname,
-                "*",
-                &info.realname
-            ]).await?;
-
-            // RPL_ENDOFWHOIS (318)
-            self.send_numeric(318, &[target, "End of /WHOIS list"]).await?;
+        let info = if self.is_nick(target) {
+            Some(self.whois_info())
         } else {
-            // ERR_NOSUCHNICK (401)
-            self.send_numeric(401, &[target, "No such nick/channel"]).await?;
-        }
+            self.server.find_client_info(target).await
+        };
 
-        Ok(())
+        let reply = self.server.whois_reply(target, info.as_ref());
+        self.send_numerics(reply).await
     }
 
     pub(crate) async fn handle_who(&mut self, message: TS6Message) -> IrcResult<()> {
@@ -49,12 +47,40 @@ impl Client {
 
             // Send WHO reply for each member
             for &member_id in channel.get_members() {
-                if let Some(member) = self.server.get_client(member_id).await {
+                if member_id == self.id {
+                    let nick = self.get_nickname().unwrap();
+                    let modes = if channel.is_operator(member_id) { "@" } else { "" };
+                    self.send_numeric(352, &[
+                        target,
+                        self.get_username().unwrap(),
+                        self.get_hostname(),
+                        self.server_name.as_str(),
+                        nick,
+                        &format!("H{}", modes),
+                        "0",
+                        &self.get_realname().map_or_else(String::new, |s| s.to_string()),
+                    ]).await?;
+                } else if let Some(member) = self.server.get_remote_user(member_id).await {
+                    let modes = if channel.is_operator(member_id) { "@" } else { "" };
+                    let (server, _) = self.server.get_server_info(&member.server).await;
+
+                    // RPL_WHOREPLY
+                    self.send_numeric(352, &[
+                        target,
+                        &member.username,
+                        &member.hostname,
+                        &server,
+                        &member.nickname,
+                        &format!("H{}", modes),
+                        "1",
+                        &member.realname,
+                    ]).await?;
+                } else if let Some(member) = self.server.get_client(member_id).await {
                     let member = member.lock().await;
                     let nick = member.get_nickname().unwrap();
                     let user = member.get_username().unwrap();
                     let host = member.get_hostname();
-                    let modes = if channel.has_mode('o', Some(nick)) { "@" } else { "" };
+                    let modes = if channel.is_operator(member_id) { "@" } else { "" };
 
                     // RPL_WHOREPLY
                     self.send_numeric(352, &[
@@ -71,22 +97,51 @@ impl Client {
             }
         } else {
             // User WHO
-            if let Some(client) = self.server.find_client_by_nick(target).await {
-                let client = client.lock().await;
-                let nick = client.get_nickname().unwrap();
-                let user = client.get_username().unwrap();
-                let host = client.get_hostname();
-
+            if self.is_nick(target) {
                 self.send_numeric(352, &[
                     "*",
-                    user,
-                    host,
+                    self.get_username().unwrap(),
+                    self.get_hostname(),
                     self.server_name.as_str(),
-                    nick,
+                    self.get_nickname().unwrap(),
                     "H",
                     "0",
-                    &client.get_realname().map_or_else(String::new, |s| s.to_string()),
+                    &self.get_realname().map_or_else(String::new, |s| s.to_string()),
                 ]).await?;
+            } else {
+                match self.server.find_client_by_nick(target).await {
+                    Some(User::Local(client)) => {
+                        let client = client.lock().await;
+                        let nick = client.get_nickname().unwrap();
+                        let user = client.get_username().unwrap();
+                        let host = client.get_hostname();
+
+                        self.send_numeric(352, &[
+                            "*",
+                            user,
+                            host,
+                            self.server_name.as_str(),
+                            nick,
+                            "H",
+                            "0",
+                            &client.get_realname().map_or_else(String::new, |s| s.to_string()),
+                        ]).await?;
+                    }
+                    Some(User::Remote(user)) => {
+                        let (server, _) = self.server.get_server_info(&user.server).await;
+                        self.send_numeric(352, &[
+                            "*",
+                            &user.username,
+                            &user.hostname,
+                            &server,
+                            &user.nickname,
+                            "H",
+                            "1",
+                            &user.realname,
+                        ]).await?;
+                    }
+                    None => {}
+                }
             }
         }
 
@@ -101,6 +156,7 @@ impl Client {
         if !self.registered {
             return Err(IrcError::Protocol("You must register first".into()));
         }
+        self.last_active = Instant::now();
 
         // Check for required parameters
         let (target, text) = match (message.params.get(0), message.params.get(1)) {
@@ -125,20 +181,46 @@ impl Client {
             );
 
             // Broadcast to channel (excluding sender)
-            self.server.broadcast_to_channel(target, &msg, Some(self.id)).await
+            self.server.broadcast_to_channel(target, &msg, Some(self.id)).await?;
+
+            // And once to each server with members in it
+            let server_msg = TS6Message::with_source(
+                self.uid.clone(),
+                "PRIVMSG".to_string(),
+                vec![target.to_string(), text.to_string()],
+            );
+            self.server.send_to_channel_servers(target, &server_msg, None).await;
+            Ok(())
         } else {
             // Handle private messages to users
-            if let Some(target_client) = self.server.find_client_by_nick(target).await {
-                let msg = TS6Message::with_source(
-                    self.get_prefix(),
-                    "PRIVMSG".to_string(),
-                    vec![target.to_string(), text.to_string()],
-                );
+            match self.server.find_client_by_nick(target).await {
+                Some(User::Local(target_client)) => {
+                    let msg = TS6Message::with_source(
+                        self.get_prefix(),
+                        "PRIVMSG".to_string(),
+                        vec![target.to_string(), text.to_string()],
+                    );
 
-                let target_client = target_client.lock().await;
-                target_client.send_message(&msg).await
-            } else {
-                self.send_numeric(401, &[target, "No such nick/channel"]).await
+                    if self.is_nick(target) {
+                        return self.send_message(&msg).await;
+                    }
+                    let target_client = target_client.lock().await;
+                    target_client.send_message(&msg).await
+                }
+                Some(User::Remote(user)) => {
+                    // Servers address users by UID
+                    let msg = TS6Message::with_source(
+                        self.uid.clone(),
+                        "PRIVMSG".to_string(),
+                        vec![user.uid.clone(), text.to_string()],
+                    );
+                    if let Err(e) = self.server.send_to_remote_user(&user, &msg).await {
+                        warn!("Dropping PRIVMSG to {}: {}", user.nickname, e);
+                        return self.send_numeric(401, &[target, "No such nick/channel"]).await;
+                    }
+                    Ok(())
+                }
+                None => self.send_numeric(401, &[target, "No such nick/channel"]).await,
             }
         }
     }
@@ -148,6 +230,7 @@ impl Client {
         if !self.registered {
             return Ok(()); // Silently ignore per RFC
         }
+        self.last_active = Instant::now();
 
         // Check for required parameters
         let (target, text) = match (message.params.get(0), message.params.get(1)) {
@@ -170,21 +253,58 @@ impl Client {
 
                 // Broadcast to channel (excluding sender)
                 self.server.broadcast_to_channel(target, &msg, Some(self.id)).await?;
-            }
-        } else {
-            // Handle private notices to users
-            if let Some(target_client) = self.server.find_client_by_nick(target).await {
-                let msg = TS6Message::with_source(
-                    self.get_prefix(),
+
+                let server_msg = TS6Message::with_source(
+                    self.uid.clone(),
                     "NOTICE".to_string(),
                     vec![target.to_string(), text.to_string()],
                 );
+                self.server.send_to_channel_servers(target, &server_msg, None).await;
+            }
+        } else {
+            // Handle private notices to users
+            match self.server.find_client_by_nick(target).await {
+                Some(User::Local(target_client)) => {
+                    let msg = TS6Message::with_source(
+                        self.get_prefix(),
+                        "NOTICE".to_string(),
+                        vec![target.to_string(), text.to_string()],
+                    );
 
-                let target_client = target_client.lock().await;
-                target_client.send_message(&msg).await?;
+                    if self.is_nick(target) {
+                        self.send_message(&msg).await?;
+                    } else {
+                        let target_client = target_client.lock().await;
+                        target_client.send_message(&msg).await?;
+                    }
+                }
+                Some(User::Remote(user)) => {
+                    let msg = TS6Message::with_source(
+                        self.uid.clone(),
+                        "NOTICE".to_string(),
+                        vec![user.uid.clone(), text.to_string()],
+                    );
+                    // Notices never get an automatic reply, so one that can't be delivered is dropped
+                    if let Err(e) = self.server.send_to_remote_user(&user, &msg).await {
+                        warn!("Dropping NOTICE to {}: {}", user.nickname, e);
+                    }
+                }
+                None => {}
             }
         }
 
         Ok(())
     }
-} 
\ No newline at end of file
+
+    fn whois_info(&self) -> WhoisInfo {
+        WhoisInfo {
+            nickname: self.get_nickname().cloned().unwrap_or_default(),
+            username: self.get_username().cloned().unwrap_or_default(),
+            hostname: self.get_hostname().to_string(),
+            realname: self.get_realname().cloned().unwrap_or_default(),
+            server: self.server_name.clone(),
+            server_info: self.server.config.server.description.clone(),
+            idle: Some((self.idle_time(), self.signon_time())),
+        }
+    }
+}
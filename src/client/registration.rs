@@ -2,6 +2,7 @@ use chrono::Local;
 use tracing::{debug, info};
 
 use crate::error::IrcResult;
+use crate::ts6::TS6Message;
 
 use super::Client;
 
@@ -15,6 +16,13 @@ impl Client {
             self.username.is_some() &&
             !self.cap_negotiating {
             debug!("All registration requirements met for client {}, completing registration", self.id);
+            let nickname = self.nickname.clone().unwrap_or_default();
+            let username = self.username.clone().unwrap_or_default();
+            if let Some(reason) = self.server.find_user_ban(&nickname, &username, &self.hostname, self.ip_addr).await {
+                info!("Rejecting banned client {}: {}", self.get_mask(), reason);
+                self.reject_banned(&reason).await;
+                return Ok(());
+            }
             self.complete_registration().await?;
         } else {
             debug!("Client {} not ready for registration", self.id);
@@ -27,6 +35,7 @@ impl Client {
         debug!("Sending registration messages to client {}", self.id);
 
         self.registered = true;
+        self.signon = chrono::Utc::now().timestamp();
 
         // Send welcome messages
         self.send_numeric(001, &[&format!("Welcome to {} {}", self.server_name, self.get_mask())]).await?;
@@ -38,12 +47,7 @@ impl Client {
         self.send_numeric(005, &["CHANTYPES=# EXCEPTS INVEX CHANMODES=eIbq,k,flj,CFLMPQScgimnprstuz CHANLIMIT=#:100 PREFIX=(ov)@+ MAXLIST=bqeI:100 MODES=4 NETWORK=ExampleNet STATUSMSG=@+ CALLERID=g CASEMAPPING=rfc1459 :are supported by this server"]).await?;
 
         // Send LUSERS
-        self.send_numeric(251, &[&format!("There are {} users and {} invisible on {} server", 0, 0, 1)]).await?;
-        self.send_numeric(252, &["0", "operator(s) online"]).await?;
-        self.send_numeric(254, &["0", "channels formed"]).await?;
-        self.send_numeric(255, &[&format!("I have {} clients and {} servers", 1, 0)]).await?;
-        self.send_numeric(265, &[&format!("Current local users: {}, Max: {}", 1, 1)]).await?;
-        self.send_numeric(266, &[&format!("Current global users: {}, Max: {}", 1, 1)]).await?;
+        self.handle_lusers(TS6Message::new("LUSERS".to_string(), vec![])).await?;
 
         // Send MOTD
         self.send_numeric(375, &["- Message of the day"]).await?;
@@ -53,10 +57,27 @@ impl Client {
         // Start ping timer after registration is complete
         self.start_ping_timer();
 
+        // Introduce the new user to the network
+        let euid = self.euid(&self.server.config.server.sid);
+        self.server.introduce_user(&euid).await;
+
         debug!("Completed registration sequence for client {}", self.id);
         Ok(())
     }
 
+    // Tells a banned client why before closing; the numeric is written directly so it precedes the ERROR
+    pub(crate) async fn reject_banned(&mut self, reason: &str) {
+        let nickname = self.nickname.clone().unwrap_or_else(|| "*".to_string());
+        // ERR_YOUREBANNEDCREEP (465)
+        let banned = TS6Message::with_source(
+            self.server_name.clone(),
+            "465".to_string(),
+            vec![nickname, format!("You are banned from this server- {}", reason)],
+        );
+        self.write_raw(banned.to_string().as_bytes()).await.ok();
+        self.disconnect(reason).await;
+    }
+
     pub fn is_registered(&self) -> bool {
         self.registered
     }
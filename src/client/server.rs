@@ -1,10 +1,10 @@
 use std::time::Instant;
 
-use chrono::Local;
 use tracing::debug;
 use tracing::warn;
 
 use crate::error::{IrcError, IrcResult};
+use crate::server::HuntTarget;
 use crate::ts6::TS6Message;
 
 use super::*;
@@ -33,75 +33,133 @@ impl Client {
         Ok(())
     }
 
-    pub(crate) async fn handle_motd(&mut self, _message: TS6Message) -> IrcResult<()> {
-        // RPL_MOTDSTART (375)
-        self.send_numeric(375, &["- Message of the day"]).await?;
-        // RPL_MOTD (372)
-        self.send_numeric(372, &["- Welcome to IRCd-rs!"]).await?;
-        // RPL_ENDOFMOTD (376)
-        self.send_numeric(376, &["End of /MOTD command."]).await?;
-        Ok(())
+    // Forwards a command to the server its hunted parameter names; false means it is answered here
+    pub(crate) async fn hunt(&self, message: &TS6Message, index: usize) -> IrcResult<bool> {
+        let Some(target) = message.params.get(index) else {
+            return Ok(false);
+        };
+
+        match self.server.hunt_server(target).await {
+            HuntTarget::Local => Ok(false),
+            HuntTarget::Remote(link, server) => {
+                let mut params = message.params.clone();
+                params[index] = server;
+                let msg = TS6Message::with_source(self.uid.clone(), message.command.clone(), params);
+                link.lock().await.send_message(&msg).await?;
+                Ok(true)
+            }
+            HuntTarget::NotFound => {
+                // ERR_NOSUCHSERVER (402)
+                self.send_numeric(402, &[target, "No such server"]).await?;
+                Ok(true)
+            }
+        }
     }
 
-    pub(crate) async fn handle_version(&mut self, _message: TS6Message) -> IrcResult<()> {
-        self.send_numeric(351, &[
-            "ircd-rs-0.1.0",
-            &self.server_name,
-            "Available on GitHub"
-        ]).await?;
-        Ok(())
+    pub(crate) async fn handle_motd(&mut self, message: TS6Message) -> IrcResult<()> {
+        if self.hunt(&message, 0).await? {
+            return Ok(());
+        }
+        self.send_numerics(self.server.motd_reply()).await
     }
 
-    pub(crate) async fn handle_admin(&mut self, _message: TS6Message) -> IrcResult<()> {
-        // RPL_ADMINME (256)
-        self.send_numeric(256, &[&self.server_name, "Administrative info"]).await?;
-        // RPL_ADMINLOC1 (257)
-        self.send_numeric(257, &["Location: Earth"]).await?;
-        // RPL_ADMINLOC2 (258)
-        self.send_numeric(258, &["Server Info"]).await?;
-        // RPL_ADMINEMAIL (259)
-        self.send_numeric(259, &["admin@example.com"]).await?;
-        Ok(())
+    pub(crate) async fn handle_version(&mut self, message: TS6Message) -> IrcResult<()> {
+        if self.hunt(&message, 0).await? {
+            return Ok(());
+        }
+        self.send_numerics(self.server.version_reply()).await
     }
 
-    pub(crate) async fn handle_info(&mut self, _message: TS6Message) -> IrcResult<()> {
-        // RPL_INFO (371)
-        self.send_numeric(371, &["IRCd-rs Server"]).await?;
-        self.send_numeric(371, &["Written in Rust"]).await?;
-        // RPL_ENDOFINFO (374)
-        self.send_numeric(374, &["End of /INFO list"]).await?;
-        Ok(())
+    pub(crate) async fn handle_admin(&mut self, message: TS6Message) -> IrcResult<()> {
+        if self.hunt(&message, 0).await? {
+            return Ok(());
+        }
+        self.send_numerics(self.server.admin_reply()).await
     }
 
-    pub(crate) async fn handle_lusers(&mut self, _message: TS6Message) -> IrcResult<()> {
-        let stats = self.server.get_stats().await;
-
-        // RPL_LUSERCLIENT (251)
-        self.send_numeric(251, &[&format!(
-            "There are {} users and {} invisible on 1 server",
-            stats.visible_users,
-            stats.invisible_users
-        )]).await?;
-
-        // RPL_LUSEROP (252)
-        self.send_numeric(252, &[
-            &stats.oper_count.to_string(),
-            "operator(s) online"
-        ]).await?;
-
-        // RPL_LUSERCHANNELS (254)
-        self.send_numeric(254, &[
-            &stats.channel_count.to_string(),
-            "channels formed"
-        ]).await?;
-
-        // RPL_LUSERME (255)
-        self.send_numeric(255, &[&format!(
-            "I have {} clients and {} servers",
-            stats.local_users,
-            stats.local_servers
-        )]).await?;
+    pub(crate) async fn handle_info(&mut self, message: TS6Message) -> IrcResult<()> {
+        if self.hunt(&message, 0).await? {
+            return Ok(());
+        }
+        self.send_numerics(self.server.info_reply()).await
+    }
 
-        Ok(())
+    pub(crate) async fn handle_time(&mut self, message: TS6Message) -> IrcResult<()> {
+        if self.hunt(&message, 0).await? {
+            return Ok(());
+        }
+        self.send_numerics(self.server.time_reply()).await
+    }
+
+    pub(crate) async fn handle_lusers(&mut self, message: TS6Message) -> IrcResult<()> {
+        // LUSERS [mask [server]]
+        if self.hunt(&message, 1).await? {
+            return Ok(());
+        }
+        let reply = self.server.lusers_reply(Some(self)).await;
+        self.send_numerics(reply).await
+    }
+
+    pub(crate) async fn handle_links(&mut self, message: TS6Message) -> IrcResult<()> {
+        // LINKS [[remote] mask]
+        let mask = message.params.last().map(String::as_str).unwrap_or("*");
+        let reply = self.server.links_reply(mask, self.is_oper()).await;
+        self.send_numerics(reply).await
+    }
+
+    pub(crate) async fn handle_map(&mut self, _message: TS6Message) -> IrcResult<()> {
+        if !self.check_privileges(&[]).await? {
+            return Ok(());
+        }
+        let reply = self.server.map_reply(self).await;
+        self.send_numerics(reply).await
+    }
+
+    pub(crate) async fn handle_connect(&mut self, message: TS6Message) -> IrcResult<()> {
+        // CONNECT target [port [server]]
+        let Some(target) = message.params.first() else {
+            // ERR_NEEDMOREPARAMS (461)
+            return self.send_numeric(461, &["CONNECT", "Not enough parameters"]).await;
+        };
+        if self.hunt(&message, 2).await? {
+            return Ok(());
+        }
+        let port = message.params.get(1).and_then(|port| port.parse().ok()).filter(|&port| port != 0);
+        let notice = self.server.connect_on_request(target, port).await;
+        self.send_notice(&notice).await
+    }
+
+    pub(crate) async fn handle_squit(&mut self, message: TS6Message) -> IrcResult<()> {
+        // SQUIT server [:reason]
+        let Some(target) = message.params.first() else {
+            // ERR_NEEDMOREPARAMS (461)
+            return self.send_numeric(461, &["SQUIT", "Not enough parameters"]).await;
+        };
+        let nickname = self.get_nickname().cloned().unwrap_or_default();
+        let reason = message.params.get(1).cloned().unwrap_or_else(|| nickname.clone());
+        let Some(server) = self.server.find_server_by_name(target).await else {
+            // ERR_NOSUCHSERVER (402)
+            return self.send_numeric(402, &[target, "No such server"]).await;
+        };
+
+        // Our own links are closed here, anything further away by its uplink
+        if let Some(link) = self.server.get_linked_server(&server.name).await {
+            info!("{} used SQUIT on {}: {}", nickname, server.name, reason);
+            link.lock().await.squit(&reason).await;
+            return Ok(());
+        }
+        if !self.check_privileges(&[Privilege::RemoteSquit]).await? {
+            return Ok(());
+        }
+        let Some(route) = self.server.route_to_server(&server.name).await else {
+            return self.send_numeric(402, &[target, "No such server"]).await;
+        };
+        let squit = TS6Message::with_source(
+            self.uid.clone(),
+            "SQUIT".to_string(),
+            vec![server.sid.clone().unwrap_or(server.name), reason],
+        );
+        let route = route.lock().await;
+        route.send_message(&squit).await
     }
-} 
\ No newline at end of file
+}
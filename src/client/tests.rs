@@ -6,8 +6,9 @@ mod tests {
 
     use tokio::time::{Duration, sleep};
 
-    use crate::config::ServerConfig;
-    use crate::server::Server;
+    use crate::config::{DatabaseConfig, OLine, PrivSet, ServerConfig};
+    use crate::ircv3::Capability;
+    use crate::server::{hash_password, Privilege, Server, User};
     use crate::test_utils::TestClient;
 
     // Each test gets its own port in the 6910 range
@@ -16,6 +17,10 @@ mod tests {
     const PORT_CLIENT_REGISTRATION: u16 = 6913;
     const PORT_CLIENT_MODES: u16 = 6914;
     const PORT_CLIENT_PING: u16 = 6915;
+    const PORT_OPER: u16 = 6916;
+    const PORT_OPER_PRIVILEGES: u16 = 6917;
+    const PORT_BAN_COMMANDS: u16 = 6918;
+    const PORT_CAPABILITY_NAK: u16 = 6926;
 
     // Helper function to create a test config
     fn test_config(port: u16) -> ServerConfig {
@@ -30,6 +35,7 @@ mod tests {
             network: crate::config::Network {
                 name: "TestNet".to_string(),
                 links: vec![],
+                flatten_links: false,
             },
             limits: crate::config::Limits {
                 max_clients: 100,
@@ -129,6 +135,35 @@ mod tests {
         assert!(!client.has_capability("extended-join"));
     }
 
+    #[tokio::test]
+    async fn test_capability_nak() {
+        let server = Arc::new(Server::new(test_config(PORT_CAPABILITY_NAK)).await.unwrap());
+        start_server(Arc::clone(&server), PORT_CAPABILITY_NAK).await;
+
+        let addr: SocketAddr = format!("127.0.0.1:{}", PORT_CAPABILITY_NAK).parse().unwrap();
+        let mut client = TestClient::connect(addr).await.unwrap();
+        client.send_cap_ls().await.unwrap();
+        client.handle_cap_ls().await.unwrap();
+
+        // One unsupported capability rejects the whole request
+        client.send_raw("CAP REQ :batch bogus-cap").await.unwrap();
+        assert!(client.handle_cap_ack().await.is_err(), "Request with an unknown capability was acknowledged");
+        client.send_raw("CAP REQ :multi-prefix").await.unwrap();
+        client.handle_cap_ack().await.unwrap();
+        client.send_cap_end().await.unwrap();
+        client.send_nick("capnick").await.unwrap();
+        client.send_user("capuser", "test.com").await.unwrap();
+        // CAP replies are followed by empty flush lines, which expect_line takes for a close
+        while !client.read_message().await.unwrap().contains(" 001 ") {}
+
+        let Some(User::Local(capclient)) = server.find_client_by_nick("capnick").await else {
+            panic!("capnick is not a local user");
+        };
+        let capclient = capclient.lock().await;
+        assert!(capclient.has_capability(Capability::MultiPrefix));
+        assert!(!capclient.has_capability(Capability::Batch));
+    }
+
     #[tokio::test]
     async fn test_client_registration() {
         let server = Arc::new(Server::new(test_config(PORT_CLIENT_REGISTRATION)).await.unwrap());
@@ -205,4 +240,187 @@ mod tests {
         let response = client.read_message().await.unwrap();
         assert!(response.starts_with("PONG"));
     }
-} 
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_oper() {
+        let mut config = test_config(PORT_OPER);
+        config.access.olines.push(OLine {
+            name: "admin".to_string(),
+            mask: "*!*@*".to_string(),
+            password: hash_password("secret").unwrap(),
+            encrypted: true,
+            flags: vec!["kill".to_string()],
+            privset: None,
+        });
+        let server = Arc::new(Server::new(config).await.unwrap());
+        let server_clone = Arc::clone(&server);
+        tokio::spawn(async move {
+            server_clone.run().await.unwrap();
+        });
+
+        let addr: SocketAddr = format!("127.0.0.1:{}", PORT_OPER).parse().unwrap();
+        wait_for_server(&addr).await;
+
+        let mut client = TestClient::connect(addr).await.unwrap();
+        client.register("testnick", "testuser", "test.com").await.unwrap();
+
+        // Clients can't make themselves operators
+        client.send_raw("MODE testnick +oO").await.unwrap();
+        client.send_raw("MODE testnick").await.unwrap();
+        assert!(client.expect_line(" 221 ").await.unwrap().ends_with(":+"));
+
+        client.send_raw("OPER admin").await.unwrap();
+        client.expect_line(":test.server 461 testnick OPER :Not enough parameters").await.unwrap();
+        client.send_raw("OPER nobody secret").await.unwrap();
+        client.expect_line(":test.server 491 testnick :No O-lines for your host").await.unwrap();
+        client.send_raw("OPER admin wrong").await.unwrap();
+        client.expect_line(":test.server 464 testnick :Password incorrect").await.unwrap();
+
+        client.send_raw("OPER admin secret").await.unwrap();
+        assert_eq!(client.read_message().await.unwrap(), ":testnick MODE testnick :+o");
+        client.expect_line(":test.server 381 testnick :You are now an IRC operator").await.unwrap();
+        match server.find_client_by_nick("testnick").await {
+            Some(User::Local(oper)) => assert!(oper.lock().await.has_privilege(Privilege::Kill)),
+            _ => panic!("testnick is not a local user"),
+        }
+
+        // Deopering drops the privileges
+        client.send_raw("MODE testnick -o").await.unwrap();
+        client.send_raw("MODE testnick").await.unwrap();
+        assert!(client.expect_line(" 221 ").await.unwrap().ends_with(":+"));
+        match server.find_client_by_nick("testnick").await {
+            Some(User::Local(oper)) => assert!(!oper.lock().await.has_privilege(Privilege::Kill)),
+            _ => panic!("testnick is not a local user"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_oper_privileges() {
+        let mut config = test_config(PORT_OPER_PRIVILEGES);
+        config.access.privsets.push(PrivSet {
+            name: "oper".to_string(),
+            extends: None,
+            privileges: vec!["kline".to_string()],
+        });
+        config.access.olines.push(OLine {
+            name: "helper".to_string(),
+            mask: "*!*@*".to_string(),
+            password: "helperpass".to_string(),
+            encrypted: false,
+            flags: vec!["wallops".to_string()],
+            privset: Some("oper".to_string()),
+        });
+        let server = Arc::new(Server::new(config).await.unwrap());
+        let server_clone = Arc::clone(&server);
+        tokio::spawn(async move {
+            server_clone.run().await.unwrap();
+        });
+
+        let addr: SocketAddr = format!("127.0.0.1:{}", PORT_OPER_PRIVILEGES).parse().unwrap();
+        wait_for_server(&addr).await;
+
+        let mut client = TestClient::connect(addr).await.unwrap();
+        client.register("testnick", "testuser", "test.com").await.unwrap();
+
+        client.send_raw("SQUIT some.server").await.unwrap();
+        client.expect_line(":test.server 481 testnick :Permission Denied - You're not an IRC operator").await.unwrap();
+
+        client.send_raw("OPER helper helperpass").await.unwrap();
+        client.expect_line(" 381 ").await.unwrap();
+
+        // Errors name the missing privilege, including remote variants
+        client.send_raw("SQUIT some.server").await.unwrap();
+        client.expect_line(":test.server 723 testnick squit :Insufficient oper privileges.").await.unwrap();
+        client.send_raw("KLINE 60 *@bad.host ON other.server :Go away").await.unwrap();
+        client.expect_line(":test.server 723 testnick remoteban :Insufficient oper privileges.").await.unwrap();
+        match server.find_client_by_nick("testnick").await {
+            Some(User::Local(oper)) => {
+                let oper = oper.lock().await;
+                assert!(oper.has_privilege(Privilege::Kline));
+                assert!(oper.has_privilege(Privilege::Wallops));
+                assert!(!oper.has_privilege(Privilege::Kill));
+            }
+            _ => panic!("testnick is not a local user"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ban_commands() {
+        let db_path = std::env::temp_dir().join(format!("ircd-rs-bans-{}.json", PORT_BAN_COMMANDS));
+        std::fs::remove_file(&db_path).ok();
+        let mut config = test_config(PORT_BAN_COMMANDS);
+        config.database = Some(DatabaseConfig {
+            path: db_path.to_string_lossy().into_owned(),
+            persist_lines: true,
+        });
+        config.access.olines.push(OLine {
+            name: "banner".to_string(),
+            mask: "*!*@*".to_string(),
+            password: "banpass".to_string(),
+            encrypted: false,
+            flags: vec!["kline".to_string(), "dline".to_string(), "gline".to_string()],
+            privset: None,
+        });
+        let server = Arc::new(Server::new(config.clone()).await.unwrap());
+        start_server(Arc::clone(&server), PORT_BAN_COMMANDS).await;
+        let addr: SocketAddr = format!("127.0.0.1:{}", PORT_BAN_COMMANDS).parse().unwrap();
+
+        let mut oper = TestClient::connect(addr).await.unwrap();
+        oper.register("opernick", "operuser", "test.com").await.unwrap();
+        oper.send_raw("STATS k").await.unwrap();
+        oper.expect_line(" 481 opernick ").await.unwrap();
+        oper.send_raw("OPER banner banpass").await.unwrap();
+        oper.expect_line(" 381 ").await.unwrap();
+
+        // Missing parameters are refused without closing the connection
+        oper.send_raw("KLINE").await.unwrap();
+        oper.expect_line(":test.server 461 opernick KLINE :Not enough parameters").await.unwrap();
+        oper.send_raw("UNDLINE").await.unwrap();
+        oper.expect_line(":test.server 461 opernick UNDLINE :Not enough parameters").await.unwrap();
+
+        // New K-lines disconnect users already connected
+        let mut victim = TestClient::connect(addr).await.unwrap();
+        victim.register("victim", "baduser", "test.com").await.unwrap();
+        oper.send_raw("KLINE 10 baduser@* :Go away").await.unwrap();
+        oper.expect_line("opernick added temporary 10 min. K-Line for [baduser@*] [Go away]").await.unwrap();
+        victim.expect_line(":test.server 465 victim :You are banned from this server- Go away").await.unwrap();
+        victim.expect_line("ERROR :Closing Link").await.unwrap();
+        oper.expect_line("Disconnected banned user victim!baduser@127.0.0.1").await.unwrap();
+
+        oper.send_raw("DLINE 192.0.2.0/24 :Bad network").await.unwrap();
+        oper.expect_line("opernick added D-Line for [192.0.2.0/24] [Bad network]").await.unwrap();
+        oper.send_raw("GLINE spammer@*.example :Spam").await.unwrap();
+        oper.expect_line("opernick added G-Line for [spammer@*.example] [Spam]").await.unwrap();
+
+        oper.send_raw("STATS k").await.unwrap();
+        oper.expect_line(":test.server 216 opernick k * * baduser :Go away").await.unwrap();
+        oper.expect_line(":test.server 219 opernick k :End of /STATS report").await.unwrap();
+        oper.send_raw("STATS d").await.unwrap();
+        oper.expect_line(":test.server 225 opernick D 192.0.2.0/24 :Bad network").await.unwrap();
+        oper.send_raw("STATS g").await.unwrap();
+        oper.expect_line(":test.server 223 opernick G *.example * spammer :Spam").await.unwrap();
+
+        // Lines persist across restarts until removed
+        let restarted = Server::new(config.clone()).await.unwrap();
+        assert_eq!(restarted.get_klines().await.len(), 1);
+        assert_eq!(restarted.get_dlines().await.len(), 1);
+        assert_eq!(restarted.get_glines().await.len(), 1);
+
+        oper.send_raw("UNKLINE baduser@*").await.unwrap();
+        oper.expect_line("opernick has removed the K-Line for: [baduser@*]").await.unwrap();
+        oper.send_raw("UNKLINE baduser@*").await.unwrap();
+        oper.expect_line(":test.server NOTICE opernick :No K-Line for baduser@*").await.unwrap();
+        oper.send_raw("UNDLINE 192.0.2.0/24").await.unwrap();
+        oper.expect_line("opernick has removed the D-Line for: [192.0.2.0/24]").await.unwrap();
+        oper.send_raw("UNGLINE spammer@*.example").await.unwrap();
+        oper.expect_line("opernick has removed the G-Line for: [spammer@*.example]").await.unwrap();
+
+        let mut victim = TestClient::connect(addr).await.unwrap();
+        victim.register("victim", "baduser", "test.com").await.unwrap();
+        let restarted = Server::new(config).await.unwrap();
+        assert!(restarted.get_klines().await.is_empty());
+        assert!(restarted.get_dlines().await.is_empty());
+        assert!(restarted.get_glines().await.is_empty());
+        std::fs::remove_file(&db_path).ok();
+    }
+}
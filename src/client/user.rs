@@ -18,6 +18,11 @@ impl Client {
         let new_nick = message.params[0].clone();
         debug!("Client {} requesting nick change to {}", self.id, new_nick);
 
+        // Reserved nicknames can't be used
+        if let Some(resv) = self.server.find_resv(&new_nick).await {
+            return self.send_numeric(432, &[&new_nick, &resv.reason]).await;
+        }
+
         // Check if nickname is available
         if let Err(e) = self.server.register_nickname(&new_nick, self.id).await {
             return Err(e);
@@ -29,9 +34,15 @@ impl Client {
         }
 
         // Store the nickname in the client struct
-        self.nickname = Some(new_nick);
+        self.set_nickname(new_nick.clone())?;
         debug!("Client {} nickname set to {:?}", self.id, self.nickname);
 
+        // :uid NICK newnick :nickTS
+        if self.registered {
+            let nick = TS6Message::with_source(self.uid.clone(), "NICK".to_string(), vec![new_nick, self.nick_ts.to_string()]);
+            self.server.send_to_servers(&nick, None).await;
+        }
+
         // Check if we can complete registration
         self.check_registration().await?;
 
@@ -91,13 +102,19 @@ impl Client {
                     match c {
                         '+' => adding = true,
                         '-' => adding = false,
-                        'i' | 'w' | 'o' | 'O' | 'r' => {
+                        'i' | 'w' | 'r' => {
                             if adding {
                                 self.modes.insert(c);
                             } else {
                                 self.modes.remove(&c);
                             }
                         }
+                        // Operator status is only granted through OPER
+                        'o' | 'O' if adding => continue,
+                        'o' => self.deoper().await,
+                        'O' => {
+                            self.modes.remove(&c);
+                        }
                         _ => continue,
                     }
                 }
@@ -118,16 +135,8 @@ impl Client {
             .map(|s| s.as_str())
             .unwrap_or("Client Quit");
 
-        let quit_msg = TS6Message::with_source(
-            self.get_prefix(),
-            "QUIT".to_string(),
-            vec![quit_message.to_string()],
-        );
-
-        // Broadcast quit to all channels user is in
-        self.server.broadcast_global(&quit_msg.to_string()).await?;
-
-        // The actual cleanup is handled by the connection handler
+        // Channels and servers are told when the connection handler cleans up
+        self.disconnect(&format!("Quit: {}", quit_message)).await;
         Ok(())
     }
-} 
\ No newline at end of file
+}
@@ -0,0 +1,307 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+
+use crate::config::{DLine, GLine, KLine, Resv};
+use crate::error::IrcResult;
+use crate::ts6::TS6Message;
+
+use super::*;
+
+// Splits a leading duration in minutes off ban parameters, as seconds; 0 is permanent
+fn take_duration(params: &[String]) -> (i64, &[String]) {
+    match params.first().and_then(|param| param.parse::<i64>().ok()) {
+        Some(minutes) if minutes >= 0 && params.len() > 1 => (minutes * 60, &params[1..]),
+        _ => (0, params),
+    }
+}
+
+// Splits "ON server" off the parameters after a ban mask
+fn take_target(params: &[String]) -> (Option<&str>, &[String]) {
+    match params {
+        [on, target, rest @ ..] if on.eq_ignore_ascii_case("ON") => (Some(target.as_str()), rest),
+        _ => (None, params),
+    }
+}
+
+// A ban mask as user and host; a bare host bans every user on it
+fn split_user_host(mask: &str) -> (String, String) {
+    match mask.split_once('@') {
+        Some((user, host)) => (user.to_string(), host.to_string()),
+        None => ("*".to_string(), mask.to_string()),
+    }
+}
+
+fn describe(kind: &str, duration: i64) -> String {
+    match duration {
+        0 => kind.to_string(),
+        _ => format!("temporary {} min. {}", duration / 60, kind),
+    }
+}
+
+impl Client {
+    pub(crate) async fn handle_kline(&mut self, message: TS6Message) -> IrcResult<()> {
+        // KLINE [minutes] user@host [ON server] :reason
+        let (duration, params) = take_duration(&message.params);
+        let Some(mask) = params.first() else {
+            // ERR_NEEDMOREPARAMS (461)
+            return self.send_numeric(461, &["KLINE", "Not enough parameters"]).await;
+        };
+        let (user, host) = split_user_host(mask);
+        let (target, rest) = take_target(&params[1..]);
+        let reason = rest.first().cloned().unwrap_or_else(|| "No reason".to_string());
+
+        let params = vec![duration.to_string(), user.clone(), host.clone(), reason.clone()];
+        if !self.forward_ban(target, "KLINE", params).await {
+            return Ok(());
+        }
+
+        let kline = KLine {
+            mask: format!("{}@{}", user, host),
+            reason: reason.clone(),
+            set_by: self.get_prefix(),
+            duration,
+            set_time: Utc::now(),
+        };
+        let notice = format!("{} added {} for [{}] [{}]", self.oper_name(), describe("K-Line", duration), kline.mask, reason);
+        // The ban is in force either way; the operator is told it won't survive a restart
+        if let Err(e) = self.server.add_kline(kline).await.map_err(|e| e.to_string()) {
+            self.send_notice(&format!("Failed to save K-line: {}", e)).await?;
+        }
+        self.announce_ban(notice, true);
+        Ok(())
+    }
+
+    pub(crate) async fn handle_unkline(&mut self, message: TS6Message) -> IrcResult<()> {
+        // UNKLINE user@host [ON server]
+        let Some(mask) = message.params.first() else {
+            // ERR_NEEDMOREPARAMS (461)
+            return self.send_numeric(461, &["UNKLINE", "Not enough parameters"]).await;
+        };
+        let (user, host) = split_user_host(mask);
+        let (target, _) = take_target(&message.params[1..]);
+        if !self.forward_ban(target, "UNKLINE", vec![user.clone(), host.clone()]).await {
+            return Ok(());
+        }
+
+        let mask = format!("{}@{}", user, host);
+        let removed = match self.server.remove_kline(mask.clone()).await.map_err(|e| e.to_string()) {
+            Ok(removed) => removed,
+            Err(e) => return self.send_notice(&format!("Failed to remove K-line: {}", e)).await,
+        };
+        if !removed {
+            return self.send_notice(&format!("No K-Line for {}", mask)).await;
+        }
+        self.announce_ban(format!("{} has removed the K-Line for: [{}]", self.oper_name(), mask), false);
+        Ok(())
+    }
+
+    pub(crate) async fn handle_dline(&mut self, message: TS6Message) -> IrcResult<()> {
+        // DLINE [minutes] ip[/prefix] [ON server] :reason
+        let (duration, params) = take_duration(&message.params);
+        let Some(mask) = params.first() else {
+            // ERR_NEEDMOREPARAMS (461)
+            return self.send_numeric(461, &["DLINE", "Not enough parameters"]).await;
+        };
+        let Ok(ip) = mask.parse() else {
+            return self.send_notice(&format!("Invalid D-Line [{}]", mask)).await;
+        };
+        let (target, rest) = take_target(&params[1..]);
+        let reason = rest.first().cloned().unwrap_or_else(|| "No reason".to_string());
+        if !self.forward_ban(target, "DLINE", vec![duration.to_string(), mask.clone(), reason.clone()]).await {
+            return Ok(());
+        }
+
+        let dline = DLine {
+            ip,
+            reason: reason.clone(),
+            set_by: self.get_prefix(),
+            duration,
+            set_time: Utc::now(),
+        };
+        let notice = format!("{} added {} for [{}] [{}]", self.oper_name(), describe("D-Line", duration), ip, reason);
+        if let Err(e) = self.server.add_dline(dline).await.map_err(|e| e.to_string()) {
+            self.send_notice(&format!("Failed to save D-line: {}", e)).await?;
+        }
+        self.announce_ban(notice, true);
+        Ok(())
+    }
+
+    pub(crate) async fn handle_undline(&mut self, message: TS6Message) -> IrcResult<()> {
+        // UNDLINE ip[/prefix] [ON server]
+        let Some(mask) = message.params.first() else {
+            // ERR_NEEDMOREPARAMS (461)
+            return self.send_numeric(461, &["UNDLINE", "Not enough parameters"]).await;
+        };
+        let Ok(ip) = mask.parse() else {
+            return self.send_notice(&format!("Invalid D-Line [{}]", mask)).await;
+        };
+        let (target, _) = take_target(&message.params[1..]);
+        if !self.forward_ban(target, "UNDLINE", vec![mask.clone()]).await {
+            return Ok(());
+        }
+
+        let removed = match self.server.remove_dline(ip).await.map_err(|e| e.to_string()) {
+            Ok(removed) => removed,
+            Err(e) => return self.send_notice(&format!("Failed to remove D-line: {}", e)).await,
+        };
+        if !removed {
+            return self.send_notice(&format!("No D-Line for {}", ip)).await;
+        }
+        self.announce_ban(format!("{} has removed the D-Line for: [{}]", self.oper_name(), ip), false);
+        Ok(())
+    }
+
+    pub(crate) async fn handle_gline(&mut self, message: TS6Message) -> IrcResult<()> {
+        // GLINE [minutes] user@host :reason
+        let (duration, params) = take_duration(&message.params);
+        let Some(mask) = params.first() else {
+            // ERR_NEEDMOREPARAMS (461)
+            return self.send_numeric(461, &["GLINE", "Not enough parameters"]).await;
+        };
+        let (user, host) = split_user_host(mask);
+        let reason = params.get(1).cloned().unwrap_or_else(|| "No reason".to_string());
+        // G-lines cover the whole network
+        self.forward_ban(Some("*"), "GLINE", vec![duration.to_string(), user.clone(), host.clone(), reason.clone()]).await;
+
+        let gline = GLine {
+            mask: format!("{}@{}", user, host),
+            reason: reason.clone(),
+            set_by: self.get_prefix(),
+            duration,
+            set_time: Utc::now(),
+        };
+        let notice = format!("{} added {} for [{}] [{}]", self.oper_name(), describe("G-Line", duration), gline.mask, reason);
+        if let Err(e) = self.server.add_gline(gline).await.map_err(|e| e.to_string()) {
+            self.send_notice(&format!("Failed to save G-line: {}", e)).await?;
+        }
+        self.announce_ban(notice, true);
+        Ok(())
+    }
+
+    pub(crate) async fn handle_ungline(&mut self, message: TS6Message) -> IrcResult<()> {
+        // UNGLINE user@host
+        let Some(mask) = message.params.first() else {
+            // ERR_NEEDMOREPARAMS (461)
+            return self.send_numeric(461, &["UNGLINE", "Not enough parameters"]).await;
+        };
+        let (user, host) = split_user_host(mask);
+        self.forward_ban(Some("*"), "UNGLINE", vec![user.clone(), host.clone()]).await;
+
+        let mask = format!("{}@{}", user, host);
+        let removed = match self.server.remove_gline(&mask).await.map_err(|e| e.to_string()) {
+            Ok(removed) => removed,
+            Err(e) => return self.send_notice(&format!("Failed to remove G-line: {}", e)).await,
+        };
+        if !removed {
+            return self.send_notice(&format!("No G-Line for {}", mask)).await;
+        }
+        self.announce_ban(format!("{} has removed the G-Line for: [{}]", self.oper_name(), mask), false);
+        Ok(())
+    }
+
+    pub(crate) async fn handle_resv(&mut self, message: TS6Message) -> IrcResult<()> {
+        // RESV [minutes] nick|channel [ON server] :reason
+        let (duration, params) = take_duration(&message.params);
+        let Some(mask) = params.first() else {
+            // ERR_NEEDMOREPARAMS (461)
+            return self.send_numeric(461, &["RESV", "Not enough parameters"]).await;
+        };
+        let (target, rest) = take_target(&params[1..]);
+        let reason = rest.first().cloned().unwrap_or_else(|| "No reason".to_string());
+        if !self.forward_ban(target, "RESV", vec![duration.to_string(), mask.clone(), "0".to_string(), reason.clone()]).await {
+            return Ok(());
+        }
+
+        let resv = Resv {
+            mask: mask.clone(),
+            reason: reason.clone(),
+            set_by: self.get_prefix(),
+            duration,
+            set_time: Utc::now(),
+        };
+        let notice = format!("{} added {} for [{}] [{}]", self.oper_name(), describe("RESV", duration), mask, reason);
+        if let Err(e) = self.server.add_resv(resv).await.map_err(|e| e.to_string()) {
+            self.send_notice(&format!("Failed to save RESV: {}", e)).await?;
+        }
+        self.announce_ban(notice, false);
+        Ok(())
+    }
+
+    pub(crate) async fn handle_unresv(&mut self, message: TS6Message) -> IrcResult<()> {
+        // UNRESV nick|channel [ON server]
+        let Some(mask) = message.params.first() else {
+            // ERR_NEEDMOREPARAMS (461)
+            return self.send_numeric(461, &["UNRESV", "Not enough parameters"]).await;
+        };
+        let (target, _) = take_target(&message.params[1..]);
+        if !self.forward_ban(target, "UNRESV", vec![mask.clone()]).await {
+            return Ok(());
+        }
+
+        let removed = match self.server.remove_resv(mask).await.map_err(|e| e.to_string()) {
+            Ok(removed) => removed,
+            Err(e) => return self.send_notice(&format!("Failed to remove RESV: {}", e)).await,
+        };
+        if !removed {
+            return self.send_notice(&format!("No RESV for {}", mask)).await;
+        }
+        self.announce_ban(format!("{} has removed the RESV for: [{}]", self.oper_name(), mask), false);
+        Ok(())
+    }
+
+    pub(crate) async fn handle_stats(&mut self, message: TS6Message) -> IrcResult<()> {
+        // STATS letter
+        let Some(letter) = message.params.first().and_then(|param| param.chars().next()) else {
+            // ERR_NEEDMOREPARAMS (461)
+            return self.send_numeric(461, &["STATS", "Not enough parameters"]).await;
+        };
+        // Ban lists and link state are for operators only
+        if "kdglKDGL".contains(letter) && !self.check_privileges(&[]).await? {
+            return Ok(());
+        }
+        let reply = self.server.stats_reply(letter).await;
+        self.send_numerics(reply).await
+    }
+
+    // Sends a ban for another server through ENCAP, returning whether it also applies here
+    async fn forward_ban(&self, target: Option<&str>, command: &str, params: Vec<String>) -> bool {
+        let Some(target) = target else {
+            return true;
+        };
+        let mut encap_params = vec![target.to_string(), command.to_string()];
+        encap_params.extend(params);
+        let encap = TS6Message::with_source(self.uid.clone(), "ENCAP".to_string(), encap_params);
+        self.server.send_to_matching_servers(target, &encap, None).await;
+        self.server.mask_match(&self.server.config.server.name.to_lowercase(), &target.to_lowercase())
+    }
+
+    // Tells the operators about a ban change and, for a new ban, disconnects the users it covers.
+    // Both lock every client, this one included, so they run once this line is done.
+    fn announce_ban(&self, notice: String, enforce: bool) {
+        let server = Arc::clone(&self.server);
+        tokio::spawn(async move {
+            server.send_server_notice(&server.config.server.name, &notice).await;
+            if enforce {
+                for mask in server.disconnect_banned().await {
+                    let notice = format!("Disconnected banned user {}", mask);
+                    server.send_server_notice(&server.config.server.name, &notice).await;
+                }
+            }
+        });
+    }
+
+    // How operators are named in ban notices
+    fn oper_name(&self) -> String {
+        self.get_nickname().cloned().unwrap_or_default()
+    }
+
+    pub(crate) async fn send_notice(&self, text: &str) -> IrcResult<()> {
+        let notice = TS6Message::with_source(
+            self.server_name.clone(),
+            "NOTICE".to_string(),
+            vec![self.get_nickname().cloned().unwrap_or_else(|| "*".to_string()), text.to_string()],
+        );
+        self.send_message(&notice).await
+    }
+}
@@ -1,10 +1,11 @@
 use std::fs;
-use std::net::IpAddr;
 use std::path::Path;
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
+use crate::cidr::IpNet;
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct ServerConfig {
     pub server: Server,
@@ -33,6 +34,8 @@ pub struct Network {
     pub name: String,
     #[serde(default)]
     pub links: Vec<Link>,
+    #[serde(default)]
+    pub flatten_links: bool, // Show non-opers every server as linked to us in LINKS
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -65,6 +68,8 @@ pub struct AccessConfig {
     #[serde(default)]
     pub dlines: Vec<DLine>,
     #[serde(default)]
+    pub elines: Vec<ELine>,
+    #[serde(default)]
     pub glines: Vec<GLine>,
     #[serde(default)]
     pub ilines: Vec<ILine>,
@@ -74,6 +79,10 @@ pub struct AccessConfig {
     pub ulines: Vec<ULine>,
     #[serde(default)]
     pub alines: Vec<ALine>,
+    #[serde(default)]
+    pub resvs: Vec<Resv>,
+    #[serde(default)]
+    pub privsets: Vec<PrivSet>,
 }
 
 impl Default for AccessConfig {
@@ -81,11 +90,14 @@ impl Default for AccessConfig {
         Self {
             klines: Vec::new(),
             dlines: Vec::new(),
+            elines: Vec::new(),
             glines: Vec::new(),
             ilines: Vec::new(),
             olines: Vec::new(),
             ulines: Vec::new(),
             alines: Vec::new(),
+            resvs: Vec::new(),
+            privsets: Vec::new(),
         }
     }
 }
@@ -103,7 +115,7 @@ pub struct KLine {
 
 #[derive(Debug, Deserialize, Clone, Serialize)]
 pub struct DLine {
-    pub ip: IpAddr,            // IP or network to ban
+    pub ip: IpNet,             // IP or CIDR network to ban
     pub reason: String,
     pub set_by: String,
     #[serde(default = "default_duration")]
@@ -123,6 +135,12 @@ pub struct GLine {
     pub set_time: DateTime<Utc>,
 }
 
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct ELine {
+    pub ip: IpNet,             // IP or CIDR network exempt from D-lines, K-lines and G-lines
+    pub reason: String,
+}
+
 #[derive(Debug, Deserialize, Clone, Serialize)]
 pub struct ILine {
     pub mask: String,          // Allow connection mask
@@ -133,9 +151,24 @@ pub struct ILine {
 
 #[derive(Debug, Deserialize, Clone, Serialize)]
 pub struct OLine {
+    #[serde(default)]
+    pub name: String,          // Name given to OPER, the mask for O-lines written without one
     pub mask: String,          // Operator access mask
     pub password: String,      // Encrypted password
+    #[serde(default = "default_encrypted")]
+    pub encrypted: bool,       // False for plaintext passwords
     pub flags: Vec<String>,    // Operator privileges
+    #[serde(default)]
+    pub privset: Option<String>, // Privilege set granted on top of the flags
+}
+
+/// A named set of operator privileges that O-lines can refer to.
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct PrivSet {
+    pub name: String,
+    #[serde(default)]
+    pub extends: Option<String>, // Set whose privileges are included too
+    pub privileges: Vec<String>,
 }
 
 #[derive(Debug, Deserialize, Clone, Serialize)]
@@ -151,6 +184,17 @@ pub struct ALine {
     pub class: String,         // Auth class
 }
 
+#[derive(Debug, Deserialize, Clone, Serialize)]
+pub struct Resv {
+    pub mask: String,          // Reserved nickname or channel mask
+    pub reason: String,
+    pub set_by: String,
+    #[serde(default = "default_duration")]
+    pub duration: i64,
+    #[serde(default = "Utc::now")]
+    pub set_time: DateTime<Utc>,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct DatabaseConfig {
     pub path: String,     // Path to the database file
@@ -178,6 +222,10 @@ fn default_duration() -> i64 {
     0 // Permanent by default
 }
 
+fn default_encrypted() -> bool {
+    true // O-line passwords are argon2 hashes unless marked otherwise
+}
+
 fn default_ping_interval() -> u64 {
     16 // Default ping interval in seconds
 }
@@ -186,6 +234,10 @@ fn default_ping_timeout() -> u64 {
     128 // Default ping timeout in seconds
 }
 
+fn default_connect_frequency() -> u64 {
+    300 // Default connect frequency in seconds
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct ServerLinkConfig {
     pub name: String,
@@ -195,12 +247,48 @@ pub struct ServerLinkConfig {
     pub address: String,  // IP:Port for connecting
     pub autoconnect: bool,
     pub ssl: bool,
+    #[serde(default = "default_connect_frequency")]
+    pub connect_frequency: u64, // Longest delay between autoconnect attempts, in seconds
+}
+
+/// A ban line that lapses `duration` seconds after it was set; a duration of 0 never expires.
+pub trait Expiring {
+    fn set_time(&self) -> DateTime<Utc>;
+    fn duration(&self) -> i64;
+
+    fn expires_at(&self) -> Option<DateTime<Utc>> {
+        (self.duration() > 0).then(|| self.set_time() + chrono::Duration::seconds(self.duration()))
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires_at().is_some_and(|at| at <= Utc::now())
+    }
 }
 
+macro_rules! impl_expiring {
+    ($($line:ty),*) => {
+        $(impl Expiring for $line {
+            fn set_time(&self) -> DateTime<Utc> {
+                self.set_time
+            }
+
+            fn duration(&self) -> i64 {
+                self.duration
+            }
+        })*
+    };
+}
+
+impl_expiring!(KLine, DLine, GLine, Resv);
+
 impl ServerConfig {
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn std::error::Error>> {
         let contents = fs::read_to_string(path)?;
-        let config: ServerConfig = toml::from_str(&contents)?;
+        let mut config: ServerConfig = toml::from_str(&contents)?;
+        // O-lines from before OPER took a name keep their mask as one
+        for oline in config.access.olines.iter_mut().filter(|oline| oline.name.is_empty()) {
+            oline.name = oline.mask.clone();
+        }
         Ok(config)
     }
 } 
\ No newline at end of file
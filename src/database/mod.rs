@@ -1,53 +1,131 @@
 use std::fs;
+use std::io;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
-use tokio::sync::RwLock;
+use serde_json::Value;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::{Mutex, RwLock};
 
-use crate::config::{ALine, DLine, GLine, ILine, KLine, OLine, ULine};
+use crate::cidr::IpNet;
+use crate::config::{ALine, DLine, ELine, GLine, ILine, KLine, OLine, Resv, ULine};
+
+// Version written by this build; files without one are version 0
+const SCHEMA_VERSION: u32 = 1;
+
+// Upgrades from each version to the next, indexed by the version they start from
+const MIGRATIONS: [fn(&mut Value); SCHEMA_VERSION as usize] = [migrate_v0];
+
+// Version 0 O-lines had no name; they keep their mask as one so OPER can still find them
+fn migrate_v0(content: &mut Value) {
+    let Some(olines) = content.get_mut("olines").and_then(Value::as_array_mut) else {
+        return;
+    };
+    for oline in olines.iter_mut().filter_map(Value::as_object_mut) {
+        if !oline.contains_key("name") {
+            let mask = oline.get("mask").cloned().unwrap_or_default();
+            oline.insert("name".to_string(), mask);
+        }
+    }
+}
 
 #[derive(Default, Serialize, Deserialize)]
 pub struct DatabaseContent {
+    #[serde(default)]
+    version: u32,
+    #[serde(default)]
     klines: Vec<KLine>,
+    #[serde(default)]
     dlines: Vec<DLine>,
+    #[serde(default)]
     glines: Vec<GLine>,
+    #[serde(default)]
     ilines: Vec<ILine>,
+    #[serde(default)]
     olines: Vec<OLine>,
+    #[serde(default)]
     ulines: Vec<ULine>,
+    #[serde(default)]
     alines: Vec<ALine>,
+    #[serde(default)]
+    resvs: Vec<Resv>,
+    #[serde(default)]
+    elines: Vec<ELine>,
+    #[serde(default)]
+    max_local_users: usize,
+    #[serde(default)]
+    max_global_users: usize,
 }
 
 pub struct Database {
     path: PathBuf,
     content: Arc<RwLock<DatabaseContent>>,
+    save_lock: Mutex<()>, // One save at a time, so an older snapshot never replaces a newer one
 }
 
 impl Database {
-    pub async fn new<P: AsRef<Path>>(path: P) -> Result<Self, std::io::Error> {
+    pub async fn new<P: AsRef<Path>>(path: P) -> Result<Self, io::Error> {
         let path = path.as_ref().to_path_buf();
-        let content = if path.exists() {
-            let data = fs::read_to_string(&path)?;
-            serde_json::from_str(&data).unwrap_or_default()
+        let (content, migrated) = if path.exists() {
+            Self::load(&path)?
         } else {
-            DatabaseContent::default()
+            (DatabaseContent { version: SCHEMA_VERSION, ..Default::default() }, false)
         };
 
-        Ok(Self {
+        let db = Self {
             path,
             content: Arc::new(RwLock::new(content)),
-        })
+            save_lock: Mutex::new(()),
+        };
+        if migrated {
+            db.save().await?;
+        }
+        Ok(db)
     }
 
-    async fn save(&self) -> Result<(), std::io::Error> {
-        let content = self.content.read().await;
-        let data = serde_json::to_string_pretty(&*content)?;
-        fs::write(&self.path, data)?;
-        Ok(())
+    // Reads the file, upgrading it to the current schema; true if it needed upgrading.
+    // A file that can't be read is an error rather than an empty database, so it is never overwritten.
+    fn load(path: &Path) -> Result<(DatabaseContent, bool), io::Error> {
+        let data = fs::read_to_string(path)?;
+        let mut value: Value = serde_json::from_str(&data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+
+        let version = value.get("version").and_then(Value::as_u64).unwrap_or(0);
+        if version > SCHEMA_VERSION as u64 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} has schema version {}, newer than {}", path.display(), version, SCHEMA_VERSION),
+            ));
+        }
+        for migration in &MIGRATIONS[version as usize..] {
+            migration(&mut value);
+        }
+
+        let mut content: DatabaseContent = serde_json::from_value(value)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), e)))?;
+        content.version = SCHEMA_VERSION;
+        Ok((content, version < SCHEMA_VERSION as u64))
+    }
+
+    // Writes a temporary file next to the database and renames it over, so a crash
+    // part way through leaves the previous contents rather than a truncated file
+    async fn save(&self) -> Result<(), io::Error> {
+        let _saving = self.save_lock.lock().await;
+        let data = serde_json::to_string_pretty(&*self.content.read().await)?;
+
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        let temp = PathBuf::from(temp);
+        let mut file = tokio::fs::File::create(&temp).await?;
+        file.write_all(data.as_bytes()).await?;
+        file.sync_all().await?;
+        tokio::fs::rename(&temp, &self.path).await
     }
 
-    pub async fn add_kline(&self, kline: KLine) -> Result<(), std::io::Error> {
+    pub async fn add_kline(&self, kline: KLine) -> Result<(), io::Error> {
         let mut content = self.content.write().await;
+        content.klines.retain(|k| !k.mask.eq_ignore_ascii_case(&kline.mask));
         content.klines.push(kline);
         drop(content);
         self.save().await
@@ -57,14 +135,177 @@ impl Database {
         self.content.read().await.klines.clone()
     }
 
-    pub async fn remove_kline(&self, mask: &str) -> Result<(), std::io::Error> {
+    pub async fn remove_kline(&self, mask: &str) -> Result<(), io::Error> {
         let mut content = self.content.write().await;
-        content.klines.retain(|k| k.mask != mask);
+        content.klines.retain(|k| !k.mask.eq_ignore_ascii_case(mask));
         drop(content);
         self.save().await
     }
 
-    // Similar methods for other line types...
-}
+    pub async fn add_dline(&self, dline: DLine) -> Result<(), io::Error> {
+        let mut content = self.content.write().await;
+        content.dlines.retain(|d| d.ip != dline.ip);
+        content.dlines.push(dline);
+        drop(content);
+        self.save().await
+    }
+
+    pub async fn get_dlines(&self) -> Vec<DLine> {
+        self.content.read().await.dlines.clone()
+    }
+
+    pub async fn remove_dline(&self, ip: IpNet) -> Result<(), io::Error> {
+        let mut content = self.content.write().await;
+        content.dlines.retain(|d| d.ip != ip);
+        drop(content);
+        self.save().await
+    }
+
+    pub async fn add_gline(&self, gline: GLine) -> Result<(), io::Error> {
+        let mut content = self.content.write().await;
+        content.glines.retain(|g| !g.mask.eq_ignore_ascii_case(&gline.mask));
+        content.glines.push(gline);
+        drop(content);
+        self.save().await
+    }
+
+    pub async fn get_glines(&self) -> Vec<GLine> {
+        self.content.read().await.glines.clone()
+    }
+
+    pub async fn remove_gline(&self, mask: &str) -> Result<(), io::Error> {
+        let mut content = self.content.write().await;
+        content.glines.retain(|g| !g.mask.eq_ignore_ascii_case(mask));
+        drop(content);
+        self.save().await
+    }
+
+    pub async fn add_iline(&self, iline: ILine) -> Result<(), io::Error> {
+        let mut content = self.content.write().await;
+        content.ilines.retain(|i| i.mask != iline.mask);
+        content.ilines.push(iline);
+        drop(content);
+        self.save().await
+    }
+
+    pub async fn get_ilines(&self) -> Vec<ILine> {
+        self.content.read().await.ilines.clone()
+    }
+
+    pub async fn remove_iline(&self, mask: &str) -> Result<(), io::Error> {
+        let mut content = self.content.write().await;
+        content.ilines.retain(|i| i.mask != mask);
+        drop(content);
+        self.save().await
+    }
+
+    // O-lines are keyed by name and mask, as one name may be usable from several hosts
+    pub async fn add_oline(&self, oline: OLine) -> Result<(), io::Error> {
+        let mut content = self.content.write().await;
+        content.olines.retain(|o| !(o.name == oline.name && o.mask == oline.mask));
+        content.olines.push(oline);
+        drop(content);
+        self.save().await
+    }
+
+    pub async fn get_olines(&self) -> Vec<OLine> {
+        self.content.read().await.olines.clone()
+    }
+
+    pub async fn remove_oline(&self, name: &str, mask: &str) -> Result<(), io::Error> {
+        let mut content = self.content.write().await;
+        content.olines.retain(|o| !(o.name == name && o.mask == mask));
+        drop(content);
+        self.save().await
+    }
+
+    pub async fn add_uline(&self, uline: ULine) -> Result<(), io::Error> {
+        let mut content = self.content.write().await;
+        content.ulines.retain(|u| u.server != uline.server);
+        content.ulines.push(uline);
+        drop(content);
+        self.save().await
+    }
+
+    pub async fn get_ulines(&self) -> Vec<ULine> {
+        self.content.read().await.ulines.clone()
+    }
+
+    pub async fn remove_uline(&self, server: &str) -> Result<(), io::Error> {
+        let mut content = self.content.write().await;
+        content.ulines.retain(|u| u.server != server);
+        drop(content);
+        self.save().await
+    }
+
+    pub async fn add_aline(&self, aline: ALine) -> Result<(), io::Error> {
+        let mut content = self.content.write().await;
+        content.alines.retain(|a| a.mask != aline.mask);
+        content.alines.push(aline);
+        drop(content);
+        self.save().await
+    }
+
+    pub async fn get_alines(&self) -> Vec<ALine> {
+        self.content.read().await.alines.clone()
+    }
+
+    pub async fn remove_aline(&self, mask: &str) -> Result<(), io::Error> {
+        let mut content = self.content.write().await;
+        content.alines.retain(|a| a.mask != mask);
+        drop(content);
+        self.save().await
+    }
+
+    pub async fn add_resv(&self, resv: Resv) -> Result<(), io::Error> {
+        let mut content = self.content.write().await;
+        content.resvs.retain(|r| !r.mask.eq_ignore_ascii_case(&resv.mask));
+        content.resvs.push(resv);
+        drop(content);
+        self.save().await
+    }
+
+    pub async fn get_resvs(&self) -> Vec<Resv> {
+        self.content.read().await.resvs.clone()
+    }
 
-// ... rest of the implementation ... 
\ No newline at end of file
+    pub async fn remove_resv(&self, mask: &str) -> Result<(), io::Error> {
+        let mut content = self.content.write().await;
+        content.resvs.retain(|r| !r.mask.eq_ignore_ascii_case(mask));
+        drop(content);
+        self.save().await
+    }
+
+    pub async fn add_eline(&self, eline: ELine) -> Result<(), io::Error> {
+        let mut content = self.content.write().await;
+        content.elines.retain(|e| e.ip != eline.ip);
+        content.elines.push(eline);
+        drop(content);
+        self.save().await
+    }
+
+    pub async fn get_elines(&self) -> Vec<ELine> {
+        self.content.read().await.elines.clone()
+    }
+
+    pub async fn remove_eline(&self, ip: IpNet) -> Result<(), io::Error> {
+        let mut content = self.content.write().await;
+        content.elines.retain(|e| e.ip != ip);
+        drop(content);
+        self.save().await
+    }
+
+    // Highest local and global user counts, kept across restarts
+    pub async fn get_max_users(&self) -> (usize, usize) {
+        let content = self.content.read().await;
+        (content.max_local_users, content.max_global_users)
+    }
+
+    pub async fn set_max_users(&self, local: usize, global: usize) -> Result<(), io::Error> {
+        let mut content = self.content.write().await;
+        content.max_local_users = local;
+        content.max_global_users = global;
+        drop(content);
+        self.save().await
+    }
+}
@@ -7,6 +7,7 @@ pub enum Capability {
     ExtendedJoin,
     ServerTime,
     MessageTags,
+    Batch,
 }
 
 impl fmt::Display for Capability {
@@ -16,6 +17,7 @@ impl fmt::Display for Capability {
             Capability::ExtendedJoin => write!(f, "extended-join"),
             Capability::ServerTime => write!(f, "server-time"),
             Capability::MessageTags => write!(f, "message-tags"),
+            Capability::Batch => write!(f, "batch"),
         }
     }
 }
@@ -29,6 +31,7 @@ impl FromStr for Capability {
             "extended-join" => Ok(Capability::ExtendedJoin),
             "server-time" => Ok(Capability::ServerTime),
             "message-tags" => Ok(Capability::MessageTags),
+            "batch" => Ok(Capability::Batch),
             _ => Err(()),
         }
     }
@@ -41,6 +44,7 @@ impl Capability {
             Capability::ExtendedJoin => "extended-join",
             Capability::ServerTime => "server-time",
             Capability::MessageTags => "message-tags",
+            Capability::Batch => "batch",
         }
     }
 } 
@@ -1,13 +1,31 @@
-use std::io;
+use std::collections::HashSet;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{tcp::{OwnedReadHalf, OwnedWriteHalf}, TcpStream};
-use tracing::{debug, info, warn};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::{Mutex, Notify};
+use tracing::{debug, error, info, warn};
 
 use crate::error::{IrcError, IrcResult};
+use crate::server::Server;
 use crate::ts6::parser::parse_message;
-use crate::ts6::TS6Message;
+use crate::ts6::{euid_to_uid, TS6Message};
+
+// Longest message we build for a burst, leaving room for CR LF
+const MAX_BURST_LINE: usize = 510;
+
+/// What a peer sent us during its burst, to tell a slow burst from a hung link.
+#[derive(Clone, Debug, Default)]
+pub struct BurstStats {
+    pub users: usize,
+    pub channels: usize,
+    pub bytes: usize,
+    pub started: Option<Instant>,
+    pub duration: Option<Duration>, // Set once the peer has finished bursting
+}
 
 pub struct ServerLink {
     name: String,
@@ -15,42 +33,135 @@ pub struct ServerLink {
     description: String,
     password: String,
     incoming: bool,
+    registered: bool,
+    addr: SocketAddr,
     capabilities: Vec<String>,
-    reader: BufReader<OwnedReadHalf>,
-    writer: OwnedWriteHalf,
+    remote_password: Option<String>,
+    remote_sid: Option<String>,
+    remote_capabilities: Option<HashSet<String>>,
+    burst: BurstStats,
+    server: Arc<Server>,
+    reader: Option<BufReader<OwnedReadHalf>>,
+    tx: UnboundedSender<Vec<u8>>,
+    squit: Arc<Notify>,          // Signals the read loop to end the link, e.g. on an operator's SQUIT
+    squit_reason: Option<String>,
 }
 
 impl ServerLink {
-    pub fn new(stream: TcpStream, name: String, sid: String, description: String, password: String) -> Self {
+    pub fn new(stream: TcpStream, name: String, sid: String, description: String, password: String, server: Arc<Server>) -> IrcResult<Self> {
+        let addr = stream.peer_addr()?;
         let (read, write) = stream.into_split();
+        Ok(Self::from_parts(BufReader::new(read), write, addr, name, sid, description, password, server))
+    }
+
+    /// Creates a link for a server that connected to us; its identity is learned during the handshake.
+    pub fn incoming(reader: BufReader<OwnedReadHalf>, writer: OwnedWriteHalf, addr: SocketAddr, server: Arc<Server>) -> Self {
+        let mut link = Self::from_parts(reader, writer, addr, String::new(), String::new(), String::new(), String::new(), server);
+        link.incoming = true;
+        link
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn from_parts(
+        reader: BufReader<OwnedReadHalf>,
+        write: OwnedWriteHalf,
+        addr: SocketAddr,
+        name: String,
+        sid: String,
+        description: String,
+        password: String,
+        server: Arc<Server>,
+    ) -> Self {
+        let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+
+        // Spawn writer task so the link can be written to without holding its lock
+        tokio::spawn(async move {
+            let mut writer = write;
+            while let Some(data) = rx.recv().await {
+                if let Err(e) = writer.write_all(&data).await {
+                    error!("Failed to write to server link: {}", e);
+                    break;
+                }
+                if let Err(e) = writer.flush().await {
+                    error!("Failed to flush server link: {}", e);
+                    break;
+                }
+            }
+            debug!("Server link writer task exiting");
+        });
+
         Self {
             name,
             sid,
             description,
             password,
             incoming: false,
+            registered: false,
+            addr,
             capabilities: vec![
                 "QS".to_string(),     // Quit Storm
                 "ENCAP".to_string(),  // Encapsulation
                 "TB".to_string(),     // Topic Burst
                 "SAVE".to_string(),   // SAVE nickname
+                "EUID".to_string(),   // Extended user introduction
+                "EX".to_string(),     // Ban exceptions (+e)
+                "IE".to_string(),     // Invite exceptions (+I)
                 "SERVICES".to_string(), // Services support
             ],
-            reader: BufReader::new(read),
-            writer: write,
+            remote_password: None,
+            remote_sid: None,
+            remote_capabilities: None,
+            burst: BurstStats::default(),
+            server,
+            reader: Some(reader),
+            tx,
+            squit: Arc::new(Notify::new()),
+            squit_reason: None,
         }
     }
 
-    pub async fn handle_connection(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    pub async fn handle_connection(link: Arc<Mutex<ServerLink>>) -> IrcResult<()> {
+        let (reader, server) = {
+            let mut link = link.lock().await;
+            let reader = link.reader.take()
+                .ok_or_else(|| IrcError::ServerLink("Server link is already running".into()))?;
+            (reader, Arc::clone(&link.server))
+        };
+
+        let result = Self::read_loop(&link, reader, &server).await;
+
+        // Whatever ended the link, it can no longer be routed through
+        let reason = match &result {
+            Ok(()) => "Connection closed".to_string(),
+            Err(IrcError::ServerLink(reason)) => reason.clone(),
+            Err(e) => e.to_string(),
+        };
+        server.unregister_server_link(&link, &reason).await;
+        result
+    }
+
+    async fn read_loop(link: &Arc<Mutex<ServerLink>>, reader: BufReader<OwnedReadHalf>, server: &Server) -> IrcResult<()> {
+        let mut lines = reader.lines();
+        let squit = Arc::clone(&link.lock().await.squit);
         loop {
-            let mut line = String::new();
-            if self.reader.read_line(&mut line).await? == 0 {
-                break; // EOF
-            }
+            let line = tokio::select! {
+                line = lines.next_line() => match line? {
+                    Some(line) => line,
+                    None => return Ok(()),
+                },
+                _ = squit.notified() => {
+                    let reason = link.lock().await.squit_reason.clone().unwrap_or_default();
+                    return Err(IrcError::ServerLink(reason));
+                }
+            };
+            debug!("Received line from server link: {}", line);
 
             match parse_message(&line) {
                 Ok(msg) => {
-                    self.handle_message(msg).await?;
+                    link.lock().await.record_burst_line(&msg, line.len());
+
+                    // Don't hold the link lock while the server processes the message
+                    server.handle_server_message(link, msg).await?;
                 }
                 Err(e) => {
                     warn!("Failed to parse message: {}", e);
@@ -58,23 +169,28 @@ impl ServerLink {
                 }
             }
         }
-        Ok(())
     }
 
-    async fn send_pass(&mut self) -> IrcResult<()> {
+    pub(crate) async fn send_credentials(&self) -> IrcResult<()> {
+        self.send_pass().await?;
+        self.send_capab().await?;
+        self.send_server().await
+    }
+
+    async fn send_pass(&self) -> IrcResult<()> {
         let pass_msg = TS6Message::new(
             "PASS".to_string(),
             vec![
                 self.password.clone(),
                 "TS".to_string(),
                 "6".to_string(),
-                self.sid.clone(),
+                self.server.config.server.sid.clone(),
             ],
         );
         self.send_message(&pass_msg).await
     }
 
-    async fn send_capab(&mut self) -> IrcResult<()> {
+    async fn send_capab(&self) -> IrcResult<()> {
         let capab_msg = TS6Message::new(
             "CAPAB".to_string(),
             vec![self.capabilities.join(" ")],
@@ -82,73 +198,370 @@ impl ServerLink {
         self.send_message(&capab_msg).await
     }
 
-    async fn send_server(&mut self) -> IrcResult<()> {
+    async fn send_server(&self) -> IrcResult<()> {
         let server_msg = TS6Message::new(
             "SERVER".to_string(),
             vec![
-                self.name.clone(),
+                self.server.config.server.name.clone(),
                 "1".to_string(), // Hopcount
-                self.description.clone(),
+                self.server.config.server.description.clone(),
             ],
         );
         self.send_message(&server_msg).await
     }
 
-    async fn send_burst(&mut self) -> IrcResult<()> {
-        // Send all local users
-        self.send_users_burst().await?;
+    pub(crate) async fn send_svinfo(&self) -> IrcResult<()> {
+        let svinfo_msg = TS6Message::new(
+            "SVINFO".to_string(),
+            vec![
+                "6".to_string(), // Current TS version
+                "6".to_string(), // Minimum TS version
+                "0".to_string(),
+                crate::ts6::generate_ts().to_string(),
+            ],
+        );
+        self.send_message(&svinfo_msg).await
+    }
+
+    pub(crate) async fn send_burst(link: &Arc<Mutex<ServerLink>>) -> IrcResult<()> {
+        let (server, capabilities, peer, peer_sid) = {
+            let link = link.lock().await;
+            (Arc::clone(&link.server), link.remote_capabilities.clone().unwrap_or_default(), link.name.clone(), link.sid.clone())
+        };
+
+        // Nothing behind the peer is sent back to it
+        let subtree = server.get_server_subtree(&peer).await;
+        let skip_names: HashSet<String> = subtree.iter().map(|remote| remote.name.clone()).collect();
+        let skip_sids: HashSet<String> = subtree.into_iter().filter_map(|remote| remote.sid).collect();
+
+        // Collect the burst without holding the link lock, as it locks every client
+        let mut burst = Vec::new();
+
+        // Send all servers
+        burst.extend(Self::servers_burst(&server, &skip_names).await);
+
+        // Send all users
+        burst.extend(Self::users_burst(&server, capabilities.contains("EUID"), &skip_sids).await);
 
         // Send all channels
-        self.send_channels_burst().await?;
+        burst.extend(Self::channels_burst(&server, &capabilities, &skip_sids).await);
 
-        // End of burst
-        let eob_msg = TS6Message::new("EOB".to_string(), vec![]);
-        self.send_message(&eob_msg).await
+        // The peer answers this PING once it has processed everything before it, ending our burst
+        burst.push(TS6Message::with_source(
+            server.config.server.sid.clone(),
+            "PING".to_string(),
+            vec![server.config.server.name.clone(), peer_sid],
+        ));
+
+        let link = link.lock().await;
+        for message in &burst {
+            link.send_message(message).await?;
+        }
+        debug!("Sent burst of {} messages to {}", burst.len(), link.name);
+        Ok(())
     }
 
-    async fn send_message(&mut self, message: &TS6Message) -> IrcResult<()> {
+    pub async fn send_message(&self, message: &TS6Message) -> IrcResult<()> {
         let msg = format!("{}\r\n", message.to_string());
-        self.writer.write_all(msg.as_bytes()).await?;
-        self.writer.flush().await?;
+        debug!("Sending message to server link {}: {:?}", self.name, msg);
+        self.tx.send(msg.into_bytes()).map_err(|_| {
+            let err = std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Channel closed");
+            IrcError::Io(err)
+        })?;
         Ok(())
     }
 
-    async fn send_users_burst(&mut self) -> IrcResult<()> {
-        // TODO: Implement user burst
-        // For each local user:
-        // UID <nickname> <hopcount> <timestamp> <username> <hostname> <uid> <modes> <realname>
-        Ok(())
+    /// Sends `ERROR :Closing Link` to the peer and returns the error that ends the link.
+    pub(crate) async fn close(&self, reason: &str) -> IrcError {
+        let target = if self.name.is_empty() {
+            self.addr.ip().to_string()
+        } else {
+            self.name.clone()
+        };
+        info!("Closing server link {}: {}", target, reason);
+
+        let error_msg = TS6Message::new(
+            "ERROR".to_string(),
+            vec![format!("Closing Link: {} ({})", target, reason)],
+        );
+        self.send_message(&error_msg).await.ok();
+
+        IrcError::ServerLink(reason.to_string())
     }
 
-    async fn send_channels_burst(&mut self) -> IrcResult<()> {
-        // TODO: Implement channel burst
-        // For each channel:
-        // SJOIN <timestamp> <channel> <modes> :[<prefix>]<uid> ...
-        Ok(())
+    /// Closes the link from outside its read loop, as for an operator's SQUIT.
+    pub(crate) async fn squit(&mut self, reason: &str) {
+        self.close(reason).await;
+        self.squit_reason = Some(reason.to_string());
+        self.squit.notify_one();
     }
 
-    async fn handle_message(&mut self, message: TS6Message) -> IrcResult<()> {
-        match message.command.as_str() {
-            "PING" => {
-                let pong = TS6Message::new(
-                    "PONG".to_string(),
-                    message.params,
-                );
-                self.send_message(&pong).await?;
+    // Introduces every known server with SID, or SERVER for TS5 servers, uplinks first
+    async fn servers_burst(server: &Server, skip: &HashSet<String>) -> Vec<TS6Message> {
+        let servers = server.get_remote_servers().await;
+        let mut burst = Vec::new();
+
+        for remote in &servers {
+            if skip.contains(&remote.name) {
+                continue;
             }
-            "SQUIT" => {
-                // Handle server quit
-                warn!("Server {} quit: {}",
-                    message.params.get(0).unwrap_or(&"unknown".to_string()),
-                    message.params.get(1).unwrap_or(&"No reason given".to_string())
-                );
-                return Err(IrcError::Protocol("Server quit".into()));
+            let uplink_sid = if remote.uplink.eq_ignore_ascii_case(&server.config.server.name) {
+                Some(server.config.server.sid.clone())
+            } else {
+                servers.iter()
+                    .find(|uplink| uplink.name.eq_ignore_ascii_case(&remote.uplink))
+                    .and_then(|uplink| uplink.sid.clone())
+            };
+            let Some(uplink_sid) = uplink_sid else {
+                continue;
+            };
+
+            let hopcount = (remote.hopcount + 1).to_string();
+            burst.push(match &remote.sid {
+                Some(sid) => TS6Message::with_source(
+                    uplink_sid,
+                    "SID".to_string(),
+                    vec![remote.name.clone(), hopcount, sid.clone(), remote.description.clone()],
+                ),
+                None => TS6Message::with_source(
+                    uplink_sid,
+                    "SERVER".to_string(),
+                    vec![remote.name.clone(), hopcount, remote.description.clone()],
+                ),
+            });
+        }
+
+        burst
+    }
+
+    // Introduces every registered user with EUID, or UID plus ENCAP REALHOST/LOGIN
+    async fn users_burst(server: &Server, euid: bool, skip_sids: &HashSet<String>) -> Vec<TS6Message> {
+        let our_sid = &server.config.server.sid;
+        let mut introductions = Vec::new();
+
+        for client in server.get_clients().await {
+            let client = client.lock().await;
+            if !client.is_registered() {
+                continue;
             }
-            // Add other message handlers
-            _ => {
-                debug!("Unhandled server message: {:?}", message);
+
+            introductions.push(client.euid(our_sid));
+        }
+
+        for user in server.get_remote_users().await {
+            if skip_sids.contains(&user.server) {
+                continue;
             }
+            let hopcount = server.find_server_by_sid(&user.server).await
+                .map_or(1, |remote| remote.hopcount + 1);
+            let mut modes: Vec<char> = user.modes.iter().copied().collect();
+            modes.sort_unstable();
+
+            introductions.push(TS6Message::with_source(
+                user.server.clone(),
+                "EUID".to_string(),
+                vec![
+                    user.nickname,
+                    hopcount.to_string(),
+                    user.nick_ts.to_string(),
+                    format!("+{}", modes.iter().collect::<String>()),
+                    user.username,
+                    user.hostname,
+                    user.ip,
+                    user.uid,
+                    user.realhost,
+                    user.account.unwrap_or_else(|| "*".to_string()),
+                    user.realname,
+                ],
+            ));
         }
-        Ok(())
+
+        if euid {
+            return introductions;
+        }
+        introductions.iter().flat_map(euid_to_uid).collect()
+    }
+
+    // Sends every channel as SJOIN with member prefixes, followed by BMASK for its ban-like lists and TB for its topic
+    async fn channels_burst(server: &Server, capabilities: &HashSet<String>, skip_sids: &HashSet<String>) -> Vec<TS6Message> {
+        let our_sid = &server.config.server.sid;
+        let mut burst = Vec::new();
+
+        for channel in server.get_channels().await {
+            // Snapshot the channel so no client is locked while the channel lock is held
+            let (name, ts, modes, members, lists, topic) = {
+                let channel = channel.read().await;
+                let members: Vec<(u32, String)> = channel.get_members().iter()
+                    .map(|&id| (id, channel.member_prefix(id)))
+                    .collect();
+                let lists: Vec<(char, Vec<String>)> = ['b', 'e', 'I'].iter()
+                    .map(|&mode| (mode, channel.get_list(mode).iter().map(|ban| ban.mask.clone()).collect()))
+                    .collect();
+                let (topic, setter, _) = channel.get_topic_details();
+                let topic = topic.map(|topic| (topic, setter, channel.topic_ts()));
+                (channel.name.clone(), channel.created_at().to_string(), channel.get_simple_modes(), members, lists, topic)
+            };
+
+            let mut nicklist = Vec::new();
+            for (id, prefix) in members {
+                match server.get_user_uid(id).await {
+                    Some(uid) if !skip_sids.iter().any(|sid| uid.starts_with(sid.as_str())) => {
+                        nicklist.push(format!("{}{}", prefix, uid));
+                    }
+                    _ => {}
+                }
+            }
+            if nicklist.is_empty() {
+                continue;
+            }
+
+            // SJOIN ts channel modes [mode params...] :[prefix]uid ...
+            let mut params = vec![ts.clone(), name.clone()];
+            params.extend(modes);
+            let prefix_len = format!(":{} SJOIN {} :", our_sid, params.join(" ")).len();
+            for chunk in split_burst_items(prefix_len, &nicklist) {
+                let mut chunk_params = params.clone();
+                chunk_params.push(chunk);
+                burst.push(TS6Message::with_source(our_sid.clone(), "SJOIN".to_string(), chunk_params));
+            }
+
+            // BMASK ts channel type :masks (exceptions and invex only to peers supporting them)
+            for (mode, masks) in lists {
+                if masks.is_empty()
+                    || (mode == 'e' && !capabilities.contains("EX"))
+                    || (mode == 'I' && !capabilities.contains("IE")) {
+                    continue;
+                }
+                let prefix_len = format!(":{} BMASK {} {} {} :", our_sid, ts, name, mode).len();
+                for chunk in split_burst_items(prefix_len, &masks) {
+                    burst.push(TS6Message::with_source(
+                        our_sid.clone(),
+                        "BMASK".to_string(),
+                        vec![ts.clone(), name.clone(), mode.to_string(), chunk],
+                    ));
+                }
+            }
+
+            // TB channel topicTS setter :topic
+            if let Some((topic, setter, topic_ts)) = topic.filter(|_| capabilities.contains("TB")) {
+                burst.push(TS6Message::with_source(
+                    our_sid.clone(),
+                    "TB".to_string(),
+                    vec![name.clone(), topic_ts.to_string(), setter.unwrap_or_else(|| server.config.server.name.clone()), topic],
+                ));
+            }
+        }
+
+        burst
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn sid(&self) -> &str {
+        &self.sid
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn is_incoming(&self) -> bool {
+        self.incoming
+    }
+
+    pub fn is_registered(&self) -> bool {
+        self.registered
+    }
+
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.remote_capabilities.as_ref()
+            .is_some_and(|caps| caps.contains(capability))
+    }
+
+    pub fn burst_stats(&self) -> &BurstStats {
+        &self.burst
     }
-}
\ No newline at end of file
+
+    pub fn is_synced(&self) -> bool {
+        self.burst.duration.is_some()
+    }
+
+    // Counts what the peer sends between registering and finishing its burst
+    fn record_burst_line(&mut self, msg: &TS6Message, len: usize) {
+        if !self.registered || self.is_synced() {
+            return;
+        }
+        self.burst.bytes += len + 2;
+        match msg.command.as_str() {
+            "UID" | "EUID" => self.burst.users += 1,
+            "SJOIN" => self.burst.channels += 1,
+            _ => {}
+        }
+    }
+
+    /// Marks the peer's burst as finished, returning its statistics the first time.
+    pub(crate) fn finish_burst(&mut self) -> Option<BurstStats> {
+        if !self.registered || self.is_synced() {
+            return None;
+        }
+        self.burst.duration = Some(self.burst.started.map_or(Duration::ZERO, |started| started.elapsed()));
+        Some(self.burst.clone())
+    }
+
+    pub(crate) fn set_remote_pass(&mut self, password: String, sid: String) {
+        self.remote_password = Some(password);
+        self.remote_sid = Some(sid);
+    }
+
+    pub(crate) fn remote_pass(&self) -> Option<(&str, &str)> {
+        match (&self.remote_password, &self.remote_sid) {
+            (Some(password), Some(sid)) => Some((password, sid)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn set_remote_capabilities(&mut self, capabilities: HashSet<String>) {
+        self.remote_capabilities = Some(capabilities);
+    }
+
+    pub(crate) fn has_remote_capabilities(&self) -> bool {
+        self.remote_capabilities.is_some()
+    }
+
+    /// Marks the handshake as complete, adopting the peer's verified identity.
+    pub(crate) fn complete_registration(&mut self, name: String, sid: String, description: String, password: String) {
+        self.name = name;
+        self.sid = sid;
+        self.description = description;
+        self.password = password;
+        self.registered = true;
+        self.burst.started = Some(Instant::now());
+    }
+}
+
+// Joins items with spaces into chunks that fit on a burst line after a prefix of the given length
+fn split_burst_items(prefix_len: usize, items: &[String]) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+
+    for item in items {
+        if !current.is_empty() && prefix_len + current.len() + 1 + item.len() > MAX_BURST_LINE {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(item);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
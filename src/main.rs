@@ -9,6 +9,7 @@ use crate::cli::{Cli, generate_example_config};
 use crate::config::ServerConfig;
 use crate::server::Server;
 
+mod cidr;
 mod config;
 mod server;
 mod client;
@@ -35,6 +36,11 @@ async fn main() -> Result<(), Box<dyn Error>> {
         return Ok(());
     }
 
+    if let Some(password) = &cli.mkpasswd {
+        println!("{}", server::hash_password(password)?);
+        return Ok(());
+    }
+
     // Initialize logging
     let log_level = match cli.log_level.to_lowercase().as_str() {
         "error" => Level::ERROR,
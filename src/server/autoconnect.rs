@@ -0,0 +1,228 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+
+use crate::config::ServerLinkConfig;
+use crate::error::{IrcError, IrcResult};
+use crate::link::ServerLink;
+use crate::server::{RemoteUser, Server};
+use crate::ts6::TS6Message;
+
+// First retry delay after a failed attempt, doubled for every further failure
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(2);
+
+/// Where a configured link is in its lifecycle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LinkState {
+    Connecting,  // Connecting or exchanging credentials
+    Bursting,    // Registered, waiting for the peer to finish its burst
+    Established,
+    Failed,
+}
+
+impl LinkState {
+    // Name shown to operators in STATS l
+    pub fn name(self) -> &'static str {
+        match self {
+            LinkState::Connecting => "connecting",
+            LinkState::Bursting => "bursting",
+            LinkState::Established => "established",
+            LinkState::Failed => "failed",
+        }
+    }
+}
+
+/// State of a configured link, for operators.
+#[derive(Clone, Debug)]
+pub struct LinkStatus {
+    pub state: LinkState,
+    pub failures: u32, // Failed attempts since the link was last established
+    pub last_error: Option<String>,
+}
+
+// Delay before the next attempt, backing off exponentially up to the link's connect frequency
+pub(crate) fn retry_delay(failures: u32, frequency: Duration) -> Duration {
+    let backoff = RETRY_BASE_DELAY.saturating_mul(1 << failures.saturating_sub(1).min(16));
+    backoff.min(frequency)
+}
+
+impl Server {
+    pub async fn get_link_status(&self, name: &str) -> Option<LinkStatus> {
+        let link_states = self.link_states.read().await;
+        link_states.get(&name.to_lowercase()).cloned()
+    }
+
+    // Every link that has been attempted or registered, by name
+    pub async fn get_link_statuses(&self) -> Vec<(String, LinkStatus)> {
+        let link_states = self.link_states.read().await;
+        let mut statuses: Vec<_> = link_states.iter()
+            .map(|(name, status)| (name.clone(), status.clone()))
+            .collect();
+        statuses.sort_by(|a, b| a.0.cmp(&b.0));
+        statuses
+    }
+
+    pub(crate) async fn set_link_state(&self, name: &str, state: LinkState) {
+        debug!("Link {} is now {:?}", name, state);
+        let mut link_states = self.link_states.write().await;
+        let status = link_states.entry(name.to_lowercase()).or_insert(LinkStatus {
+            state,
+            failures: 0,
+            last_error: None,
+        });
+        status.state = state;
+        if state == LinkState::Established {
+            status.failures = 0;
+        }
+    }
+
+    pub(crate) async fn set_link_failed(&self, name: &str, reason: &str) {
+        let mut link_states = self.link_states.write().await;
+        let status = link_states.entry(name.to_lowercase()).or_insert(LinkStatus {
+            state: LinkState::Failed,
+            failures: 0,
+            last_error: None,
+        });
+        status.state = LinkState::Failed;
+        status.failures += 1;
+        status.last_error = Some(reason.to_string());
+    }
+
+    // Whether the server is reachable already, directly or through another link
+    pub(crate) async fn is_linked(&self, name: &str) -> bool {
+        self.get_linked_server(name).await.is_some() || self.find_server_by_name(name).await.is_some()
+    }
+
+    // Connects to a configured server and sends our credentials; the caller runs the link
+    pub(crate) async fn open_link(&self, config: &ServerLinkConfig) -> IrcResult<Arc<Mutex<ServerLink>>> {
+        if self.is_linked(&config.name).await {
+            return Err(IrcError::ServerLink(format!("Already linked to {}", config.name)));
+        }
+        debug!("Connecting to server {} at {}", config.name, config.address);
+        self.set_link_state(&config.name, LinkState::Connecting).await;
+
+        // Connect to remote server
+        let stream = TcpStream::connect(&config.address).await.map_err(|e| {
+            error!("Failed to connect to {}: {}", config.address, e);
+            IrcError::Io(e)
+        })?;
+
+        // Create server link with the stream
+        let server_link = ServerLink::new(
+            stream,
+            config.name.clone(),
+            config.sid.clone(),
+            config.description.clone(),
+            config.password.clone(),
+            Arc::new(self.clone()),
+        )?;
+
+        // As the initiator we send PASS, CAPAB and SERVER first
+        server_link.send_credentials().await?;
+
+        // Store server link
+        let server_link = Arc::new(Mutex::new(server_link));
+        self.linked_servers.write().await.insert(config.name.clone(), Arc::clone(&server_link));
+        Ok(server_link)
+    }
+
+    // Opens a link and runs it in the background; the task ends when the link does
+    pub async fn connect_to_server(&self, config: &ServerLinkConfig) -> IrcResult<JoinHandle<()>> {
+        let server_link = self.open_link(config).await?;
+        let name = config.name.clone();
+        Ok(tokio::spawn(async move {
+            if let Err(e) = ServerLink::handle_connection(server_link).await {
+                warn!("Link to {} closed: {}", name, e);
+            }
+        }))
+    }
+
+    // Connects to a configured server for an operator's CONNECT, returning the notice telling them how it went
+    pub(crate) async fn connect_on_request(&self, target: &str, port: Option<u16>) -> String {
+        let Some(config) = self.find_link_config(target) else {
+            return format!("Connect: Host {} not listed in the configuration", target);
+        };
+        if self.is_linked(&config.name).await {
+            return format!("Connect: Server {} already exists", config.name);
+        }
+
+        let mut config = config.clone();
+        if let Some(port) = port {
+            let host = config.address.rsplit_once(':').map_or(config.address.as_str(), |(host, _)| host);
+            config.address = format!("{}:{}", host, port);
+        }
+        match self.connect_to_server(&config).await {
+            Ok(_) => format!("Connecting to {}[{}]", config.name, config.address),
+            Err(e) => format!("Connect to {} failed: {}", config.name, e),
+        }
+    }
+
+    // A CONNECT from an operator on another server that has been hunted to us. The future is boxed
+    // because the link it opens runs the link handler this is called from, a cycle async fns can't express.
+    pub(crate) fn handle_remote_connect<'a>(
+        &'a self,
+        user: &'a RemoteUser,
+        params: &'a [String],
+    ) -> Pin<Box<dyn Future<Output = IrcResult<()>> + Send + 'a>> {
+        Box::pin(async move {
+            if !user.modes.contains(&'o') {
+                warn!("Ignoring CONNECT from {}, who is not an operator", user.get_prefix());
+                return Ok(());
+            }
+            let Some(target) = params.first() else {
+                return Err(IrcError::Protocol("Invalid CONNECT parameters".into()));
+            };
+            let port = params.get(1).and_then(|port| port.parse().ok()).filter(|&port| port != 0);
+            let text = self.connect_on_request(target, port).await;
+            let notice = TS6Message::with_source(self.config.server.sid.clone(), "NOTICE".to_string(), vec![user.uid.clone(), text]);
+            self.send_to_remote_user(user, &notice).await
+        })
+    }
+
+    // Starts a supervisor for every link marked autoconnect
+    pub(crate) fn start_autoconnect(self: &Arc<Self>) {
+        for config in self.config.links.iter().filter(|link| link.autoconnect) {
+            let server = Arc::clone(self);
+            let config = config.clone();
+            tokio::spawn(async move {
+                server.supervise_link(config).await;
+            });
+        }
+    }
+
+    // Keeps a link up for as long as the server runs, retrying with backoff when it fails
+    async fn supervise_link(&self, config: ServerLinkConfig) {
+        let frequency = Duration::from_secs(config.connect_frequency);
+        info!("Autoconnecting to {} every {:?} at most", config.name, frequency);
+
+        loop {
+            // Don't duplicate a link the peer opened, or one reaching it through another server
+            if self.is_linked(&config.name).await {
+                tokio::time::sleep(frequency).await;
+                continue;
+            }
+
+            match self.connect_to_server(&config).await {
+                // A link that ran records its own failure when it is unregistered
+                Ok(link) => {
+                    link.await.ok();
+                }
+                Err(e) => {
+                    warn!("Failed to connect to {}: {}", config.name, e);
+                    self.set_link_failed(&config.name, &e.to_string()).await;
+                }
+            }
+
+            let failures = self.get_link_status(&config.name).await.map_or(1, |status| status.failures);
+            let delay = retry_delay(failures, frequency);
+            info!("Reconnecting to {} in {:?}", config.name, delay);
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
@@ -1,3 +1,4 @@
+use std::collections::HashSet;
 use std::sync::Arc;
 
 use tokio::sync::RwLock;
@@ -48,11 +49,17 @@ impl Server {
         }
     }
 
+    pub(crate) async fn get_channels(&self) -> Vec<Arc<RwLock<Channel>>> {
+        let channels = self.channels.read().await;
+        channels.values().cloned().collect()
+    }
+
     pub async fn get_channel(&self, name: &str) -> Option<Arc<RwLock<Channel>>> {
         let channels = self.channels.read().await;
         channels.get(name).cloned()
     }
 
+    // Delivers to local members only; remote members are reached through the server protocol
     pub async fn broadcast_to_channel(&self, channel_name: &str, message: &TS6Message, skip_client: Option<u32>) -> IrcResult<()> {
         let member_ids = {
             let channels = self.channels.read().await;
@@ -81,6 +88,30 @@ impl Server {
         Ok(())
     }
 
+    // Everyone sharing a channel with the given user, not including the user
+    pub(crate) async fn get_common_channel_members(&self, client_id: u32) -> HashSet<u32> {
+        let mut members = HashSet::new();
+        for channel_name in self.get_client_channels(client_id).await {
+            if let Some(channel) = self.get_channel(&channel_name).await {
+                members.extend(channel.read().await.get_members().iter().copied());
+            }
+        }
+        members.remove(&client_id);
+        members
+    }
+
+    // Sends a message once to every local user sharing a channel with the given user
+    pub(crate) async fn broadcast_to_common_channels(&self, client_id: u32, message: &TS6Message) {
+        for id in self.get_common_channel_members(client_id).await {
+            if let Some(client) = self.get_client(id).await {
+                let client = client.lock().await;
+                if let Err(e) = client.send_message(message).await {
+                    warn!("Failed to send message to client {}: {}", id, e);
+                }
+            }
+        }
+    }
+
     pub async fn check_channel_membership(&self, channel_name: &str, client_id: u32) -> bool {
         let channels = self.channels.read().await;
         if let Some(channel) = channels.get(channel_name) {
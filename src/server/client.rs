@@ -1,16 +1,16 @@
-use std::sync::{Arc, mpsc};
+use std::sync::Arc;
 
 use tokio::sync::Mutex;
 use tracing::{debug, info};
 
 use crate::client::Client;
-use crate::server::{ClientId, Server};
+use crate::server::{ClientId, RemoteUser, Server};
 
-pub enum ServerMessage {
-    WhoisLookup {
-        nickname: String,
-        respond_to: mpsc::Sender<Option<WhoisInfo>>,
-    },
+/// A user known to this server, either connected here or on a linked server.
+#[derive(Clone)]
+pub enum User {
+    Local(Arc<Mutex<Client>>),
+    Remote(Box<RemoteUser>),
 }
 
 #[derive(Clone)]
@@ -19,6 +19,9 @@ pub struct WhoisInfo {
     pub username: String,
     pub hostname: String,
     pub realname: String,
+    pub server: String,
+    pub server_info: String,
+    pub idle: Option<(u64, i64)>, // Seconds idle and signon time, known only where the user is connected
 }
 
 impl Server {
@@ -27,14 +30,23 @@ impl Server {
         client_map.get(&id).cloned()
     }
 
+    pub(crate) async fn get_clients(&self) -> Vec<Arc<Mutex<Client>>> {
+        let client_map = self.client_map.read().await;
+        client_map.values().cloned().collect()
+    }
+
     // Update add_client to store in both the list and map
     pub async fn add_client(&self, client: Arc<Mutex<Client>>) {
-        let id = client.lock().await.id();
+        let (id, uid) = {
+            let client = client.lock().await;
+            (client.id(), client.uid().to_string())
+        };
         let mut clients = self.clients.write().await;
         let mut client_map = self.client_map.write().await;
 
         clients.push(id);
         client_map.insert(id, client);
+        self.uid_map.write().await.insert(uid, id);
         debug!("Added client {} to server", id);
     }
 
@@ -53,23 +65,78 @@ impl Server {
         if client_map.remove(&id).is_some() {
             info!("Removed client {} from server", id);
         }
+        self.uid_map.write().await.retain(|_, &mut cid| cid != id);
 
         // Could also clean up from channels here if needed
         debug!("Client {} cleanup completed", id);
     }
 
-    pub async fn find_client_info(&self, nickname: &str) -> Option<WhoisInfo> {
-        let (resp_tx, resp_rx) = mpsc::channel();
+    pub async fn find_client_by_uid(&self, uid: &str) -> Option<ClientId> {
+        let uid_map = self.uid_map.read().await;
+        uid_map.get(uid).copied()
+    }
+
+    // Local client or remote user by ID
+    pub(crate) async fn get_user(&self, id: ClientId) -> Option<User> {
+        if let Some(client) = self.get_client(id).await {
+            return Some(User::Local(client));
+        }
+        self.get_remote_user(id).await.map(|user| User::Remote(Box::new(user)))
+    }
+
+    // Nickname of a user by ID, used where only channel membership is known
+    pub(crate) async fn get_user_nick(&self, id: ClientId) -> Option<String> {
+        match self.get_user(id).await? {
+            User::Local(client) => client.lock().await.get_nickname().cloned(),
+            User::Remote(user) => Some(user.nickname),
+        }
+    }
 
-        self.tx.send(ServerMessage::WhoisLookup {
-            nickname: nickname.to_string(),
-            respond_to: resp_tx,
-        }).expect("Server task died");
+    pub(crate) async fn get_user_uid(&self, id: ClientId) -> Option<String> {
+        match self.get_user(id).await? {
+            User::Local(client) => Some(client.lock().await.uid().to_string()),
+            User::Remote(user) => Some(user.uid),
+        }
+    }
 
-        resp_rx.recv().expect("Server task died")
+    pub(crate) async fn get_user_prefix(&self, id: ClientId) -> Option<String> {
+        match self.get_user(id).await? {
+            User::Local(client) => Some(client.lock().await.get_prefix()),
+            User::Remote(user) => Some(user.get_prefix()),
+        }
     }
 
-    pub async fn find_client_by_nick(&self, nickname: &str) -> Option<Arc<Mutex<Client>>> {
+    // The caller must not look up its own nickname, as that would lock its own client
+    pub async fn find_client_info(&self, nickname: &str) -> Option<WhoisInfo> {
+        match self.find_client_by_nick(nickname).await? {
+            User::Local(client) => {
+                let client = client.lock().await;
+                Some(WhoisInfo {
+                    nickname: client.get_nickname().cloned().unwrap_or_default(),
+                    username: client.get_username().cloned().unwrap_or_default(),
+                    hostname: client.get_hostname().to_string(),
+                    realname: client.get_realname().cloned().unwrap_or_default(),
+                    server: self.config.server.name.clone(),
+                    server_info: self.config.server.description.clone(),
+                    idle: Some((client.idle_time(), client.signon_time())),
+                })
+            }
+            User::Remote(user) => {
+                let (server, server_info) = self.get_server_info(&user.server).await;
+                Some(WhoisInfo {
+                    nickname: user.nickname,
+                    username: user.username,
+                    hostname: user.hostname,
+                    realname: user.realname,
+                    server,
+                    server_info,
+                    idle: None,
+                })
+            }
+        }
+    }
+
+    pub async fn find_client_by_nick(&self, nickname: &str) -> Option<User> {
         let nickname_lower = nickname.to_lowercase();
         debug!("find_client_by_nick: Looking for nickname {} (lowercase: {})", nickname, nickname_lower);
 
@@ -79,13 +146,16 @@ impl Server {
             nicknames.get(&nickname_lower).copied()
         };
 
-        // Then get the client from the client map if we found an ID
+        // Then get the local client or remote user if we found an ID
         if let Some(id) = client_id {
-            let clients = self.client_map.read().await;
-            return clients.get(&id).cloned();
+            return self.get_user(id).await;
         }
 
         debug!("find_client_by_nick: No match found for {}", nickname);
         None
     }
+
+    pub(crate) async fn find_client_id_by_nick(&self, nickname: &str) -> Option<ClientId> {
+        self.nickname_map.read().await.get(&nickname.to_lowercase()).copied()
+    }
 }
\ No newline at end of file
@@ -0,0 +1,224 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::error::{IrcError, IrcResult};
+use crate::link::ServerLink;
+use crate::server::{ClientId, Server, User};
+use crate::ts6::TS6Message;
+
+// Nick TS given to users saved to their UID, as charybdis does
+pub(crate) const SAVE_NICK_TS: u64 = 100;
+
+/// What happens to a user arriving with a nickname that is already taken.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum NickCollision {
+    Won,  // The nickname is free for the new user
+    Save, // The new user loses and is renamed to its UID
+    Kill, // The new user loses and must be killed
+}
+
+// Applies the nick TS rules, returning whether the existing and the new user lose
+fn collision_losers(existing_ts: u64, existing_userhost: &str, new_ts: u64, new_userhost: &str) -> (bool, bool) {
+    let same_userhost = existing_userhost.eq_ignore_ascii_case(new_userhost);
+    match new_ts.cmp(&existing_ts) {
+        Ordering::Less if same_userhost => (false, true),
+        Ordering::Less => (true, false),
+        Ordering::Equal => (true, true),
+        Ordering::Greater if same_userhost => (true, false),
+        Ordering::Greater => (false, true),
+    }
+}
+
+impl Server {
+    // Resolves a collision between the current holder of a nickname and a user arriving from a link.
+    // The existing user is dealt with here; the caller acts on the returned fate of the new user.
+    pub(crate) async fn resolve_nick_collision(
+        &self,
+        link: &Arc<Mutex<ServerLink>>,
+        nickname: &str,
+        new_ts: u64,
+        new_userhost: &str,
+    ) -> NickCollision {
+        let existing_id = {
+            let nicknames = self.nickname_map.read().await;
+            nicknames.get(&nickname.to_lowercase()).copied()
+        };
+        let Some(existing_id) = existing_id else {
+            return NickCollision::Won;
+        };
+        let Some((existing_ts, existing_userhost)) = self.get_nick_ts_and_userhost(existing_id).await else {
+            return NickCollision::Won;
+        };
+
+        let (existing_loses, new_loses) = collision_losers(existing_ts, &existing_userhost, new_ts, new_userhost);
+        let save = link.lock().await.has_capability("SAVE");
+        info!("Nick collision on {}: existing user {}, new user {}", nickname,
+              if existing_loses { "loses" } else { "wins" }, if new_loses { "loses" } else { "wins" });
+
+        if existing_loses {
+            if save {
+                self.save_user(existing_id, None).await;
+            } else {
+                self.kill_for_collision(existing_id).await;
+            }
+        }
+
+        match (new_loses, save) {
+            (false, _) => NickCollision::Won,
+            (true, true) => NickCollision::Save,
+            (true, false) => NickCollision::Kill,
+        }
+    }
+
+    async fn get_nick_ts_and_userhost(&self, id: ClientId) -> Option<(u64, String)> {
+        match self.get_user(id).await? {
+            User::Local(client) => {
+                let client = client.lock().await;
+                let username = client.get_username().cloned().unwrap_or_default();
+                Some((client.nick_ts(), format!("{}@{}", username, client.get_hostname())))
+            }
+            User::Remote(user) => Some((user.nick_ts, format!("{}@{}", user.username, user.hostname))),
+        }
+    }
+
+    // Renames a user to its UID and tells the network, except the link the SAVE came from
+    pub(crate) async fn save_user(&self, id: ClientId, skip: Option<&Arc<Mutex<ServerLink>>>) {
+        let Some(uid) = self.get_user_uid(id).await else {
+            return;
+        };
+        let Some((nick_ts, _)) = self.get_nick_ts_and_userhost(id).await else {
+            return;
+        };
+
+        self.rename_user(id, &uid, SAVE_NICK_TS).await;
+        self.propagate_save(&uid, nick_ts, skip).await;
+    }
+
+    // Sends SAVE to links that support it and the equivalent NICK to the others
+    async fn propagate_save(&self, uid: &str, nick_ts: u64, skip: Option<&Arc<Mutex<ServerLink>>>) {
+        let save_msg = TS6Message::with_source(
+            self.config.server.sid.clone(),
+            "SAVE".to_string(),
+            vec![uid.to_string(), nick_ts.to_string()],
+        );
+        let nick_msg = TS6Message::with_source(
+            uid.to_string(),
+            "NICK".to_string(),
+            vec![uid.to_string(), SAVE_NICK_TS.to_string()],
+        );
+
+        let links: Vec<_> = self.linked_servers.read().await.values().cloned().collect();
+        for link in links {
+            if skip.is_some_and(|skip| Arc::ptr_eq(skip, &link)) {
+                continue;
+            }
+            let link = link.lock().await;
+            if !link.is_registered() {
+                continue;
+            }
+            let message = if link.has_capability("SAVE") { &save_msg } else { &nick_msg };
+            if let Err(e) = link.send_message(message).await {
+                warn!("Failed to send to server {}: {}", link.name(), e);
+            }
+        }
+    }
+
+    // Kills an existing user that lost a collision on a link without SAVE
+    async fn kill_for_collision(&self, id: ClientId) {
+        let Some(user) = self.get_user(id).await else {
+            return;
+        };
+        let Some(uid) = self.get_user_uid(id).await else {
+            return;
+        };
+
+        let kill_msg = self.collision_kill(&uid);
+        self.send_to_servers(&kill_msg, None).await;
+
+        match user {
+            User::Local(client) => {
+                let mut client = client.lock().await;
+                if let Some(nickname) = client.get_nickname().cloned() {
+                    self.unregister_nickname(&nickname).await;
+                }
+                client.disconnect_killed("Nick collision").await;
+            }
+            User::Remote(user) => self.remove_remote_user(&user, "Nick collision").await,
+        }
+    }
+
+    pub(crate) fn collision_kill(&self, uid: &str) -> TS6Message {
+        TS6Message::with_source(
+            self.config.server.sid.clone(),
+            "KILL".to_string(),
+            vec![uid.to_string(), format!("{} (Nick collision)", self.config.server.name)],
+        )
+    }
+
+    // Changes a user's nickname, showing the change to the user and everyone sharing a channel
+    pub(crate) async fn rename_user(&self, id: ClientId, new_nick: &str, nick_ts: u64) {
+        let Some(user) = self.get_user(id).await else {
+            return;
+        };
+        let Some(old_prefix) = self.get_user_prefix(id).await else {
+            return;
+        };
+        let Some(old_nick) = self.get_user_nick(id).await else {
+            return;
+        };
+
+        {
+            let mut nicknames = self.nickname_map.write().await;
+            if nicknames.get(&old_nick.to_lowercase()) == Some(&id) {
+                nicknames.remove(&old_nick.to_lowercase());
+            }
+            nicknames.insert(new_nick.to_lowercase(), id);
+        }
+
+        let nick_msg = TS6Message::with_source(old_prefix, "NICK".to_string(), vec![new_nick.to_string()]);
+        match user {
+            User::Local(client) => {
+                let mut client = client.lock().await;
+                client.set_nickname_ts(new_nick.to_string(), nick_ts);
+                client.send_message(&nick_msg).await.ok();
+            }
+            User::Remote(_) => {
+                if let Some(remote) = self.remote_users.write().await.get_mut(&id) {
+                    remote.nickname = new_nick.to_string();
+                    remote.nick_ts = nick_ts;
+                }
+            }
+        }
+        debug!("Renamed {} to {}", old_nick, new_nick);
+        self.broadcast_to_common_channels(id, &nick_msg).await;
+    }
+
+    pub(crate) async fn handle_server_save(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // :sid SAVE uid :nickTS
+        if msg.params.len() < 2 {
+            return Err(IrcError::Protocol("Invalid SAVE parameters".into()));
+        }
+
+        let uid = &msg.params[0];
+        let Some(id) = self.find_client_by_uid(uid).await else {
+            debug!("SAVE for unknown user {}", uid);
+            return Ok(());
+        };
+
+        // Drop the SAVE unless it matches the user's current nick TS and the user isn't saved already
+        let nick_ts = msg.params[1].parse::<u64>().unwrap_or(0);
+        let current_ts = self.get_nick_ts_and_userhost(id).await.map(|(ts, _)| ts);
+        let current_nick = self.get_user_nick(id).await;
+        if current_ts != Some(nick_ts) || current_nick.as_deref() == Some(uid.as_str()) {
+            debug!("Dropping stale SAVE for {}", uid);
+            return Ok(());
+        }
+
+        self.rename_user(id, uid, SAVE_NICK_TS).await;
+        self.propagate_save(uid, nick_ts, Some(link)).await;
+        Ok(())
+    }
+}
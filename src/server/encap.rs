@@ -0,0 +1,346 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::config::{DLine, GLine, KLine, Resv};
+use crate::error::{IrcError, IrcResult};
+use crate::link::ServerLink;
+use crate::server::{ClientId, Server, User};
+use crate::ts6::TS6Message;
+
+impl Server {
+    // Sends a message down every link with a server matching the mask behind it, except the one it came from
+    pub(crate) async fn send_to_matching_servers(&self, mask: &str, message: &TS6Message, skip: Option<&Arc<Mutex<ServerLink>>>) {
+        let mask = mask.to_lowercase();
+        let links: Vec<_> = self.linked_servers.read().await.values().cloned().collect();
+        for link in links {
+            if skip.is_some_and(|skip| Arc::ptr_eq(skip, &link)) {
+                continue;
+            }
+            let name = {
+                let link = link.lock().await;
+                if !link.is_registered() {
+                    continue;
+                }
+                link.name().to_string()
+            };
+
+            let subtree = self.get_server_subtree(&name).await;
+            if !subtree.iter().any(|server| self.mask_match(&server.name.to_lowercase(), &mask)) {
+                continue;
+            }
+            let link = link.lock().await;
+            if let Err(e) = link.send_message(message).await {
+                warn!("Failed to send to server {}: {}", link.name(), e);
+            }
+        }
+    }
+
+    pub(crate) async fn handle_server_encap(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // :source ENCAP mask subcommand [params...]
+        if msg.params.len() < 2 {
+            return Err(IrcError::Protocol("Invalid ENCAP parameters".into()));
+        }
+
+        // Pass it on first; servers that don't know the subcommand still route it
+        let mask = &msg.params[0];
+        self.send_to_matching_servers(mask, &msg, Some(link)).await;
+
+        if !self.mask_match(&self.config.server.name.to_lowercase(), &mask.to_lowercase()) {
+            return Ok(());
+        }
+        let source = msg.source.clone().unwrap_or_default();
+        let subcommand = msg.params[1].to_uppercase();
+        self.dispatch_encap(&source, &subcommand, &msg.params[2..]).await
+    }
+
+    // Runs the local handler for an ENCAP subcommand
+    async fn dispatch_encap(&self, source: &str, subcommand: &str, params: &[String]) -> IrcResult<()> {
+        let is_ban = matches!(subcommand, "KLINE" | "UNKLINE" | "DLINE" | "UNDLINE" | "GLINE" | "UNGLINE" | "RESV" | "UNRESV");
+        if is_ban && !self.is_shared_ban_source(source).await {
+            warn!("Ignoring ENCAP {} from {}: not an operator on a U-lined server", subcommand, source);
+            return Ok(());
+        }
+
+        match subcommand {
+            "LOGIN" => self.handle_encap_login(source, params).await,
+            "SU" => self.handle_encap_su(params).await,
+            "CHGHOST" => self.handle_encap_chghost(params).await,
+            "REALHOST" => self.handle_encap_realhost(source, params).await,
+            "CERTFP" => self.handle_encap_certfp(source, params).await,
+            "KLINE" => self.handle_encap_kline(source, params).await,
+            "UNKLINE" => self.handle_encap_unkline(source, params).await,
+            "DLINE" => self.handle_encap_dline(source, params).await,
+            "UNDLINE" => self.handle_encap_undline(source, params).await,
+            "GLINE" => self.handle_encap_gline(source, params).await,
+            "UNGLINE" => self.handle_encap_ungline(source, params).await,
+            "RESV" => self.handle_encap_resv(source, params).await,
+            "UNRESV" => self.handle_encap_unresv(source, params).await,
+            "SNOTE" => self.handle_encap_snote(source, params).await,
+            _ => {
+                debug!("Unhandled ENCAP {} from {}", subcommand, source);
+                Ok(())
+            }
+        }
+    }
+
+    // Applies a change to a remote user, returning false for unknown or local users
+    pub(crate) async fn update_remote_user<F: FnOnce(&mut crate::server::RemoteUser)>(&self, uid: &str, update: F) -> bool {
+        let Some(id) = self.find_client_by_uid(uid).await else {
+            return false;
+        };
+        let mut remote_users = self.remote_users.write().await;
+        match remote_users.get_mut(&id) {
+            Some(user) => {
+                update(user);
+                true
+            }
+            None => false,
+        }
+    }
+
+    async fn set_account(&self, id: ClientId, account: Option<String>) {
+        match self.get_user(id).await {
+            Some(User::Local(client)) => client.lock().await.set_account(account),
+            Some(User::Remote(user)) => {
+                self.update_remote_user(&user.uid, |user| user.account = account).await;
+            }
+            None => {}
+        }
+    }
+
+    async fn handle_encap_login(&self, source: &str, params: &[String]) -> IrcResult<()> {
+        // :uid ENCAP * LOGIN account
+        let account = params.first()
+            .ok_or_else(|| IrcError::Protocol("Invalid LOGIN parameters".into()))?;
+        let account = Some(account.clone()).filter(|account| account != "*");
+        if let Some(id) = self.find_client_by_uid(source).await {
+            self.set_account(id, account).await;
+        }
+        Ok(())
+    }
+
+    async fn handle_encap_su(&self, params: &[String]) -> IrcResult<()> {
+        // :services ENCAP * SU uid [account]; no account logs the user out
+        let uid = params.first()
+            .ok_or_else(|| IrcError::Protocol("Invalid SU parameters".into()))?;
+        let account = params.get(1).cloned().filter(|account| !account.is_empty());
+        if let Some(id) = self.find_client_by_uid(uid).await {
+            self.set_account(id, account).await;
+        }
+        Ok(())
+    }
+
+    async fn handle_encap_chghost(&self, params: &[String]) -> IrcResult<()> {
+        // :source ENCAP * CHGHOST uid newhost
+        if params.len() < 2 {
+            return Err(IrcError::Protocol("Invalid CHGHOST parameters".into()));
+        }
+        let Some(id) = self.find_client_by_uid(&params[0]).await else {
+            return Ok(());
+        };
+        match self.get_user(id).await {
+            Some(User::Local(client)) => client.lock().await.set_hostname(params[1].clone()),
+            Some(User::Remote(user)) => {
+                self.update_remote_user(&user.uid, |user| user.hostname = params[1].clone()).await;
+            }
+            None => {}
+        }
+        Ok(())
+    }
+
+    async fn handle_encap_realhost(&self, source: &str, params: &[String]) -> IrcResult<()> {
+        // :uid ENCAP * REALHOST host
+        let host = params.first()
+            .ok_or_else(|| IrcError::Protocol("Invalid REALHOST parameters".into()))?;
+        self.update_remote_user(source, |user| user.realhost = host.clone()).await;
+        Ok(())
+    }
+
+    async fn handle_encap_certfp(&self, source: &str, params: &[String]) -> IrcResult<()> {
+        // :uid ENCAP * CERTFP fingerprint
+        let certfp = params.first()
+            .ok_or_else(|| IrcError::Protocol("Invalid CERTFP parameters".into()))?;
+        self.update_remote_user(source, |user| user.certfp = Some(certfp.clone())).await;
+        Ok(())
+    }
+
+    // Bans are only taken from operators on servers we have a U-line for
+    async fn is_shared_ban_source(&self, source: &str) -> bool {
+        let Some(user) = self.find_remote_user_by_uid(source).await else {
+            return false;
+        };
+        if !user.modes.contains(&'o') {
+            return false;
+        }
+        let (server, _) = self.get_server_info(&user.server).await;
+        let server = server.to_lowercase();
+        self.ulines.read().await.iter()
+            .any(|uline| self.mask_match(&server, &uline.server.to_lowercase()))
+    }
+
+    // Who set a line, as shown in listings
+    async fn get_setter(&self, source: &str) -> String {
+        if let Some(id) = self.find_client_by_uid(source).await {
+            if let Some(prefix) = self.get_user_prefix(id).await {
+                return prefix;
+            }
+        }
+        let (name, _) = self.get_server_info(source).await;
+        name
+    }
+
+    async fn handle_encap_kline(&self, source: &str, params: &[String]) -> IrcResult<()> {
+        // :uid ENCAP mask KLINE duration user host :reason
+        if params.len() < 4 {
+            return Err(IrcError::Protocol("Invalid KLINE parameters".into()));
+        }
+        let kline = KLine {
+            mask: format!("{}@{}", params[1], params[2]),
+            reason: params[3].clone(),
+            set_by: self.get_setter(source).await,
+            duration: params[0].parse().unwrap_or(0),
+            set_time: Utc::now(),
+        };
+        info!("K-line for {} from {}: {}", kline.mask, kline.set_by, kline.reason);
+        self.add_kline(kline).await
+            .map_err(|e| IrcError::Protocol(format!("Failed to add K-line: {}", e)))?;
+        self.disconnect_banned().await;
+        Ok(())
+    }
+
+    async fn handle_encap_unkline(&self, source: &str, params: &[String]) -> IrcResult<()> {
+        // :uid ENCAP mask UNKLINE user host
+        if params.len() < 2 {
+            return Err(IrcError::Protocol("Invalid UNKLINE parameters".into()));
+        }
+        let mask = format!("{}@{}", params[0], params[1]);
+        info!("K-line for {} removed by {}", mask, self.get_setter(source).await);
+        self.remove_kline(mask).await
+            .map_err(|e| IrcError::Protocol(format!("Failed to remove K-line: {}", e)))?;
+        Ok(())
+    }
+
+    async fn handle_encap_gline(&self, source: &str, params: &[String]) -> IrcResult<()> {
+        // :uid ENCAP * GLINE duration user host :reason
+        if params.len() < 4 {
+            return Err(IrcError::Protocol("Invalid GLINE parameters".into()));
+        }
+        let gline = GLine {
+            mask: format!("{}@{}", params[1], params[2]),
+            reason: params[3].clone(),
+            set_by: self.get_setter(source).await,
+            duration: params[0].parse().unwrap_or(0),
+            set_time: Utc::now(),
+        };
+        info!("G-line for {} from {}: {}", gline.mask, gline.set_by, gline.reason);
+        self.add_gline(gline).await
+            .map_err(|e| IrcError::Protocol(format!("Failed to add G-line: {}", e)))?;
+        self.disconnect_banned().await;
+        Ok(())
+    }
+
+    async fn handle_encap_ungline(&self, source: &str, params: &[String]) -> IrcResult<()> {
+        // :uid ENCAP * UNGLINE user host
+        if params.len() < 2 {
+            return Err(IrcError::Protocol("Invalid UNGLINE parameters".into()));
+        }
+        let mask = format!("{}@{}", params[0], params[1]);
+        info!("G-line for {} removed by {}", mask, self.get_setter(source).await);
+        self.remove_gline(&mask).await
+            .map_err(|e| IrcError::Protocol(format!("Failed to remove G-line: {}", e)))?;
+        Ok(())
+    }
+
+    async fn handle_encap_dline(&self, source: &str, params: &[String]) -> IrcResult<()> {
+        // :uid ENCAP mask DLINE duration ip :reason
+        if params.len() < 3 {
+            return Err(IrcError::Protocol("Invalid DLINE parameters".into()));
+        }
+        let ip = params[1].parse()
+            .map_err(|e| IrcError::Protocol(format!("Invalid D-line mask: {}", e)))?;
+        let dline = DLine {
+            ip,
+            reason: params[2].clone(),
+            set_by: self.get_setter(source).await,
+            duration: params[0].parse().unwrap_or(0),
+            set_time: Utc::now(),
+        };
+        info!("D-line for {} from {}: {}", dline.ip, dline.set_by, dline.reason);
+        self.add_dline(dline).await
+            .map_err(|e| IrcError::Protocol(format!("Failed to add D-line: {}", e)))?;
+        self.disconnect_banned().await;
+        Ok(())
+    }
+
+    async fn handle_encap_undline(&self, source: &str, params: &[String]) -> IrcResult<()> {
+        // :uid ENCAP mask UNDLINE ip
+        let ip = params.first()
+            .ok_or_else(|| IrcError::Protocol("Invalid UNDLINE parameters".into()))?
+            .parse()
+            .map_err(|e| IrcError::Protocol(format!("Invalid D-line mask: {}", e)))?;
+        info!("D-line for {} removed by {}", ip, self.get_setter(source).await);
+        self.remove_dline(ip).await
+            .map_err(|e| IrcError::Protocol(format!("Failed to remove D-line: {}", e)))?;
+        Ok(())
+    }
+
+    async fn handle_encap_resv(&self, source: &str, params: &[String]) -> IrcResult<()> {
+        // :uid ENCAP mask RESV duration name 0 :reason
+        if params.len() < 4 {
+            return Err(IrcError::Protocol("Invalid RESV parameters".into()));
+        }
+        let resv = Resv {
+            mask: params[1].clone(),
+            reason: params[params.len() - 1].clone(),
+            set_by: self.get_setter(source).await,
+            duration: params[0].parse().unwrap_or(0),
+            set_time: Utc::now(),
+        };
+        info!("RESV for {} from {}: {}", resv.mask, resv.set_by, resv.reason);
+        self.add_resv(resv).await
+            .map_err(|e| IrcError::Protocol(format!("Failed to add RESV: {}", e)))?;
+        Ok(())
+    }
+
+    async fn handle_encap_unresv(&self, source: &str, params: &[String]) -> IrcResult<()> {
+        // :uid ENCAP mask UNRESV name
+        let mask = params.first()
+            .ok_or_else(|| IrcError::Protocol("Invalid UNRESV parameters".into()))?;
+        info!("RESV for {} removed by {}", mask, self.get_setter(source).await);
+        self.remove_resv(mask).await
+            .map_err(|e| IrcError::Protocol(format!("Failed to remove RESV: {}", e)))?;
+        Ok(())
+    }
+
+    async fn handle_encap_snote(&self, source: &str, params: &[String]) -> IrcResult<()> {
+        // :sid ENCAP * SNOTE letter :text
+        if params.len() < 2 {
+            return Err(IrcError::Protocol("Invalid SNOTE parameters".into()));
+        }
+        let (server, _) = self.get_server_info(source).await;
+        self.send_server_notice(&server, &params[1]).await;
+        Ok(())
+    }
+
+    // Sends a server notice to every local operator
+    pub(crate) async fn send_server_notice(&self, server: &str, text: &str) {
+        for client in self.get_clients().await {
+            let client = client.lock().await;
+            if !client.get_modes().contains('o') {
+                continue;
+            }
+            let Some(nickname) = client.get_nickname().cloned() else {
+                continue;
+            };
+            let notice = TS6Message::with_source(
+                server.to_string(),
+                "NOTICE".to_string(),
+                vec![nickname, format!("*** Notice -- {}", text)],
+            );
+            client.send_message(&notice).await.ok();
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use tokio::sync::Notify;
+use tracing::{info, warn};
+
+use crate::cidr::IpNet;
+use crate::config::Expiring;
+use crate::server::Server;
+
+/// A temporary ban waiting to lapse, by the key its list is searched with.
+#[derive(Debug)]
+pub(crate) enum TimedBan {
+    KLine(String),
+    DLine(IpNet),
+    GLine(String),
+    Resv(String),
+}
+
+/// Temporary bans by the time they lapse, with a wakeup for the expiry task when an earlier one is added.
+#[derive(Default)]
+pub(crate) struct ExpiryQueue {
+    due: BTreeMap<DateTime<Utc>, Vec<TimedBan>>,
+    wakeup: Arc<Notify>,
+}
+
+impl ExpiryQueue {
+    fn schedule(&mut self, ban: TimedBan, at: Option<DateTime<Utc>>) {
+        let Some(at) = at else {
+            return;
+        };
+        let earliest = self.due.keys().next().is_none_or(|&next| at < next);
+        self.due.entry(at).or_default().push(ban);
+        if earliest {
+            self.wakeup.notify_one();
+        }
+    }
+
+    fn take_due(&mut self, now: DateTime<Utc>) -> Vec<TimedBan> {
+        let later = self.due.split_off(&(now + chrono::Duration::nanoseconds(1)));
+        std::mem::replace(&mut self.due, later).into_values().flatten().collect()
+    }
+}
+
+impl Server {
+    // Queues a line to be removed once its duration has passed; permanent lines are ignored
+    pub(crate) async fn schedule_expiry(&self, ban: TimedBan, line: &impl Expiring) {
+        self.expiry.lock().await.schedule(ban, line.expires_at());
+    }
+
+    pub(crate) fn start_expiry(self: &Arc<Self>) {
+        let server = Arc::clone(self);
+        tokio::spawn(async move {
+            server.run_expiry().await;
+        });
+    }
+
+    async fn run_expiry(&self) {
+        self.schedule_existing().await;
+        let wakeup = Arc::clone(&self.expiry.lock().await.wakeup);
+        loop {
+            let next = self.expiry.lock().await.due.keys().next().copied();
+            match next {
+                Some(at) => {
+                    let wait = (at - Utc::now()).to_std().unwrap_or_default();
+                    tokio::select! {
+                        _ = tokio::time::sleep(wait) => {}
+                        _ = wakeup.notified() => continue,
+                    }
+                }
+                None => {
+                    wakeup.notified().await;
+                    continue;
+                }
+            }
+
+            let due = self.expiry.lock().await.take_due(Utc::now());
+            for ban in due {
+                self.expire(ban).await;
+            }
+        }
+    }
+
+    // Lines from the config and database that were set before the task started
+    async fn schedule_existing(&self) {
+        let mut bans = Vec::new();
+        for kline in self.klines.read().await.iter() {
+            bans.push((TimedBan::KLine(kline.mask.clone()), kline.expires_at()));
+        }
+        for dline in self.dlines.read().await.values() {
+            bans.push((TimedBan::DLine(dline.ip), dline.expires_at()));
+        }
+        for gline in self.glines.read().await.iter() {
+            bans.push((TimedBan::GLine(gline.mask.clone()), gline.expires_at()));
+        }
+        for resv in self.resvs.read().await.iter() {
+            bans.push((TimedBan::Resv(resv.mask.clone()), resv.expires_at()));
+        }
+
+        let mut expiry = self.expiry.lock().await;
+        for (ban, at) in bans {
+            expiry.schedule(ban, at);
+        }
+    }
+
+    // Removes a lapsed line, unless it has since been replaced by one that is still active
+    async fn expire(&self, ban: TimedBan) {
+        let (kind, mask, result) = match &ban {
+            TimedBan::KLine(mask) => {
+                let expired = self.klines.read().await.iter()
+                    .any(|kline| kline.mask.eq_ignore_ascii_case(mask) && kline.is_expired());
+                if !expired {
+                    return;
+                }
+                ("K-line", mask.clone(), self.remove_kline(mask.clone()).await.map_err(|e| e.to_string()))
+            }
+            TimedBan::DLine(ip) => {
+                let expired = self.dlines.read().await.values()
+                    .any(|dline| dline.ip == *ip && dline.is_expired());
+                if !expired {
+                    return;
+                }
+                ("D-line", ip.to_string(), self.remove_dline(*ip).await.map_err(|e| e.to_string()))
+            }
+            TimedBan::GLine(mask) => {
+                let expired = self.glines.read().await.iter()
+                    .any(|gline| gline.mask.eq_ignore_ascii_case(mask) && gline.is_expired());
+                if !expired {
+                    return;
+                }
+                ("G-line", mask.clone(), self.remove_gline(mask).await.map_err(|e| e.to_string()))
+            }
+            TimedBan::Resv(mask) => {
+                let expired = self.resvs.read().await.iter()
+                    .any(|resv| resv.mask.eq_ignore_ascii_case(mask) && resv.is_expired());
+                if !expired {
+                    return;
+                }
+                ("RESV", mask.clone(), self.remove_resv(mask).await.map_err(|e| e.to_string()))
+            }
+        };
+
+        // A line still in the database would come back on restart, so it isn't reported as gone
+        if let Err(e) = result {
+            warn!("Failed to remove expired {} for {}: {}", kind, mask, e);
+            return;
+        }
+        info!("Temporary {} for [{}] expired", kind, mask);
+        self.send_server_notice(&self.config.server.name, &format!("Temporary {} for [{}] expired", kind, mask)).await;
+    }
+}
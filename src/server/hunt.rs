@@ -0,0 +1,165 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use crate::error::{IrcError, IrcResult};
+use crate::link::ServerLink;
+use crate::server::{Numeric, RemoteUser, Server, User};
+use crate::ts6::TS6Message;
+
+/// Where a command naming a server, mask or user should be answered.
+pub(crate) enum HuntTarget {
+    Local,
+    Remote(Arc<Mutex<ServerLink>>, String), // Link to send it down, and the SID or name to address
+    NotFound,
+}
+
+impl Server {
+    // Finds the server a hunted parameter refers to: this server, a user's server, a server or a server mask
+    pub(crate) async fn hunt_server(&self, target: &str) -> HuntTarget {
+        if self.is_us(target) {
+            return HuntTarget::Local;
+        }
+
+        let user = match self.find_client_by_uid(target).await {
+            Some(id) => self.get_user(id).await,
+            None => self.find_client_by_nick(target).await,
+        };
+        match user {
+            Some(User::Local(_)) => return HuntTarget::Local,
+            Some(User::Remote(user)) => {
+                return match self.find_server_by_sid(&user.server).await {
+                    Some(server) => self.hunt_route(&server.name, server.sid.as_deref()).await,
+                    None => HuntTarget::NotFound,
+                };
+            }
+            None => {}
+        }
+
+        let server = match self.find_server_by_sid(target).await {
+            Some(server) => Some(server),
+            None => self.find_server_by_name(target).await,
+        };
+        if let Some(server) = server {
+            return self.hunt_route(&server.name, server.sid.as_deref()).await;
+        }
+
+        // Masks prefer this server, then the nearest match
+        let mask = target.to_lowercase();
+        if self.mask_match(&self.config.server.name.to_lowercase(), &mask) {
+            return HuntTarget::Local;
+        }
+        for server in self.get_remote_servers().await {
+            if self.mask_match(&server.name.to_lowercase(), &mask) {
+                return self.hunt_route(&server.name, server.sid.as_deref()).await;
+            }
+        }
+        HuntTarget::NotFound
+    }
+
+    async fn hunt_route(&self, name: &str, sid: Option<&str>) -> HuntTarget {
+        match self.route_to_server(name).await {
+            Some(link) => HuntTarget::Remote(link, sid.unwrap_or(name).to_string()),
+            None => HuntTarget::NotFound,
+        }
+    }
+
+    // Sends numerics from this server to a user on another server
+    pub(crate) async fn send_numerics_to_remote(&self, user: &RemoteUser, numerics: Vec<Numeric>) -> IrcResult<()> {
+        for (numeric, params) in numerics {
+            let mut message_params = vec![user.uid.clone()];
+            message_params.extend(params);
+            let message = TS6Message::with_source(
+                self.config.server.sid.clone(),
+                format!("{:03}", numeric),
+                message_params,
+            );
+            self.send_to_remote_user(user, &message).await?;
+        }
+        Ok(())
+    }
+
+    // The reply to a hunted command that has reached this server
+    async fn hunted_reply(&self, command: &str, params: &[String]) -> Vec<Numeric> {
+        match command {
+            "VERSION" => self.version_reply(),
+            "ADMIN" => self.admin_reply(),
+            "INFO" => self.info_reply(),
+            "MOTD" => self.motd_reply(),
+            "TIME" => self.time_reply(),
+            "LUSERS" => self.lusers_reply(None).await,
+            _ => {
+                // WHOIS server :nick
+                let nickname = params.last().map(String::as_str).unwrap_or_default();
+                let info = self.find_client_info(nickname).await;
+                self.whois_reply(nickname, info.as_ref())
+            }
+        }
+    }
+
+    pub(crate) async fn handle_server_hunted(&self, link: &Arc<Mutex<ServerLink>>, mut msg: TS6Message, index: usize) -> IrcResult<()> {
+        // :uid COMMAND [params...] hunted [params...]
+        let source = msg.source.clone().unwrap_or_default();
+        let Some(user) = self.find_remote_user_by_uid(&source).await else {
+            debug!("{} from unknown user {}", msg.command, source);
+            return Ok(());
+        };
+        let Some(target) = msg.params.get(index).cloned() else {
+            return Err(IrcError::Protocol(format!("Invalid {} parameters", msg.command)));
+        };
+
+        match self.hunt_server(&target).await {
+            HuntTarget::Local if msg.command == "CONNECT" => self.handle_remote_connect(&user, &msg.params).await,
+            HuntTarget::Local => {
+                let reply = self.hunted_reply(&msg.command, &msg.params).await;
+                self.send_numerics_to_remote(&user, reply).await
+            }
+            HuntTarget::Remote(route, server) => {
+                if Arc::ptr_eq(&route, link) {
+                    debug!("Dropping {} for {} sent from its own direction", msg.command, target);
+                    return Ok(());
+                }
+                msg.params[index] = server;
+                route.lock().await.send_message(&msg).await
+            }
+            HuntTarget::NotFound => {
+                // ERR_NOSUCHSERVER (402)
+                let reply = vec![(402, vec![target, "No such server".to_string()])];
+                self.send_numerics_to_remote(&user, reply).await
+            }
+        }
+    }
+
+    pub(crate) async fn handle_server_numeric(&self, link: &Arc<Mutex<ServerLink>>, mut msg: TS6Message) -> IrcResult<()> {
+        // :sid NNN uid [params...]
+        let Some(target) = msg.params.first().cloned() else {
+            return Err(IrcError::Protocol(format!("Invalid numeric {}", msg.command)));
+        };
+        let Some(id) = self.find_client_by_uid(&target).await else {
+            debug!("Numeric {} for unknown user {}", msg.command, target);
+            return Ok(());
+        };
+
+        match self.get_user(id).await {
+            Some(User::Local(client)) => {
+                let source = msg.source.clone();
+                let server = self.source_server_name(link, source.as_deref()).await
+                    .or(source)
+                    .unwrap_or_default();
+                let client = client.lock().await;
+                msg.params[0] = client.get_nickname().cloned().unwrap_or_default();
+                let local_msg = TS6Message::with_source(server, msg.command.clone(), msg.params.clone());
+                client.send_message(&local_msg).await
+            }
+            Some(User::Remote(user)) => {
+                let (server, _) = self.get_server_info(&user.server).await;
+                match self.route_to_server(&server).await {
+                    Some(route) if !Arc::ptr_eq(&route, link) => route.lock().await.send_message(&msg).await,
+                    _ => Ok(()),
+                }
+            }
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use std::collections::HashMap;
+
+use chrono::Local;
+
+use crate::client::Client;
+use crate::link::BurstStats;
+use crate::server::{RemoteServer, Server, WhoisInfo};
+
+// Width of the server column in MAP, padded with dashes
+const MAP_WIDTH: usize = 50;
+
+/// A numeric reply and its parameters, before the recipient's nickname is added.
+pub(crate) type Numeric = (u16, Vec<String>);
+
+fn numeric(number: u16, params: &[&str]) -> Numeric {
+    (number, params.iter().map(|param| param.to_string()).collect())
+}
+
+// Replies to informational commands, shared by local users and requests hunted to us over a link
+impl Server {
+    pub(crate) fn version_reply(&self) -> Vec<Numeric> {
+        // RPL_VERSION (351)
+        vec![numeric(351, &["ircd-rs-0.1.0", &self.config.server.name, "Available on GitHub"])]
+    }
+
+    pub(crate) fn admin_reply(&self) -> Vec<Numeric> {
+        vec![
+            // RPL_ADMINME (256)
+            numeric(256, &[&self.config.server.name, "Administrative info"]),
+            // RPL_ADMINLOC1 (257)
+            numeric(257, &["Location: Earth"]),
+            // RPL_ADMINLOC2 (258)
+            numeric(258, &["Server Info"]),
+            // RPL_ADMINEMAIL (259)
+            numeric(259, &["admin@example.com"]),
+        ]
+    }
+
+    pub(crate) fn info_reply(&self) -> Vec<Numeric> {
+        vec![
+            // RPL_INFO (371)
+            numeric(371, &["IRCd-rs Server"]),
+            numeric(371, &["Written in Rust"]),
+            // RPL_ENDOFINFO (374)
+            numeric(374, &["End of /INFO list"]),
+        ]
+    }
+
+    pub(crate) fn motd_reply(&self) -> Vec<Numeric> {
+        vec![
+            // RPL_MOTDSTART (375)
+            numeric(375, &["- Message of the day"]),
+            // RPL_MOTD (372)
+            numeric(372, &["- Welcome to IRCd-rs!"]),
+            // RPL_ENDOFMOTD (376)
+            numeric(376, &["End of /MOTD command."]),
+        ]
+    }
+
+    pub(crate) fn time_reply(&self) -> Vec<Numeric> {
+        // RPL_TIME (391)
+        let time = Local::now().format("%A %B %-d %Y -- %H:%M:%S %:z").to_string();
+        vec![numeric(391, &[&self.config.server.name, &time])]
+    }
+
+    // The caller is the asking client when it is local, as its lock is already held
+    pub(crate) async fn lusers_reply(&self, caller: Option<&Client>) -> Vec<Numeric> {
+        let stats = self.get_stats(caller).await;
+        let servers = if stats.server_count == 1 { "server" } else { "servers" };
+
+        // RPL_LUSERCLIENT (251)
+        let mut reply = vec![numeric(251, &[&format!(
+            "There are {} users and {} invisible on {} {}",
+            stats.visible_users,
+            stats.invisible_users,
+            stats.server_count,
+            servers
+        )])];
+        // RPL_LUSEROP (252)
+        reply.push(numeric(252, &[&stats.oper_count.to_string(), "operator(s) online"]));
+        if stats.unknown_connections > 0 {
+            // RPL_LUSERUNKNOWN (253)
+            reply.push(numeric(253, &[&stats.unknown_connections.to_string(), "unknown connection(s)"]));
+        }
+        // RPL_LUSERCHANNELS (254)
+        reply.push(numeric(254, &[&stats.channel_count.to_string(), "channels formed"]));
+        // RPL_LUSERME (255)
+        reply.push(numeric(255, &[&format!(
+            "I have {} clients and {} servers",
+            stats.local_users,
+            stats.local_servers
+        )]));
+        // RPL_LOCALUSERS (265)
+        let (local, max_local) = (stats.local_users.to_string(), stats.max_local_users.to_string());
+        reply.push(numeric(265, &[&local, &max_local, &format!("Current local users {}, max {}", local, max_local)]));
+        // RPL_GLOBALUSERS (266)
+        let (global, max_global) = (stats.global_users.to_string(), stats.max_global_users.to_string());
+        reply.push(numeric(266, &[&global, &max_global, &format!("Current global users {}, max {}", global, max_global)]));
+        reply
+    }
+
+    pub(crate) async fn stats_reply(&self, letter: char) -> Vec<Numeric> {
+        let mut reply = Vec::new();
+        // Temporary lines are listed in lowercase
+        let kind = |upper: &str, duration: i64| if duration > 0 { upper.to_lowercase() } else { upper.to_string() };
+        match letter.to_ascii_lowercase() {
+            'k' => for kline in self.get_klines().await {
+                let (user, host) = kline.mask.split_once('@').unwrap_or(("*", &kline.mask));
+                // RPL_STATSKLINE (216)
+                reply.push(numeric(216, &[&kind("K", kline.duration), host, "*", user, &kline.reason]));
+            },
+            'd' => for dline in self.get_dlines().await {
+                // RPL_STATSDLINE (225)
+                reply.push(numeric(225, &[&kind("D", dline.duration), &dline.ip.to_string(), &dline.reason]));
+            },
+            'g' => for gline in self.get_glines().await {
+                let (user, host) = gline.mask.split_once('@').unwrap_or(("*", &gline.mask));
+                // RPL_STATSGLINE (223)
+                reply.push(numeric(223, &[&kind("G", gline.duration), host, "*", user, &gline.reason]));
+            },
+            'l' => for (name, status) in self.get_link_statuses().await {
+                let last_error = status.last_error.as_deref().unwrap_or("-");
+                // A connected link shows its peer's address and what the peer sent in its burst
+                let (link, burst) = match self.get_linked_server(&name).await {
+                    Some(link) => {
+                        let link = link.lock().await;
+                        (format!("{}[{}]", name, link.addr()), link.burst_stats().clone())
+                    }
+                    None => (name, BurstStats::default()),
+                };
+                let burst_time = burst.duration.map_or_else(|| "-".to_string(), |duration| duration.as_millis().to_string());
+                // RPL_STATSLINKINFO (211): link, state, failures since it was last up,
+                // burst users, channels, bytes and milliseconds, last error
+                reply.push(numeric(211, &[
+                    &link,
+                    status.state.name(),
+                    &status.failures.to_string(),
+                    &burst.users.to_string(),
+                    &burst.channels.to_string(),
+                    &burst.bytes.to_string(),
+                    &burst_time,
+                    last_error,
+                ]));
+            },
+            _ => {}
+        }
+        // RPL_ENDOFSTATS (219)
+        reply.push(numeric(219, &[&letter.to_string(), "End of /STATS report"]));
+        reply
+    }
+
+    pub(crate) fn whois_reply(&self, target: &str, info: Option<&WhoisInfo>) -> Vec<Numeric> {
+        let Some(info) = info else {
+            // ERR_NOSUCHNICK (401)
+            return vec![numeric(401, &[target, "No such nick/channel"])];
+        };
+
+        // RPL_WHOISUSER (311)
+        let mut reply = vec![numeric(311, &[target, &info.username, &info.hostname, "*", &info.realname])];
+        // RPL_WHOISSERVER (312)
+        reply.push(numeric(312, &[target, &info.server, &info.server_info]));
+        // RPL_WHOISIDLE (317), known only where the user is connected
+        if let Some((idle, signon)) = info.idle {
+            reply.push(numeric(317, &[target, &idle.to_string(), &signon.to_string(), "seconds idle, signon time"]));
+        }
+        // RPL_ENDOFWHOIS (318)
+        reply.push(numeric(318, &[target, "End of /WHOIS list"]));
+        reply
+    }
+
+    pub(crate) async fn links_reply(&self, mask: &str, oper: bool) -> Vec<Numeric> {
+        let ours = &self.config.server;
+        let flatten = !oper && self.config.network.flatten_links;
+        let mask_lower = mask.to_lowercase();
+
+        let mut reply = Vec::new();
+        if self.mask_match(&ours.name.to_lowercase(), &mask_lower) {
+            // RPL_LINKS (364)
+            reply.push(numeric(364, &[&ours.name, &ours.name, &format!("0 {}", ours.description)]));
+        }
+        for server in self.get_remote_servers().await {
+            if !oper && is_hidden(&server) {
+                continue;
+            }
+            if !self.mask_match(&server.name.to_lowercase(), &mask_lower) {
+                continue;
+            }
+            let (uplink, hopcount) = if flatten {
+                (ours.name.as_str(), 1)
+            } else {
+                (server.uplink.as_str(), server.hopcount)
+            };
+            reply.push(numeric(364, &[&server.name, uplink, &format!("{} {}", hopcount, server.description)]));
+        }
+        // RPL_ENDOFLINKS (365)
+        reply.push(numeric(365, &[mask, "End of /LINKS list"]));
+        reply
+    }
+
+    // Local users are counted as LUSERS does; the asking client's lock is already held
+    pub(crate) async fn map_reply(&self, caller: &Client) -> Vec<Numeric> {
+        let local_users = self.get_stats(Some(caller)).await.local_users;
+        let mut users: HashMap<String, usize> = HashMap::new();
+        for user in self.get_remote_users().await {
+            let (server, _) = self.get_server_info(&user.server).await;
+            *users.entry(server.to_lowercase()).or_default() += 1;
+        }
+        users.insert(self.config.server.name.to_lowercase(), local_users);
+
+        let map = ServerMap {
+            servers: self.get_remote_servers().await,
+            total: users.values().sum(),
+            users,
+        };
+        let mut reply = Vec::new();
+        map.add_server(&mut reply, &self.config.server.name, Some(&self.config.server.sid), "", "");
+        // RPL_MAPEND (017)
+        reply.push(numeric(17, &["End of /MAP"]));
+        reply
+    }
+}
+
+// Servers introduced with "(H)" are hidden from non-opers
+fn is_hidden(server: &RemoteServer) -> bool {
+    server.description.starts_with("(H)")
+}
+
+struct ServerMap {
+    servers: Vec<RemoteServer>,
+    users: HashMap<String, usize>, // User count by lowercase server name
+    total: usize,
+}
+
+impl ServerMap {
+    // Adds a server's line to the reply, then the servers linked behind it
+    fn add_server(&self, reply: &mut Vec<Numeric>, name: &str, sid: Option<&str>, branch: &str, indent: &str) {
+        let users = self.users.get(&name.to_lowercase()).copied().unwrap_or(0);
+        let percent = if self.total == 0 { 0.0 } else { users as f64 * 100.0 / self.total as f64 };
+        let server = format!("{}{}[{}] ", branch, name, sid.unwrap_or("---"));
+        // RPL_MAP (015)
+        reply.push(numeric(15, &[&format!("{:-<width$} | Users: {:>5} ({:>5.1}%)", server, users, percent, width = MAP_WIDTH)]));
+
+        let leaves: Vec<_> = self.servers.iter()
+            .filter(|server| server.uplink.eq_ignore_ascii_case(name))
+            .collect();
+        for (i, leaf) in leaves.iter().enumerate() {
+            let last = i + 1 == leaves.len();
+            let branch = format!("{}{}", indent, if last { "`- " } else { "|- " });
+            let indent = format!("{}{}", indent, if last { "   " } else { "|  " });
+            self.add_server(reply, &leaf.name, leaf.sid.as_deref(), &branch, &indent);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
+use crate::client::Client;
+use crate::error::{IrcError, IrcResult};
+use crate::link::ServerLink;
+use crate::server::{ClientId, Server, User};
+use crate::ts6::TS6Message;
+
+// The reason at the end of a KILL path, "path (reason)"
+pub(crate) fn kill_reason(path: &str) -> &str {
+    let reason = path.split_once(' ').map_or(path, |(_, reason)| reason);
+    reason.strip_prefix('(').and_then(|reason| reason.strip_suffix(')')).unwrap_or(reason)
+}
+
+impl Server {
+    // Removes a user from the network on behalf of a killer, given by prefix, and returns the user's prefix.
+    // The KILL goes to every server but the one it came from, as the given source with "path (reason)".
+    // The caller is the killing client when it is local, as its lock is already held.
+    pub(crate) async fn kill_user(
+        &self,
+        id: ClientId,
+        killer: &str,
+        source: &str,
+        path: &str,
+        skip: Option<&Arc<Mutex<ServerLink>>>,
+        caller: Option<&mut Client>,
+    ) -> Option<String> {
+        let reason = kill_reason(path);
+        let killer_name = killer.split('!').next().unwrap_or(killer);
+        let quit_reason = format!("Killed ({} ({}))", killer_name, reason);
+
+        let prefix = match self.get_user(id).await? {
+            User::Local(client) => match caller {
+                Some(caller) if caller.id() == id => self.kill_local(caller, killer, source, path, skip, &quit_reason).await,
+                _ => self.kill_local(&mut *client.lock().await, killer, source, path, skip, &quit_reason).await,
+            },
+            User::Remote(user) => {
+                let kill = TS6Message::with_source(source.to_string(), "KILL".to_string(), vec![user.uid.clone(), path.to_string()]);
+                self.send_to_servers(&kill, skip).await;
+                self.remove_remote_user(&user, &quit_reason).await;
+                user.get_prefix()
+            }
+        };
+        info!("{} killed by {}: {}", prefix, killer, reason);
+        Some(prefix)
+    }
+
+    async fn kill_local(
+        &self,
+        client: &mut Client,
+        killer: &str,
+        source: &str,
+        path: &str,
+        skip: Option<&Arc<Mutex<ServerLink>>>,
+        quit_reason: &str,
+    ) -> String {
+        let kill = TS6Message::with_source(source.to_string(), "KILL".to_string(), vec![client.uid().to_string(), path.to_string()]);
+        self.send_to_servers(&kill, skip).await;
+
+        let nickname = client.get_nickname().cloned().unwrap_or_default();
+        let notice = TS6Message::with_source(killer.to_string(), "KILL".to_string(), vec![nickname.clone(), kill_reason(path).to_string()]);
+        // Written directly, like the ERROR that follows, so the two arrive in order
+        client.write_raw(notice.to_string().as_bytes()).await.ok();
+        self.unregister_nickname(&nickname).await;
+        client.disconnect_killed(quit_reason).await;
+        client.get_prefix()
+    }
+
+    pub(crate) async fn handle_server_kill(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // :source KILL uid :path (reason)
+        if msg.params.len() < 2 {
+            return Err(IrcError::Protocol("Invalid KILL parameters".into()));
+        }
+
+        let target = &msg.params[0];
+        let Some(id) = self.find_client_by_uid(target).await else {
+            debug!("KILL for unknown user {}", target);
+            return Ok(());
+        };
+        let source = msg.source.clone().unwrap_or_default();
+        let killer = self.get_source_prefix(&source).await.unwrap_or_else(|| source.clone());
+        let path = &msg.params[1];
+
+        if let Some(prefix) = self.kill_user(id, &killer, &source, path, Some(link), None).await {
+            let killer_name = killer.split('!').next().unwrap_or(&killer);
+            self.send_server_notice(&self.config.server.name, &format!(
+                "Received KILL message for {}. From {} Path: {}", prefix, killer_name, path
+            )).await;
+        }
+        Ok(())
+    }
+}
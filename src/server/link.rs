@@ -1,11 +1,38 @@
-use tracing::info;
+use std::sync::Arc;
 
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::config::ServerLinkConfig;
 use crate::error::{IrcError, IrcResult};
-use crate::server::Server;
-use crate::ts6::TS6Message;
+use crate::link::ServerLink;
+use crate::server::{LinkState, RemoteServer, Server};
+use crate::ts6::{euid_to_uid, TS6Message};
 
 impl Server {
-    pub(crate) async fn handle_server_intro(&self, msg: TS6Message) -> IrcResult<()> {
+    pub fn find_link_config(&self, name: &str) -> Option<&ServerLinkConfig> {
+        self.config.links.iter()
+            .find(|link| link.name.eq_ignore_ascii_case(name))
+    }
+
+    pub async fn get_linked_server(&self, name: &str) -> Option<Arc<Mutex<ServerLink>>> {
+        let linked_servers = self.linked_servers.read().await;
+        linked_servers.get(name).cloned()
+    }
+
+    // Name and description of a server by SID, falling back to the SID for servers we don't know
+    pub(crate) async fn get_server_info(&self, sid: &str) -> (String, String) {
+        if sid == self.config.server.sid {
+            return (self.config.server.name.clone(), self.config.server.description.clone());
+        }
+
+        match self.find_server_by_sid(sid).await {
+            Some(server) => (server.name, server.description),
+            None => (sid.to_string(), String::new()),
+        }
+    }
+
+    pub(crate) async fn handle_server_intro(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
         // SERVER name hopcount description
         if msg.params.len() < 3 {
             return Err(IrcError::Protocol("Invalid SERVER parameters".into()));
@@ -14,42 +41,159 @@ impl Server {
         let name = &msg.params[0];
         let description = &msg.params[2];
 
-        // TODO: Add server to network topology
-        info!("Server {} introduced: {}", name, description);
-        Ok(())
+        if !link.lock().await.is_registered() {
+            return self.register_server_link(link, name, description).await;
+        }
+
+        // From a registered link SERVER introduces a TS5 server behind it
+        self.handle_remote_server(link, msg).await
     }
 
-    pub(crate) async fn handle_server_join(&self, msg: TS6Message) -> IrcResult<()> {
-        // SJOIN timestamp channel modes members
-        if msg.params.len() < 4 {
-            return Err(IrcError::Protocol("Invalid SJOIN parameters".into()));
+    async fn register_server_link(&self, link_arc: &Arc<Mutex<ServerLink>>, name: &str, description: &str) -> IrcResult<()> {
+        let mut link = link_arc.lock().await;
+
+        let (password, sid) = match link.remote_pass() {
+            Some((password, sid)) => (password.to_string(), sid.to_string()),
+            None => return Err(link.close("SERVER received before PASS").await),
+        };
+        if !link.has_remote_capabilities() {
+            return Err(link.close("SERVER received before CAPAB").await);
         }
 
-        // TODO: Process channel join with TS
+        // Verify the peer against its link block
+        let link_config = match self.find_link_config(name) {
+            Some(link_config) => link_config,
+            None => {
+                warn!("Unauthorised server connection attempt from {}", name);
+                return Err(link.close("No link configuration for server").await);
+            }
+        };
+        if !link.is_incoming() && !link.name().eq_ignore_ascii_case(name) {
+            return Err(link.close("Server name mismatch").await);
+        }
+        if link.is_incoming() && self.linked_servers.read().await.contains_key(&link_config.name) {
+            return Err(link.close("Server already linked").await);
+        }
+        if password != link_config.password {
+            return Err(link.close("Invalid password").await);
+        }
+        if sid != link_config.sid {
+            return Err(link.close("SID mismatch").await);
+        }
+        if self.find_server_by_name(name).await.is_some() || self.find_server_by_sid(&sid).await.is_some() {
+            return Err(link.close("Server already exists").await);
+        }
+
+        link.complete_registration(
+            link_config.name.clone(),
+            sid,
+            description.to_string(),
+            link_config.password.clone(),
+        );
+        info!("Server link {} ({}) registered", link.name(), link.sid());
+        self.set_link_state(link.name(), LinkState::Bursting).await;
+
+        self.add_remote_server(RemoteServer {
+            name: link.name().to_string(),
+            sid: Some(link.sid().to_string()),
+            description: link.description().to_string(),
+            hopcount: 1,
+            uplink: self.config.server.name.clone(),
+        }).await;
+        let sid_msg = TS6Message::with_source(
+            self.config.server.sid.clone(),
+            "SID".to_string(),
+            vec![link.name().to_string(), "2".to_string(), link.sid().to_string(), link.description().to_string()],
+        );
+
+        if link.is_incoming() {
+            // The listener answers with its own credentials before SVINFO and the burst
+            self.linked_servers.write().await.insert(link.name().to_string(), Arc::clone(link_arc));
+            link.send_credentials().await?;
+        }
+
+        // Either side follows a valid SERVER with SVINFO and the burst
+        link.send_svinfo().await?;
+        drop(link);
+        ServerLink::send_burst(link_arc).await?;
+
+        // Introduce the new server to the rest of the network
+        self.send_to_servers(&sid_msg, Some(link_arc)).await;
         Ok(())
     }
 
-    pub(crate) async fn handle_server_sid(&self, msg: TS6Message) -> IrcResult<()> {
-        // SID name hopcount sid description
-        if msg.params.len() < 4 {
-            return Err(IrcError::Protocol("Invalid SID parameters".into()));
+    // Sends a message to every registered link except the one it came from
+    pub(crate) async fn send_to_servers(&self, message: &TS6Message, skip: Option<&Arc<Mutex<ServerLink>>>) {
+        let links: Vec<_> = self.linked_servers.read().await.values().cloned().collect();
+        for link in links {
+            if skip.is_some_and(|skip| Arc::ptr_eq(skip, &link)) {
+                continue;
+            }
+            let link = link.lock().await;
+            if !link.is_registered() {
+                continue;
+            }
+            if let Err(e) = link.send_message(message).await {
+                warn!("Failed to send to server {}: {}", link.name(), e);
+            }
         }
+    }
 
-        // TODO: Process server introduction
-        Ok(())
+    // Introduces a user to every server, as UID plus ENCAP REALHOST/LOGIN to peers without EUID
+    pub(crate) async fn introduce_user(&self, euid: &TS6Message) {
+        let links: Vec<_> = self.linked_servers.read().await.values().cloned().collect();
+        for link in links {
+            let link = link.lock().await;
+            if !link.is_registered() {
+                continue;
+            }
+            let messages = if link.has_capability("EUID") { vec![euid.clone()] } else { euid_to_uid(euid) };
+            for message in &messages {
+                if let Err(e) = link.send_message(message).await {
+                    warn!("Failed to send to server {}: {}", link.name(), e);
+                    break;
+                }
+            }
+        }
     }
 
-    pub(crate) async fn handle_server_quit(&self, msg: TS6Message) -> IrcResult<()> {
-        // SQUIT server reason
-        if msg.params.len() < 2 {
-            return Err(IrcError::Protocol("Invalid SQUIT parameters".into()));
+    pub(crate) async fn unregister_server_link(&self, link: &Arc<Mutex<ServerLink>>, reason: &str) {
+        let (name, sid, registered) = {
+            let link = link.lock().await;
+            (link.name().to_string(), link.sid().to_string(), link.is_registered())
+        };
+
+        // Only forget the entry if it still refers to this connection
+        {
+            let mut linked_servers = self.linked_servers.write().await;
+            if !linked_servers.get(&name).is_some_and(|existing| Arc::ptr_eq(existing, link)) {
+                return;
+            }
+            linked_servers.remove(&name);
         }
+        info!("Server link {} removed: {}", name, reason);
+        self.set_link_failed(&name, reason).await;
 
-        let server = &msg.params[0];
-        let reason = &msg.params[1];
+        // Everything behind the link split from the network
+        if registered {
+            self.split_server(&name, reason).await;
+            let squit = TS6Message::with_source(
+                self.config.server.sid.clone(),
+                "SQUIT".to_string(),
+                vec![sid, reason.to_string()],
+            );
+            self.send_to_servers(&squit, None).await;
+        }
+    }
 
-        info!("Server {} quit: {}", server, reason);
-        // TODO: Remove server and its users from network
-        Ok(())
+    pub(crate) async fn handle_server_error(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // ERROR message
+        let reason = msg.params.first()
+            .cloned()
+            .unwrap_or_else(|| "No reason given".to_string());
+
+        let link = link.lock().await;
+        warn!("Server link {} sent ERROR: {}", link.name(), reason);
+        Err(IrcError::ServerLink(reason))
     }
 }
\ No newline at end of file
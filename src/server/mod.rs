@@ -1,10 +1,13 @@
 use std::collections::HashMap;
 use std::sync::Arc;
-use std::sync::mpsc::{self, Receiver, Sender};
 use std::time::Duration;
 
+use std::net::SocketAddr;
+
 use chrono::{DateTime, Utc};
 use regex;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
 use tokio::net::TcpListener;
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
@@ -12,14 +15,26 @@ use tokio::sync::RwLock;
 use tracing::{debug, error, info, warn};
 
 use crate::channel::Channel;
+use crate::cidr::CidrTable;
 use crate::client::Client;
-use crate::config::{DLine, GLine, ILine, KLine, ServerConfig, ServerLinkConfig};
+use crate::config::{ALine, DLine, ELine, GLine, ILine, KLine, OLine, Resv, ServerConfig, ULine};
 use crate::database::Database;
 use crate::error::{IrcError, IrcResult};
 use crate::link::ServerLink;
-use crate::server::client::{ServerMessage, WhoisInfo};
+pub use crate::server::client::{User, WhoisInfo};
+pub use crate::server::remote::RemoteUser;
+pub use crate::server::autoconnect::{LinkState, LinkStatus};
+pub use crate::server::topology::RemoteServer;
+pub(crate) use crate::server::expiry::{ExpiryQueue, TimedBan};
+pub(crate) use crate::server::hunt::HuntTarget;
+pub(crate) use crate::server::info::Numeric;
+pub use crate::server::oper::hash_password;
+pub(crate) use crate::server::oper::verify_password;
+pub use crate::server::privilege::Privilege;
+use crate::ts6::parser::parse_message;
 use crate::ts6::TS6Message;
 
+mod autoconnect;
 mod link;
 #[cfg(test)]
 mod tests;
@@ -28,9 +43,23 @@ mod channel;
 mod xline;
 mod registration;
 mod mask;
+mod netsplit;
 mod client;
+mod encap;
+mod expiry;
+mod hunt;
+mod info;
+mod kill;
+mod oper;
+mod privilege;
+mod collision;
 mod pass;
+mod remote;
+mod routing;
+mod sjoin;
 mod stats;
+mod topic;
+mod topology;
 
 pub struct Server {
     pub(crate) config: Arc<ServerConfig>,
@@ -41,14 +70,29 @@ pub struct Server {
     nicknames: Arc<RwLock<HashMap<String, ClientId>>>,
     registration_timeouts: Arc<RwLock<HashMap<ClientId, tokio::time::Instant>>>,
     nickname_map: Arc<RwLock<HashMap<String, ClientId>>>,
-    tx: mpsc::Sender<ServerMessage>,
+    uid_map: Arc<RwLock<HashMap<String, ClientId>>>,
+    remote_users: Arc<RwLock<HashMap<ClientId, RemoteUser>>>,
     linked_servers: Arc<RwLock<HashMap<String, Arc<Mutex<ServerLink>>>>>,
+    servers: Arc<RwLock<HashMap<String, RemoteServer>>>,
+    link_states: Arc<RwLock<HashMap<String, LinkStatus>>>,
+    resvs: Arc<RwLock<Vec<Resv>>>,
+    klines: Arc<RwLock<Vec<KLine>>>,
+    glines: Arc<RwLock<Vec<GLine>>>,
+    dlines: Arc<RwLock<CidrTable<DLine>>>,  // D-lines by network
+    elines: Arc<RwLock<CidrTable<ELine>>>,  // Ban exemptions by network
+    ilines: Arc<RwLock<Vec<ILine>>>,
+    olines: Arc<RwLock<Vec<OLine>>>,
+    ulines: Arc<RwLock<Vec<ULine>>>,
+    alines: Arc<RwLock<Vec<ALine>>>,
+    expiry: Arc<Mutex<ExpiryQueue>>,
+    max_users: Arc<RwLock<(usize, usize)>>, // Highest local and global user counts seen
 }
 
 type ClientId = u32;
 
 impl Server {
     const REGISTRATION_TIMEOUT: Duration = Duration::from_secs(60);
+    const MAX_TS_DELTA: i64 = 300; // Maximum clock difference accepted from a linked server
 
     pub async fn new(config: ServerConfig) -> Result<Self, Box<dyn std::error::Error>> {
         let database = if let Some(db_config) = &config.database {
@@ -61,8 +105,21 @@ impl Server {
             None
         };
 
-        let (tx, rx) = mpsc::channel();
-
+        let resvs = config.access.resvs.clone();
+        let klines = config.access.klines.clone();
+        let glines = config.access.glines.clone();
+        let ilines = config.access.ilines.clone();
+        let olines = config.access.olines.clone();
+        let ulines = config.access.ulines.clone();
+        let alines = config.access.alines.clone();
+        let mut dlines = CidrTable::new();
+        for dline in &config.access.dlines {
+            dlines.insert(dline.ip, dline.clone());
+        }
+        let mut elines = CidrTable::new();
+        for eline in &config.access.elines {
+            elines.insert(eline.ip, eline.clone());
+        }
         let server = Self {
             config: Arc::new(config),
             clients: Arc::new(RwLock::new(Vec::new())),
@@ -72,20 +129,30 @@ impl Server {
             nicknames: Arc::new(RwLock::new(HashMap::new())),
             registration_timeouts: Arc::new(RwLock::new(HashMap::new())),
             nickname_map: Arc::new(RwLock::new(HashMap::new())),
-            tx,
+            uid_map: Arc::new(RwLock::new(HashMap::new())),
+            remote_users: Arc::new(RwLock::new(HashMap::new())),
             linked_servers: Arc::new(RwLock::new(HashMap::new())),
+            servers: Arc::new(RwLock::new(HashMap::new())),
+            link_states: Arc::new(RwLock::new(HashMap::new())),
+            resvs: Arc::new(RwLock::new(resvs)),
+            klines: Arc::new(RwLock::new(klines)),
+            glines: Arc::new(RwLock::new(glines)),
+            dlines: Arc::new(RwLock::new(dlines)),
+            elines: Arc::new(RwLock::new(elines)),
+            ilines: Arc::new(RwLock::new(ilines)),
+            olines: Arc::new(RwLock::new(olines)),
+            ulines: Arc::new(RwLock::new(ulines)),
+            alines: Arc::new(RwLock::new(alines)),
+            expiry: Arc::new(Mutex::new(ExpiryQueue::default())),
+            max_users: Arc::new(RwLock::new((0, 0))),
         };
 
         // Load persisted lines if database is configured
         if let Some(db) = &server.database {
             server.load_persisted_lines(db).await?;
+            *server.max_users.write().await = db.get_max_users().await;
         }
 
-        // Spawn the server task in a new thread since we're using std::sync::mpsc
-        std::thread::spawn(move || {
-            server_task(rx);
-        });
-
         Ok(server)
     }
 
@@ -101,6 +168,8 @@ impl Server {
         })?;
 
         info!("Server listening on {}", addr);
+        Arc::new(self.clone()).start_autoconnect();
+        Arc::new(self.clone()).start_expiry();
 
         loop {
             match listener.accept().await {
@@ -121,69 +190,87 @@ impl Server {
         }
     }
 
-    pub async fn broadcast_global(&self, message: &str) -> IrcResult<()> {
-        debug!("Broadcasting global message: {}", message);
-        // Implementation
-        Ok(())
-    }
-
-    pub async fn connect_to_server(&self, config: &ServerLinkConfig) -> IrcResult<()> {
-        debug!("Connecting to server {} at {}", config.name, config.address);
-
-        // Connect to remote server
-        let stream = TcpStream::connect(&config.address).await.map_err(|e| {
-            error!("Failed to connect to {}: {}", config.address, e);
-            IrcError::Io(e)
-        })?;
-
-        // Create server link with the stream
-        let server_link = ServerLink::new(
-            stream,
-            config.name.clone(),
-            config.sid.clone(),
-            config.description.clone(),
-            config.password.clone(),
-        );
-
-        // Store server link
-        let server_link = Arc::new(Mutex::new(server_link));
-        self.linked_servers.write().await.insert(config.name.clone(), Arc::clone(&server_link));
-
-        // Spawn server link handler - no need to pass stream since it's stored in ServerLink
-        let server_link_clone = Arc::clone(&server_link);
-        tokio::spawn(async move {
-            if let Err(e) = server_link_clone.lock().await.handle_connection().await {
-                error!("Server link error: {}", e);
-            }
-        });
-
-        Ok(())
-    }
-
     // Handle incoming server messages
-    pub(crate) async fn handle_server_message(&self, msg: TS6Message) -> IrcResult<()> {
+    pub(crate) async fn handle_server_message(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // Only the handshake is allowed until the link has registered
+        let registered = link.lock().await.is_registered();
+        if !registered && !matches!(msg.command.as_str(), "PASS" | "CAPAB" | "SERVER" | "PING" | "ERROR") {
+            return Err(link.lock().await.close("Unregistered server").await);
+        }
+
         match msg.command.as_str() {
-            "PASS" => self.handle_server_pass(msg).await,
-            "CAPAB" => self.handle_server_capab(msg).await,
-            "SERVER" => self.handle_server_intro(msg).await,
-            "SJOIN" => self.handle_server_join(msg).await,
-            "SID" => self.handle_server_sid(msg).await,
-            "PING" => self.handle_server_ping(msg).await,
-            "PONG" => self.handle_server_pong(msg).await,
-            "SQUIT" => self.handle_server_quit(msg).await,
-            _ => Ok(()),
+            "PASS" => self.handle_server_pass(link, msg).await,
+            "CAPAB" => self.handle_server_capab(link, msg).await,
+            "SERVER" => self.handle_server_intro(link, msg).await,
+            "SVINFO" => self.handle_server_svinfo(link, msg).await,
+            "ERROR" => self.handle_server_error(link, msg).await,
+            "SJOIN" => self.handle_server_join(link, msg).await,
+            "BMASK" => self.handle_server_bmask(link, msg).await,
+            "TB" => self.handle_server_tb(link, msg).await,
+            "ETB" => self.handle_server_etb(link, msg).await,
+            "UID" | "EUID" => self.handle_server_uid(link, msg).await,
+            "NICK" => self.handle_remote_nick(link, msg).await,
+            "QUIT" => self.handle_remote_quit(link, msg).await,
+            "MODE" => self.handle_server_mode(link, msg).await,
+            "KILL" => self.handle_server_kill(link, msg).await,
+            "PRIVMSG" | "NOTICE" => self.handle_server_privmsg(link, msg).await,
+            "ENCAP" => self.handle_server_encap(link, msg).await,
+            "SID" => self.handle_server_sid(link, msg).await,
+            "PING" => self.handle_server_ping(link, msg).await,
+            "PONG" => self.handle_server_pong(link, msg).await,
+            "SQUIT" => self.handle_server_squit(link, msg).await,
+            "SAVE" => self.handle_server_save(link, msg).await,
+            "VERSION" | "ADMIN" | "INFO" | "MOTD" | "TIME" | "WHOIS" => self.handle_server_hunted(link, msg, 0).await,
+            "LUSERS" => self.handle_server_hunted(link, msg, 1).await,
+            "CONNECT" => self.handle_server_hunted(link, msg, 2).await,
+            "WALLOPS" => self.handle_server_wallops(link, msg).await,
+            cmd if cmd.len() == 3 && cmd.bytes().all(|b| b.is_ascii_digit()) => self.handle_server_numeric(link, msg).await,
+            _ => {
+                debug!("Unhandled server message: {:?}", msg);
+                Ok(())
+            }
         }
     }
 }
 
 // Update handle_connection to ensure cleanup on any error
-pub async fn handle_connection(stream: TcpStream, server: Arc<Server>) -> IrcResult<()> {
+pub async fn handle_connection(mut stream: TcpStream, server: Arc<Server>) -> IrcResult<()> {
     let addr = stream.peer_addr()?;
     stream.set_nodelay(true)?;
     debug!("Starting new connection handler for {}", addr);
 
+    // D-lined addresses are refused before anything is read
+    if let Some(dline) = server.find_dline(addr.ip()).await {
+        info!("Rejecting D-lined connection from {}: {}", addr, dline.reason);
+        // ERR_YOUREBANNEDCREEP (465)
+        let refusal = format!(
+            ":{} 465 * :You are banned from this server- {}\r\nERROR :Closing Link: {} ({})\r\n",
+            server.config.server.name, dline.reason, addr.ip(), dline.reason
+        );
+        stream.write_all(refusal.as_bytes()).await?;
+        return Ok(());
+    }
+
     // Split the stream
     let (reader, writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+
+    // Servers identify themselves with PASS <password> TS 6 <sid> as their first line
+    let mut first_line = String::new();
+    match tokio::time::timeout(Server::REGISTRATION_TIMEOUT, reader.read_line(&mut first_line)).await {
+        Ok(Ok(0)) => return Ok(()), // Closed before sending anything
+        Ok(result) => {
+            result?;
+        }
+        Err(_) => return Err(IrcError::Protocol("Registration timeout".into())),
+    }
+    let first_line = first_line.trim_end_matches(['\r', '\n']).to_string();
+
+    if let Ok(msg) = parse_message(&first_line) {
+        if msg.command == "PASS" && msg.params.len() >= 4 && msg.params[1] == "TS" {
+            return handle_server_connection(reader, writer, addr, msg, server).await;
+        }
+    }
 
     let client = Arc::new(Mutex::new(Client::new(
         writer,
@@ -199,9 +286,10 @@ pub async fn handle_connection(stream: TcpStream, server: Arc<Server>) -> IrcRes
     let connection_future = async {
         server.add_client(Arc::clone(&client)).await;
 
-        let result = {
-            let mut client = client.lock().await;
-            client.handle_connection_with_reader(reader).await
+        let first_result = client.lock().await.handle_line(&first_line).await;
+        let result = match first_result {
+            Ok(()) => Client::handle_connection_with_reader(&client, reader).await,
+            Err(e) => Err(e),
         };
 
         // Cleanup
@@ -240,6 +328,22 @@ pub async fn handle_connection(stream: TcpStream, server: Arc<Server>) -> IrcRes
     }
 }
 
+// Handles a server that connected to us, answering its handshake in reverse order from connect_to_server
+async fn handle_server_connection(
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    addr: SocketAddr,
+    pass: TS6Message,
+    server: Arc<Server>,
+) -> IrcResult<()> {
+    info!("Incoming server connection from {}", addr);
+
+    let server_link = Arc::new(Mutex::new(ServerLink::incoming(reader, writer, addr, Arc::clone(&server))));
+    server.handle_server_message(&server_link, pass).await?;
+
+    ServerLink::handle_connection(server_link).await
+}
+
 impl Clone for Server {
     fn clone(&self) -> Self {
         Self {
@@ -251,21 +355,22 @@ impl Clone for Server {
             nicknames: Arc::clone(&self.nicknames),
             registration_timeouts: Arc::clone(&self.registration_timeouts),
             nickname_map: Arc::clone(&self.nickname_map),
-            tx: self.tx.clone(),
+            uid_map: Arc::clone(&self.uid_map),
+            remote_users: Arc::clone(&self.remote_users),
             linked_servers: Arc::clone(&self.linked_servers),
-        }
-    }
-}
-
-async fn server_task(rx: mpsc::Receiver<ServerMessage>) {
-    let mut clients: HashMap<String, WhoisInfo> = HashMap::new();
-
-    while let Ok(msg) = rx.recv() {
-        match msg {
-            ServerMessage::WhoisLookup { nickname, respond_to } => {
-                let info = clients.get(&nickname.to_lowercase()).cloned();
-                respond_to.send(info).ok();
-            }
+            servers: Arc::clone(&self.servers),
+            link_states: Arc::clone(&self.link_states),
+            resvs: Arc::clone(&self.resvs),
+            klines: Arc::clone(&self.klines),
+            glines: Arc::clone(&self.glines),
+            dlines: Arc::clone(&self.dlines),
+            elines: Arc::clone(&self.elines),
+            ilines: Arc::clone(&self.ilines),
+            olines: Arc::clone(&self.olines),
+            ulines: Arc::clone(&self.ulines),
+            alines: Arc::clone(&self.alines),
+            expiry: Arc::clone(&self.expiry),
+            max_users: Arc::clone(&self.max_users),
         }
     }
 }
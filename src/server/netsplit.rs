@@ -0,0 +1,151 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::error::{IrcError, IrcResult};
+use crate::ircv3::Capability;
+use crate::link::ServerLink;
+use crate::server::{ClientId, Server};
+use crate::ts6::{generate_ts, is_valid_sid, TS6Message};
+
+impl Server {
+    pub(crate) async fn handle_server_squit(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // SQUIT target :comment
+        if msg.params.is_empty() {
+            return Err(IrcError::Protocol("Invalid SQUIT parameters".into()));
+        }
+
+        let target = &msg.params[0];
+        let comment = msg.params.get(1).cloned().unwrap_or_else(|| "No reason given".to_string());
+
+        // Aimed at us or at the link itself, SQUIT announces that the link is closing
+        let (link_name, link_sid) = {
+            let link = link.lock().await;
+            (link.name().to_string(), link.sid().to_string())
+        };
+        if [&self.config.server.sid, &self.config.server.name, &link_sid, &link_name]
+            .iter()
+            .any(|name| name.eq_ignore_ascii_case(target)) {
+            return Err(IrcError::ServerLink(comment));
+        }
+
+        let server = if is_valid_sid(target) {
+            self.find_server_by_sid(target).await
+        } else {
+            self.find_server_by_name(target).await
+        };
+        let Some(server) = server else {
+            debug!("SQUIT for unknown server {}", target);
+            return Ok(());
+        };
+        let route = self.route_to_server(&server.name).await;
+        let behind_link = route.as_ref().is_some_and(|route| Arc::ptr_eq(route, link));
+        if !behind_link {
+            // Only an operator can ask for a link elsewhere to be closed
+            let by_oper = match &msg.source {
+                Some(source) => self.find_remote_user_by_uid(source).await.is_some_and(|user| user.modes.contains(&'o')),
+                None => false,
+            };
+            if !by_oper {
+                warn!("Ignoring SQUIT for {} from {}, which is not its uplink", server.name, link_name);
+                return Ok(());
+            }
+
+            // The link is ours to close, or the SQUIT goes on towards the server's uplink
+            match self.get_linked_server(&server.name).await {
+                Some(target) => {
+                    info!("SQUIT for {} from {}: {}", server.name, msg.source.as_deref().unwrap_or_default(), comment);
+                    target.lock().await.squit(&comment).await;
+                }
+                None => {
+                    if let Some(route) = route {
+                        route.lock().await.send_message(&msg).await?;
+                    }
+                }
+            }
+            return Ok(());
+        }
+
+        self.split_server(&server.name, &comment).await;
+        self.send_to_servers(&msg, Some(link)).await;
+        Ok(())
+    }
+
+    // Removes a server and everything behind it, quitting its users with "uplink server"
+    pub(crate) async fn split_server(&self, name: &str, reason: &str) {
+        let subtree = self.get_server_subtree(name).await;
+        let Some(root) = subtree.first() else {
+            return;
+        };
+        let split_servers = (root.uplink.clone(), root.name.clone());
+        let sids: HashSet<String> = subtree.iter().filter_map(|server| server.sid.clone()).collect();
+
+        let users: Vec<_> = self.get_remote_users().await.into_iter()
+            .filter(|user| sids.contains(&user.server))
+            .collect();
+        info!("Netsplit {} {} ({}): {} servers and {} users lost",
+              split_servers.0, split_servers.1, reason, subtree.len(), users.len());
+
+        // Collect the quits each local user sees before anyone is removed from a channel
+        let quit_reason = format!("{} {}", split_servers.0, split_servers.1);
+        let mut quits: HashMap<ClientId, Vec<TS6Message>> = HashMap::new();
+        for user in &users {
+            let quit_msg = TS6Message::with_source(user.get_prefix(), "QUIT".to_string(), vec![quit_reason.clone()]);
+            for id in self.get_common_channel_members(user.id).await {
+                quits.entry(id).or_default().push(quit_msg.clone());
+            }
+        }
+        self.send_netsplit_quits(quits, &split_servers).await;
+
+        for user in &users {
+            self.forget_remote_user(user).await;
+        }
+        for server in &subtree {
+            self.remove_remote_server(&server.name).await;
+        }
+    }
+
+    // Delivers netsplit quits, grouped in a netsplit batch for clients that support it
+    async fn send_netsplit_quits(&self, quits: HashMap<ClientId, Vec<TS6Message>>, split_servers: &(String, String)) {
+        let reference = format!("netsplit{}", generate_ts());
+
+        for (id, messages) in quits {
+            let Some(client) = self.get_client(id).await else {
+                continue;
+            };
+            let client = client.lock().await;
+
+            let batch = client.has_capability(Capability::Batch);
+            let mut lines = Vec::new();
+            if batch {
+                lines.push(TS6Message::with_source(
+                    self.config.server.name.clone(),
+                    "BATCH".to_string(),
+                    vec![format!("+{}", reference), "netsplit".to_string(), split_servers.0.clone(), split_servers.1.clone()],
+                ));
+            }
+            for mut message in messages {
+                if batch {
+                    message.tags.insert("batch".to_string(), reference.clone());
+                }
+                lines.push(message);
+            }
+            if batch {
+                lines.push(TS6Message::with_source(
+                    self.config.server.name.clone(),
+                    "BATCH".to_string(),
+                    vec![format!("-{}", reference)],
+                ));
+            }
+
+            for line in &lines {
+                if let Err(e) = client.send_message(line).await {
+                    warn!("Failed to send netsplit to client {}: {}", id, e);
+                    break;
+                }
+            }
+        }
+    }
+}
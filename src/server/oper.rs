@@ -0,0 +1,76 @@
+use std::sync::Arc;
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::config::OLine;
+use crate::error::{IrcError, IrcResult};
+use crate::link::ServerLink;
+use crate::server::Server;
+use crate::ts6::TS6Message;
+
+/// Hashes an operator password into the PHC string stored in an O-line.
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default().hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+// Checks a password against an O-line's hash, or its plain text when it isn't encrypted
+pub(crate) fn verify_password(oline: &OLine, password: &str) -> bool {
+    if !oline.encrypted {
+        return constant_time_eq(oline.password.as_bytes(), password.as_bytes());
+    }
+
+    // The hash string carries its own algorithm parameters and salt
+    match PasswordHash::new(&oline.password) {
+        Ok(hash) => Argon2::default().verify_password(password.as_bytes(), &hash).is_ok(),
+        Err(e) => {
+            warn!("Invalid password hash in O-line {}: {}", oline.name, e);
+            false
+        }
+    }
+}
+
+// Compares without returning early, so timing doesn't reveal how much of a password matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+impl Server {
+    // Sends WALLOPS from the given prefix to every local user with +w
+    pub(crate) async fn send_wallops(&self, prefix: &str, text: &str) {
+        let wallops = TS6Message::with_source(prefix.to_string(), "WALLOPS".to_string(), vec![text.to_string()]);
+        for client in self.get_clients().await {
+            let client = client.lock().await;
+            if client.get_modes().contains('w') {
+                client.send_message(&wallops).await.ok();
+            }
+        }
+    }
+
+    pub(crate) async fn handle_server_wallops(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // :source WALLOPS :text
+        let Some(text) = msg.params.first() else {
+            return Err(IrcError::Protocol("Invalid WALLOPS parameters".into()));
+        };
+        let source = msg.source.clone().unwrap_or_default();
+        let Some(prefix) = self.get_source_prefix(&source).await else {
+            debug!("WALLOPS from unknown source {}", source);
+            return Ok(());
+        };
+        self.send_wallops(&prefix, text).await;
+        self.send_to_servers(&msg, Some(link)).await;
+        Ok(())
+    }
+
+    // O-lines with the given name that the user's nick!user@host mask may use
+    pub(crate) async fn find_olines(&self, name: &str, mask: &str) -> Vec<OLine> {
+        self.olines.read().await.iter()
+            .filter(|oline| oline.name == name && self.mask_match(mask, &oline.mask))
+            .cloned()
+            .collect()
+    }
+}
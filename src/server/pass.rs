@@ -1,9 +1,14 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
 use crate::error::{IrcError, IrcResult};
+use crate::link::ServerLink;
 use crate::server::Server;
-use crate::ts6::TS6Message;
+use crate::ts6::{is_valid_sid, TS6Message};
 
 impl Server {
-    pub(crate) async fn handle_server_pass(&self, msg: TS6Message) -> IrcResult<()> {
+    pub(crate) async fn handle_server_pass(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
         // PASS password TS ts sid
         if msg.params.len() < 4 {
             return Err(IrcError::Protocol("Invalid PASS parameters".into()));
@@ -13,12 +18,29 @@ impl Server {
         let ts_version = &msg.params[2];
         let sid = &msg.params[3];
 
+        let mut link = link.lock().await;
+        if link.is_registered() {
+            return Err(link.close("PASS received after registration").await);
+        }
+
         // Verify TS version
-        if ts_version != "6" {
-            return Err(IrcError::Protocol("Unsupported TS version".into()));
+        if msg.params[1] != "TS" || ts_version != "6" {
+            return Err(link.close("Unsupported TS version").await);
+        }
+
+        // Verify SID
+        if !is_valid_sid(sid) {
+            return Err(link.close("Invalid SID").await);
+        }
+        if sid == &self.config.server.sid {
+            return Err(link.close("SID collision").await);
+        }
+        if !link.is_incoming() && link.sid() != sid {
+            return Err(link.close("SID mismatch").await);
         }
 
-        // TODO: Verify password and SID
+        // The password is checked against the link configuration once SERVER names the peer
+        link.set_remote_pass(password.clone(), sid.clone());
         Ok(())
     }
-}
\ No newline at end of file
+}
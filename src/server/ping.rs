@@ -1,25 +1,78 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
 use crate::error::{IrcError, IrcResult};
-use crate::server::Server;
+use crate::link::ServerLink;
+use crate::server::{LinkState, Server};
 use crate::ts6::TS6Message;
 
 impl Server {
-    pub(crate) async fn handle_server_ping(&self, msg: TS6Message) -> IrcResult<()> {
-        // PING source [destination]
+    // Whether a destination SID or name is this server
+    pub(crate) fn is_us(&self, destination: &str) -> bool {
+        destination == self.config.server.sid || destination.eq_ignore_ascii_case(&self.config.server.name)
+    }
+
+    // Passes a PING or PONG for another server on towards it
+    async fn forward_to_server(&self, link: &Arc<Mutex<ServerLink>>, destination: &str, msg: &TS6Message) -> IrcResult<()> {
+        let Some(name) = self.source_server_name(link, Some(destination)).await else {
+            debug!("{} for unknown server {}", msg.command, destination);
+            return Ok(());
+        };
+        match self.route_to_server(&name).await {
+            Some(route) if !Arc::ptr_eq(&route, link) => route.lock().await.send_message(msg).await,
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) async fn handle_server_ping(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // [:source] PING origin [destination]
         if msg.params.is_empty() {
             return Err(IrcError::Protocol("No PING source".into()));
         }
 
+        if let Some(destination) = msg.params.get(1).filter(|destination| !self.is_us(destination)) {
+            return self.forward_to_server(link, destination, &msg).await;
+        }
+
         // Send PONG response
-        let pong = TS6Message::new(
+        let pong = TS6Message::with_source(
+            self.config.server.sid.clone(),
             "PONG".to_string(),
-            vec![self.config.server.name.clone(), msg.params[0].clone()],
+            vec![self.config.server.name.clone(), msg.source.clone().unwrap_or_else(|| msg.params[0].clone())],
         );
 
-        // TODO: Send to correct server
-        Ok(())
+        link.lock().await.send_message(&pong).await
     }
 
-    pub(crate) async fn handle_server_pong(&self, _msg: TS6Message) -> IrcResult<()> {
+    pub(crate) async fn handle_server_pong(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // [:source] PONG origin destination
+        if let Some(destination) = msg.params.get(1).filter(|destination| !self.is_us(destination)) {
+            return self.forward_to_server(link, destination, &msg).await;
+        }
+
+        // The answer to the PING ending our burst arrives after everything the peer burst to us
+        let (name, stats) = {
+            let mut link = link.lock().await;
+            let from_peer = msg.source.as_deref().is_none_or(|source| source == link.sid() || source.eq_ignore_ascii_case(link.name()));
+            if !from_peer {
+                return Ok(());
+            }
+            (link.name().to_string(), link.finish_burst())
+        };
+        let Some(stats) = stats else {
+            return Ok(());
+        };
+
+        let duration = stats.duration.unwrap_or_default();
+        info!("End of burst from {}: {} users, {} channels, {} bytes in {:?}",
+              name, stats.users, stats.channels, stats.bytes, duration);
+        self.set_link_state(&name, LinkState::Established).await;
+        self.send_server_notice(&self.config.server.name, &format!(
+            "End of burst from {} ({} users, {} channels, {} bytes in {:.2} seconds)",
+            name, stats.users, stats.channels, stats.bytes, duration.as_secs_f64(),
+        )).await;
         Ok(())
     }
 }
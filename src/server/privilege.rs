@@ -0,0 +1,93 @@
+use std::collections::HashSet;
+
+use tracing::warn;
+
+use crate::config::OLine;
+use crate::server::Server;
+
+/// An operator privilege, granted by O-line flags or the privilege set an O-line uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Privilege {
+    Kill,
+    RemoteKill,    // KILL for users on other servers
+    Kline,
+    Dline,
+    Gline,
+    Resv,
+    RemoteBan,     // Bans placed ON other servers
+    Wallops,
+    Connect,
+    RemoteConnect, // CONNECT carried out by another server
+    Squit,
+    RemoteSquit,   // SQUIT for links between other servers
+}
+
+impl Privilege {
+    // Name used in config and in ERR_NOPRIVS
+    pub fn name(self) -> &'static str {
+        match self {
+            Privilege::Kill => "kill",
+            Privilege::RemoteKill => "remotekill",
+            Privilege::Kline => "kline",
+            Privilege::Dline => "dline",
+            Privilege::Gline => "gline",
+            Privilege::Resv => "resv",
+            Privilege::RemoteBan => "remoteban",
+            Privilege::Wallops => "wallops",
+            Privilege::Connect => "connect",
+            Privilege::RemoteConnect => "remoteconnect",
+            Privilege::Squit => "squit",
+            Privilege::RemoteSquit => "remotesquit",
+        }
+    }
+
+    // Privileges an oper-only command needs, as far as its parameters tell;
+    // handlers check remote variants that depend on the network themselves
+    pub fn for_command(command: &str, params: &[String]) -> Vec<Privilege> {
+        let privilege = match command {
+            "KILL" => Privilege::Kill,
+            "KLINE" | "UNKLINE" => Privilege::Kline,
+            "DLINE" | "UNDLINE" => Privilege::Dline,
+            "GLINE" | "UNGLINE" => Privilege::Gline,
+            "RESV" | "UNRESV" => Privilege::Resv,
+            "WALLOPS" => Privilege::Wallops,
+            "CONNECT" => Privilege::Connect,
+            "SQUIT" => Privilege::Squit,
+            _ => return Vec::new(),
+        };
+
+        let mut required = vec![privilege];
+        match command {
+            // Bans placed elsewhere: KLINE [duration] mask ON server :reason
+            "KLINE" | "UNKLINE" | "DLINE" | "UNDLINE" | "RESV" | "UNRESV"
+                if params.iter().any(|param| param.eq_ignore_ascii_case("ON")) => required.push(Privilege::RemoteBan),
+            // CONNECT target port server
+            "CONNECT" if params.len() > 2 => required.push(Privilege::RemoteConnect),
+            _ => {}
+        }
+        required
+    }
+}
+
+impl Server {
+    // Privileges an O-line grants: its own flags plus those of its privilege set and the sets it extends
+    pub(crate) fn resolve_privileges(&self, oline: &OLine) -> HashSet<String> {
+        let mut privileges: HashSet<String> = oline.flags.iter().cloned().collect();
+
+        let mut next = oline.privset.clone();
+        let mut seen = HashSet::new();
+        while let Some(name) = next.take() {
+            if !seen.insert(name.clone()) {
+                warn!("Privilege set {} extends itself", name);
+                break;
+            }
+            let Some(privset) = self.config.access.privsets.iter().find(|privset| privset.name == name) else {
+                warn!("O-line {} uses unknown privilege set {}", oline.name, name);
+                break;
+            };
+            privileges.extend(privset.privileges.iter().cloned());
+            next = privset.extends.clone();
+        }
+        privileges
+    }
+}
@@ -1,8 +1,11 @@
+use std::collections::HashSet;
 use std::sync::Arc;
 
-use tracing::debug;
+use tokio::sync::Mutex;
+use tracing::{debug, info};
 
 use crate::error::{IrcError, IrcResult};
+use crate::link::ServerLink;
 use crate::server::{ClientId, Server};
 use crate::ts6::TS6Message;
 
@@ -40,13 +43,56 @@ impl Server {
         }
     }
 
-    pub(crate) async fn handle_server_capab(&self, msg: TS6Message) -> IrcResult<()> {
+    pub(crate) async fn handle_server_capab(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
         // CAPAB capabilities...
         if msg.params.is_empty() {
             return Err(IrcError::Protocol("No capabilities specified".into()));
         }
 
-        // TODO: Process capabilities
+        let mut link = link.lock().await;
+        if link.remote_pass().is_none() {
+            return Err(link.close("CAPAB received before PASS").await);
+        }
+
+        let capabilities: HashSet<String> = msg.params.iter()
+            .flat_map(|param| param.split_whitespace())
+            .map(|cap| cap.to_uppercase())
+            .collect();
+
+        // TS6 requires QS and ENCAP from every server
+        for required in ["QS", "ENCAP"] {
+            if !capabilities.contains(required) {
+                return Err(link.close(&format!("Missing required capability {}", required)).await);
+            }
+        }
+
+        debug!("Server link negotiated capabilities: {:?}", capabilities);
+        link.set_remote_capabilities(capabilities);
+        Ok(())
+    }
+
+    pub(crate) async fn handle_server_svinfo(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // SVINFO current_ts min_ts 0 current_time
+        if msg.params.len() < 4 {
+            return Err(IrcError::Protocol("Invalid SVINFO parameters".into()));
+        }
+
+        let link = link.lock().await;
+
+        let current_version = msg.params[0].parse::<u32>().unwrap_or(0);
+        let min_version = msg.params[1].parse::<u32>().unwrap_or(u32::MAX);
+        if current_version < 6 || min_version > 6 {
+            return Err(link.close("Incompatible TS version").await);
+        }
+
+        let remote_time = msg.params[3].parse::<i64>()
+            .map_err(|_| IrcError::Protocol("Invalid SVINFO time".into()))?;
+        let delta = (crate::ts6::generate_ts() as i64 - remote_time).abs();
+        if delta > Self::MAX_TS_DELTA {
+            return Err(link.close(&format!("Excessive TS delta ({}s)", delta)).await);
+        }
+
+        info!("Server {} verified TS6 (clock delta {}s)", link.name(), delta);
         Ok(())
     }
 }
\ No newline at end of file
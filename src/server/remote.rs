@@ -0,0 +1,295 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use tracing::{debug, info, warn};
+
+use crate::client::generate_client_id;
+use crate::error::{IrcError, IrcResult};
+use crate::link::ServerLink;
+use crate::server::collision::{NickCollision, SAVE_NICK_TS};
+use crate::server::{ClientId, Server};
+use crate::ts6::{euid_to_uid, TS6Message};
+
+/// A user on another server, introduced to us with UID or EUID.
+#[derive(Clone, Debug)]
+pub struct RemoteUser {
+    pub id: ClientId,
+    pub uid: String,
+    pub nickname: String,
+    pub nick_ts: u64,
+    pub username: String,
+    pub hostname: String,
+    pub realhost: String,
+    pub ip: String,
+    pub account: Option<String>,
+    pub certfp: Option<String>,
+    pub modes: HashSet<char>,
+    pub realname: String,
+    pub server: String, // SID of the server the user is connected to
+}
+
+impl RemoteUser {
+    pub fn get_prefix(&self) -> String {
+        format!("{}!{}@{}", self.nickname, self.username, self.hostname)
+    }
+}
+
+impl Server {
+    pub(crate) async fn get_remote_user(&self, id: ClientId) -> Option<RemoteUser> {
+        let remote_users = self.remote_users.read().await;
+        remote_users.get(&id).cloned()
+    }
+
+    pub(crate) async fn get_remote_users(&self) -> Vec<RemoteUser> {
+        let remote_users = self.remote_users.read().await;
+        remote_users.values().cloned().collect()
+    }
+
+    pub(crate) async fn find_remote_user_by_uid(&self, uid: &str) -> Option<RemoteUser> {
+        let id = self.find_client_by_uid(uid).await?;
+        self.get_remote_user(id).await
+    }
+
+    // Sends a server-to-server message towards a remote user through the link they are behind
+    pub(crate) async fn send_to_remote_user(&self, user: &RemoteUser, message: &TS6Message) -> IrcResult<()> {
+        let (server, _) = self.get_server_info(&user.server).await;
+        let link = self.route_to_server(&server).await
+            .ok_or_else(|| IrcError::ServerLink(format!("No route to {}", user.nickname)))?;
+        let link = link.lock().await;
+        link.send_message(message).await
+    }
+
+    pub(crate) async fn handle_server_uid(&self, link: &Arc<Mutex<ServerLink>>, mut msg: TS6Message) -> IrcResult<()> {
+        // UID nick hopcount nickTS umodes username host ip uid :gecos
+        // EUID nick hopcount nickTS umodes username host ip uid realhost account :gecos
+        let euid = msg.command == "EUID";
+        if msg.params.len() < if euid { 11 } else { 9 } {
+            return Err(IrcError::Protocol(format!("Invalid {} parameters", msg.command)));
+        }
+
+        let server = match msg.source.clone() {
+            Some(source) => source,
+            None => link.lock().await.sid().to_string(),
+        };
+        let uid = msg.params[7].clone();
+        if !uid.starts_with(&server) || uid.len() != 9 {
+            return Err(IrcError::Protocol(format!("Invalid UID {} from {}", uid, server)));
+        }
+        if self.find_server_by_sid(&server).await.is_none() {
+            warn!("Ignoring introduction of {} from unknown server {}", msg.params[0], server);
+            return Ok(());
+        }
+
+        let (realhost, account) = if euid {
+            let account = Some(msg.params[9].clone()).filter(|account| account != "*");
+            (msg.params[8].clone(), account)
+        } else {
+            (msg.params[5].clone(), None)
+        };
+
+        let mut user = RemoteUser {
+            id: generate_client_id(),
+            uid,
+            nickname: msg.params[0].clone(),
+            nick_ts: msg.params[2].parse().unwrap_or(0),
+            username: msg.params[4].clone(),
+            hostname: msg.params[5].clone(),
+            realhost,
+            ip: msg.params[6].clone(),
+            account,
+            certfp: None,
+            modes: msg.params[3].chars().filter(|&c| c != '+').collect(),
+            realname: msg.params[msg.params.len() - 1].clone(),
+            server,
+        };
+
+        if self.find_client_by_uid(&user.uid).await.is_some() {
+            warn!("Ignoring introduction of {}: UID {} already in use", user.nickname, user.uid);
+            return Ok(());
+        }
+
+        let userhost = format!("{}@{}", user.username, user.hostname);
+        match self.resolve_nick_collision(link, &user.nickname, user.nick_ts, &userhost).await {
+            NickCollision::Won => {}
+            NickCollision::Save => {
+                let save = TS6Message::with_source(
+                    self.config.server.sid.clone(),
+                    "SAVE".to_string(),
+                    vec![user.uid.clone(), user.nick_ts.to_string()],
+                );
+                link.lock().await.send_message(&save).await?;
+
+                // Introduce the user to everyone else under its UID
+                user.nickname = user.uid.clone();
+                user.nick_ts = SAVE_NICK_TS;
+                msg.params[0] = user.uid.clone();
+                msg.params[2] = SAVE_NICK_TS.to_string();
+            }
+            NickCollision::Kill => {
+                link.lock().await.send_message(&self.collision_kill(&user.uid)).await?;
+                return Ok(());
+            }
+        }
+        if let Err(e) = self.register_nickname(&user.nickname, user.id).await {
+            warn!("Ignoring introduction of {} ({}): {}", user.nickname, user.uid, e);
+            return Ok(());
+        }
+
+        debug!("Remote user {} ({}) introduced by {}", user.nickname, user.uid, user.server);
+        self.uid_map.write().await.insert(user.uid.clone(), user.id);
+        self.remote_users.write().await.insert(user.id, user);
+
+        self.propagate_introduction(link, &msg).await;
+        Ok(())
+    }
+
+    // Passes a UID/EUID on, downgrading EUID to UID plus ENCAP for links without EUID support
+    async fn propagate_introduction(&self, from: &Arc<Mutex<ServerLink>>, msg: &TS6Message) {
+        let links: Vec<_> = self.linked_servers.read().await.values().cloned().collect();
+        for link in links {
+            if Arc::ptr_eq(from, &link) {
+                continue;
+            }
+            let link = link.lock().await;
+            if !link.is_registered() {
+                continue;
+            }
+
+            let messages = if msg.command == "EUID" && !link.has_capability("EUID") {
+                euid_to_uid(msg)
+            } else {
+                vec![msg.clone()]
+            };
+            for message in &messages {
+                if let Err(e) = link.send_message(message).await {
+                    warn!("Failed to send to server {}: {}", link.name(), e);
+                }
+            }
+        }
+    }
+
+    pub(crate) async fn handle_remote_nick(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // :uid NICK newnick :nickTS
+        if msg.params.is_empty() {
+            return Err(IrcError::Protocol("Invalid NICK parameters".into()));
+        }
+        let Some(user) = self.find_remote_user_by_uid(msg.source.as_deref().unwrap_or_default()).await else {
+            debug!("NICK from unknown user {:?}", msg.source);
+            return Ok(());
+        };
+
+        let new_nick = &msg.params[0];
+        let new_ts = msg.params.get(1).and_then(|ts| ts.parse().ok()).unwrap_or(user.nick_ts);
+        if !user.nickname.eq_ignore_ascii_case(new_nick) {
+            let userhost = format!("{}@{}", user.username, user.hostname);
+            match self.resolve_nick_collision(link, new_nick, new_ts, &userhost).await {
+                NickCollision::Won => {}
+                NickCollision::Save => {
+                    // The change already happened towards the link, so only it gets the SAVE
+                    let save = TS6Message::with_source(
+                        self.config.server.sid.clone(),
+                        "SAVE".to_string(),
+                        vec![user.uid.clone(), new_ts.to_string()],
+                    );
+                    link.lock().await.send_message(&save).await?;
+
+                    let nick = TS6Message::with_source(
+                        user.uid.clone(),
+                        "NICK".to_string(),
+                        vec![user.uid.clone(), SAVE_NICK_TS.to_string()],
+                    );
+                    self.send_to_servers(&nick, Some(link)).await;
+                    self.rename_user(user.id, &user.uid, SAVE_NICK_TS).await;
+                    return Ok(());
+                }
+                NickCollision::Kill => {
+                    self.send_to_servers(&self.collision_kill(&user.uid), None).await;
+                    self.remove_remote_user(&user, "Nick collision").await;
+                    return Ok(());
+                }
+            }
+            if let Err(e) = self.register_nickname(new_nick, user.id).await {
+                warn!("Ignoring nick change of {} to {}: {}", user.nickname, new_nick, e);
+                return Ok(());
+            }
+            self.unregister_nickname(&user.nickname).await;
+        }
+
+        if let Some(remote) = self.remote_users.write().await.get_mut(&user.id) {
+            remote.nickname = new_nick.clone();
+            remote.nick_ts = new_ts;
+        }
+
+        let nick_msg = TS6Message::with_source(user.get_prefix(), "NICK".to_string(), vec![new_nick.clone()]);
+        self.broadcast_to_common_channels(user.id, &nick_msg).await;
+
+        self.send_to_servers(&msg, Some(link)).await;
+        Ok(())
+    }
+
+    pub(crate) async fn handle_server_mode(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // :uid MODE uid :umodes
+        if msg.params.len() < 2 {
+            return Err(IrcError::Protocol("Invalid MODE parameters".into()));
+        }
+        let target = &msg.params[0];
+        if target.starts_with('#') {
+            debug!("Unhandled channel MODE from server: {:?}", msg);
+            return Ok(());
+        }
+
+        let mut adding = true;
+        let updated = self.update_remote_user(target, |user| {
+            for c in msg.params[1].chars() {
+                match c {
+                    '+' => adding = true,
+                    '-' => adding = false,
+                    c if adding => {
+                        user.modes.insert(c);
+                    }
+                    c => {
+                        user.modes.remove(&c);
+                    }
+                }
+            }
+        }).await;
+        if updated {
+            self.send_to_servers(&msg, Some(link)).await;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn handle_remote_quit(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // :uid QUIT :reason
+        let Some(user) = self.find_remote_user_by_uid(msg.source.as_deref().unwrap_or_default()).await else {
+            debug!("QUIT from unknown user {:?}", msg.source);
+            return Ok(());
+        };
+
+        let reason = msg.params.first().cloned().unwrap_or_default();
+        self.remove_remote_user(&user, &reason).await;
+
+        self.send_to_servers(&msg, Some(link)).await;
+        Ok(())
+    }
+
+    // Shows a remote user quitting to local users and forgets them
+    pub(crate) async fn remove_remote_user(&self, user: &RemoteUser, reason: &str) {
+        info!("Remote user {} ({}) quit: {}", user.nickname, user.uid, reason);
+
+        let quit_msg = TS6Message::with_source(user.get_prefix(), "QUIT".to_string(), vec![reason.to_string()]);
+        self.broadcast_to_common_channels(user.id, &quit_msg).await;
+        self.forget_remote_user(user).await;
+    }
+
+    // Drops a remote user from channels and lookups without telling anyone
+    pub(crate) async fn forget_remote_user(&self, user: &RemoteUser) {
+        for channel in self.get_client_channels(user.id).await {
+            self.remove_from_channel(&channel, user.id).await.ok();
+        }
+        self.unregister_nickname(&user.nickname).await;
+        self.uid_map.write().await.remove(&user.uid);
+        self.remote_users.write().await.remove(&user.id);
+    }
+}
@@ -0,0 +1,102 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::error::{IrcError, IrcResult};
+use crate::link::ServerLink;
+use crate::server::{Server, User};
+use crate::ts6::{is_valid_sid, TS6Message};
+
+impl Server {
+    // Links with members of the channel behind them, each listed once
+    pub(crate) async fn get_channel_links(&self, channel_name: &str) -> Vec<Arc<Mutex<ServerLink>>> {
+        let Some(channel) = self.get_channel(channel_name).await else {
+            return Vec::new();
+        };
+        let members: Vec<u32> = channel.read().await.get_members().iter().copied().collect();
+
+        let mut links: Vec<Arc<Mutex<ServerLink>>> = Vec::new();
+        for id in members {
+            let Some(user) = self.get_remote_user(id).await else {
+                continue;
+            };
+            let (server, _) = self.get_server_info(&user.server).await;
+            if let Some(link) = self.route_to_server(&server).await {
+                if !links.iter().any(|known| Arc::ptr_eq(known, &link)) {
+                    links.push(link);
+                }
+            }
+        }
+        links
+    }
+
+    // Sends a channel message once down every link with members behind it, except the one it came from
+    pub(crate) async fn send_to_channel_servers(&self, channel_name: &str, message: &TS6Message, skip: Option<&Arc<Mutex<ServerLink>>>) {
+        for link in self.get_channel_links(channel_name).await {
+            if skip.is_some_and(|skip| Arc::ptr_eq(skip, &link)) {
+                continue;
+            }
+            let link = link.lock().await;
+            if let Err(e) = link.send_message(message).await {
+                warn!("Failed to send to server {}: {}", link.name(), e);
+            }
+        }
+    }
+
+    // The prefix local users see for a message from a user's UID or a server's SID or name
+    pub(crate) async fn get_source_prefix(&self, source: &str) -> Option<String> {
+        if let Some(id) = self.find_client_by_uid(source).await {
+            return self.get_user_prefix(id).await;
+        }
+        if is_valid_sid(source) {
+            return self.find_server_by_sid(source).await.map(|server| server.name);
+        }
+        self.find_server_by_name(source).await.map(|server| server.name)
+    }
+
+    pub(crate) async fn handle_server_privmsg(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // :source PRIVMSG|NOTICE target :text
+        if msg.params.len() < 2 {
+            return Err(IrcError::Protocol(format!("Invalid {} parameters", msg.command)));
+        }
+
+        let source = msg.source.clone().unwrap_or_default();
+        let Some(prefix) = self.get_source_prefix(&source).await else {
+            debug!("{} from unknown source {}", msg.command, source);
+            return Ok(());
+        };
+        let target = &msg.params[0];
+        let text = &msg.params[1];
+
+        if target.starts_with('#') {
+            let local_msg = TS6Message::with_source(prefix, msg.command.clone(), vec![target.clone(), text.clone()]);
+            self.broadcast_to_channel(target, &local_msg, None).await?;
+            self.send_to_channel_servers(target, &msg, Some(link)).await;
+            return Ok(());
+        }
+
+        // Users are addressed by UID; pass on messages for users elsewhere
+        let Some(id) = self.find_client_by_uid(target).await else {
+            debug!("{} for unknown user {}", msg.command, target);
+            return Ok(());
+        };
+        match self.get_user(id).await {
+            Some(User::Local(client)) => {
+                let client = client.lock().await;
+                let nickname = client.get_nickname().cloned().unwrap_or_default();
+                let local_msg = TS6Message::with_source(prefix, msg.command.clone(), vec![nickname, text.clone()]);
+                client.send_message(&local_msg).await?;
+            }
+            Some(User::Remote(user)) => {
+                let (server, _) = self.get_server_info(&user.server).await;
+                match self.route_to_server(&server).await {
+                    Some(route) if !Arc::ptr_eq(&route, link) => route.lock().await.send_message(&msg).await?,
+                    _ => debug!("Dropping {} for {} sent from its own direction", msg.command, user.nickname),
+                }
+            }
+            None => {}
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
+use crate::channel::format_mode_changes;
+use crate::error::{IrcError, IrcResult};
+use crate::link::ServerLink;
+use crate::server::{ClientId, Server};
+use crate::ts6::TS6Message;
+
+impl Server {
+    pub(crate) async fn handle_server_join(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // SJOIN timestamp channel modes [mode params...] members
+        if msg.params.len() < 4 {
+            return Err(IrcError::Protocol("Invalid SJOIN parameters".into()));
+        }
+
+        let their_ts = msg.params[0].parse::<u64>()
+            .map_err(|_| IrcError::Protocol("Invalid SJOIN timestamp".into()))?;
+        let channel_name = &msg.params[1];
+        let modes = &msg.params[2];
+        let mode_params = &msg.params[3..msg.params.len() - 1];
+        let nicklist = &msg.params[msg.params.len() - 1];
+
+        // Resolve the joining users, keeping their status prefixes
+        let mut joining: Vec<(ClientId, String, Vec<char>)> = Vec::new();
+        for entry in nicklist.split_whitespace() {
+            let uid = entry.trim_start_matches(['@', '+']);
+            let statuses = entry[..entry.len() - uid.len()].chars()
+                .map(|prefix| if prefix == '@' { 'o' } else { 'v' })
+                .collect();
+            match self.find_client_by_uid(uid).await {
+                Some(id) => joining.push((id, uid.to_string(), statuses)),
+                None => debug!("SJOIN for {} names unknown user {}", channel_name, uid),
+            }
+        }
+
+        let is_new = self.get_channel(channel_name).await.is_none();
+        if is_new && joining.is_empty() {
+            debug!("Ignoring SJOIN for new channel {} without known members", channel_name);
+            return Ok(());
+        }
+        let channel = self.get_or_create_channel(channel_name).await;
+
+        let mut removed_modes = Vec::new();
+        let mut removed_statuses = Vec::new();
+        let mut removed_lists = Vec::new();
+        let mut added_modes = Vec::new();
+        let mut joined = Vec::new();
+
+        let (our_ts, new_ts, accept_modes) = {
+            let mut channel = channel.write().await;
+            let our_ts = if is_new { their_ts } else { channel.created_at() };
+            // A channel created by SJOIN has exactly the modes sent, not our defaults
+            if is_new {
+                channel.clear_simple_modes();
+            }
+
+            // Channel TS rules: the older channel wins, equal TS merges
+            let (new_ts, accept_modes) = if their_ts == 0 || our_ts == 0 {
+                (0, true)
+            } else if their_ts < our_ts {
+                removed_modes = channel.clear_simple_modes();
+                removed_statuses = channel.clear_statuses();
+                removed_lists = channel.clear_lists();
+                (their_ts, true)
+            } else if their_ts == our_ts {
+                (our_ts, true)
+            } else {
+                (our_ts, false)
+            };
+            channel.set_created_at(new_ts);
+
+            if accept_modes {
+                added_modes = channel.apply_simple_modes(modes, mode_params);
+            }
+
+            for (id, _, statuses) in &joining {
+                if !channel.get_members().contains(id) {
+                    channel.add_member(*id);
+                    joined.push(*id);
+                }
+                if accept_modes {
+                    for &status in statuses {
+                        if status == 'o' {
+                            channel.set_operator(*id, true);
+                        } else {
+                            channel.set_voice(*id, true);
+                        }
+                    }
+                }
+            }
+
+            (our_ts, new_ts, accept_modes)
+        };
+
+        if !is_new && new_ts != our_ts {
+            info!("Channel {} TS changed from {} to {}", channel_name, our_ts, new_ts);
+            let notice = TS6Message::with_source(
+                self.config.server.name.clone(),
+                "NOTICE".to_string(),
+                vec![
+                    channel_name.to_string(),
+                    format!("*** Notice -- TS for {} changed from {} to {}", channel_name, our_ts, new_ts),
+                ],
+            );
+            self.broadcast_to_channel(channel_name, &notice, None).await?;
+        }
+
+        // Tell local members what the losing side lost
+        let mut removals: Vec<(char, Option<String>, bool)> = removed_modes.into_iter()
+            .map(|(mode, param)| (mode, param, false))
+            .collect();
+        for (id, status) in removed_statuses {
+            if let Some(nick) = self.get_user_nick(id).await {
+                removals.push((status, Some(nick), false));
+            }
+        }
+        removals.extend(removed_lists.into_iter().map(|(mode, mask)| (mode, Some(mask), false)));
+        self.announce_mode_changes(channel_name, &removals).await?;
+
+        // Show the new users joining, then the modes and statuses we accepted
+        for &id in &joined {
+            if let Some(prefix) = self.get_user_prefix(id).await {
+                let join_msg = TS6Message::with_source(prefix, "JOIN".to_string(), vec![channel_name.to_string()]);
+                self.broadcast_to_channel(channel_name, &join_msg, Some(id)).await?;
+            }
+        }
+        let mut additions: Vec<(char, Option<String>, bool)> = added_modes.into_iter()
+            .map(|(mode, param)| (mode, param, true))
+            .collect();
+        if accept_modes {
+            for (id, _, statuses) in &joining {
+                if let Some(nick) = self.get_user_nick(*id).await {
+                    additions.extend(statuses.iter().map(|&status| (status, Some(nick.clone()), true)));
+                }
+            }
+        }
+        self.announce_mode_changes(channel_name, &additions).await?;
+
+        // Propagate with the resulting TS and modes; statuses only if they were accepted
+        let propagated_members = joining.iter()
+            .map(|(_, uid, statuses)| {
+                let prefix: String = if accept_modes {
+                    statuses.iter().map(|&status| if status == 'o' { '@' } else { '+' }).collect()
+                } else {
+                    String::new()
+                };
+                format!("{}{}", prefix, uid)
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+        let mut params = vec![new_ts.to_string(), channel_name.to_string()];
+        params.extend(channel.read().await.get_simple_modes());
+        params.push(propagated_members);
+        let sjoin = TS6Message {
+            tags: Default::default(),
+            source: msg.source.clone(),
+            command: "SJOIN".to_string(),
+            params,
+        };
+        self.send_to_servers(&sjoin, Some(link)).await;
+
+        Ok(())
+    }
+
+    pub(crate) async fn handle_server_bmask(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // BMASK timestamp channel type masks
+        if msg.params.len() < 4 {
+            return Err(IrcError::Protocol("Invalid BMASK parameters".into()));
+        }
+
+        let their_ts = msg.params[0].parse::<u64>()
+            .map_err(|_| IrcError::Protocol("Invalid BMASK timestamp".into()))?;
+        let channel_name = &msg.params[1];
+        let mode = msg.params[2].chars().next().unwrap_or('b');
+        let setter = msg.source.clone().unwrap_or_else(|| self.config.server.name.clone());
+
+        let Some(channel) = self.get_channel(channel_name).await else {
+            return Ok(());
+        };
+
+        // Masks from a newer channel are dropped and not propagated
+        let added: Vec<(char, Option<String>, bool)> = {
+            let mut channel = channel.write().await;
+            if their_ts > channel.created_at() {
+                return Ok(());
+            }
+            msg.params[3].split_whitespace()
+                .filter(|mask| channel.add_list_entry(mode, mask.to_string(), setter.clone()))
+                .map(|mask| (mode, Some(mask.to_string()), true))
+                .collect()
+        };
+
+        self.announce_mode_changes(channel_name, &added).await?;
+        self.send_to_servers(&msg, Some(link)).await;
+        Ok(())
+    }
+
+    // Sends MODE changes from this server to local channel members, a few at a time
+    pub(crate) async fn announce_mode_changes(&self, channel_name: &str, changes: &[(char, Option<String>, bool)]) -> IrcResult<()> {
+        const MAX_MODES: usize = 4; // Matches MODES in ISUPPORT
+
+        for chunk in changes.chunks(MAX_MODES) {
+            let mut params = vec![channel_name.to_string()];
+            params.extend(format_mode_changes(chunk));
+            let mode_msg = TS6Message::with_source(self.config.server.name.clone(), "MODE".to_string(), params);
+            self.broadcast_to_channel(channel_name, &mode_msg, None).await?;
+        }
+        Ok(())
+    }
+}
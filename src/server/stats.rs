@@ -1,3 +1,8 @@
+use std::sync::Arc;
+
+use tracing::warn;
+
+use crate::client::Client;
 use crate::server::Server;
 
 #[derive(Default)]
@@ -6,6 +11,7 @@ pub struct ServerStats {
     pub invisible_users: usize,
     pub server_count: usize,
     pub oper_count: usize,
+    pub unknown_connections: usize,
     pub channel_count: usize,
     pub local_users: usize,
     pub local_servers: usize,
@@ -14,27 +20,83 @@ pub struct ServerStats {
     pub max_global_users: usize,
 }
 
+impl ServerStats {
+    fn count_user(&mut self, modes: &str) {
+        if modes.contains('i') {
+            self.invisible_users += 1;
+        } else {
+            self.visible_users += 1;
+        }
+        if modes.contains('o') {
+            self.oper_count += 1;
+        }
+    }
+}
+
 impl Server {
-    pub async fn get_stats(&self) -> ServerStats {
+    // A client asking for its own stats passes itself, as its lock is already held
+    pub async fn get_stats(&self, caller: Option<&Client>) -> ServerStats {
         let mut stats = ServerStats::default();
 
-        let client_count = {
-            let clients = self.clients.read().await;
-            clients.len()
+        let clients: Vec<_> = {
+            let client_map = self.client_map.read().await;
+            client_map.iter()
+                .filter(|(&id, _)| caller.is_none_or(|caller| caller.id() != id))
+                .map(|(_, client)| Arc::clone(client))
+                .collect()
         };
+        let mut local = Vec::new();
+        if let Some(caller) = caller {
+            local.push((caller.is_registered(), caller.get_modes()));
+        }
+        for client in clients {
+            let client = client.lock().await;
+            local.push((client.is_registered(), client.get_modes()));
+        }
+        for (registered, modes) in local {
+            if !registered {
+                stats.unknown_connections += 1;
+                continue;
+            }
+            stats.local_users += 1;
+            stats.count_user(&modes);
+        }
 
-        let channel_count = {
-            let channels = self.channels.read().await;
-            channels.len()
-        };
+        for user in self.get_remote_users().await {
+            let modes: String = user.modes.iter().collect();
+            stats.count_user(&modes);
+        }
+        stats.global_users = stats.visible_users + stats.invisible_users;
 
-        stats.local_users = client_count;
-        stats.global_users = client_count;
-        stats.channel_count = channel_count;
-        stats.max_local_users = client_count;
-        stats.max_global_users = client_count;
-        stats.server_count = 1;
+        stats.channel_count = self.channels.read().await.len();
+        stats.server_count = 1 + self.servers.read().await.len();
+        let links: Vec<_> = self.linked_servers.read().await.values().cloned().collect();
+        for link in links {
+            if link.lock().await.is_registered() {
+                stats.local_servers += 1;
+            }
+        }
 
+        (stats.max_local_users, stats.max_global_users) = self.update_max_users(stats.local_users, stats.global_users).await;
         stats
     }
-}
\ No newline at end of file
+
+    // Raises the recorded maxima to the current counts, saving new highs to the database
+    async fn update_max_users(&self, local: usize, global: usize) -> (usize, usize) {
+        let (max_users, changed) = {
+            let mut max_users = self.max_users.write().await;
+            let previous = *max_users;
+            *max_users = (max_users.0.max(local), max_users.1.max(global));
+            (*max_users, *max_users != previous)
+        };
+
+        if changed {
+            if let Some(db) = &self.database {
+                if let Err(e) = db.set_max_users(max_users.0, max_users.1).await {
+                    warn!("Failed to save user maxima: {}", e);
+                }
+            }
+        }
+        max_users
+    }
+}
@@ -7,8 +7,11 @@ mod tests {
     use tokio::net::TcpStream;
     use tokio::time::Duration;
 
-    use crate::config::KLine;
-    use crate::server::Server;
+    use crate::cidr::{CidrTable, IpNet};
+    use crate::config::{ALine, DatabaseConfig, DLine, ELine, GLine, ILine, KLine, OLine, PrivSet, Resv, ServerConfig, ULine};
+    use crate::database::Database;
+    use crate::server::autoconnect::retry_delay;
+    use crate::server::{hash_password, verify_password, Privilege, RemoteServer, Server};
     use crate::test_utils::{setup_test_server, test_config};
     use crate::test_utils::TestClient;
 
@@ -21,6 +24,10 @@ mod tests {
     const PORT_CLIENT_LIMITS: u16 = 6906;
     const PORT_KLINE: u16 = 6907;
     const PORT_CAPABILITIES: u16 = 6908;
+    const PORT_BANS: u16 = 6909;
+    const PORT_DLINE: u16 = 6910;
+    const PORT_PERSISTENCE: u16 = 6919;
+    const PORT_EXPIRY: u16 = 6920;
 
     async fn wait_for_server(addr: &SocketAddr) {
         for _ in 0..50 {  // Try for 5 seconds
@@ -158,12 +165,12 @@ mod tests {
         server.add_kline(kline).await.unwrap();
 
         // Test K-line check
-        let test_host = "*!*@banned.com".to_string();
-        assert!(server.is_host_klined(&test_host).await);
+        let ip = "192.0.2.1".parse().unwrap();
+        assert!(server.find_user_ban("nick", "user", "banned.com", ip).await.is_some());
 
         // Remove K-line
         server.remove_kline("*!*@banned.com".to_string()).await.unwrap();
-        assert!(!server.is_host_klined(&test_host).await);
+        assert!(server.find_user_ban("nick", "user", "banned.com", ip).await.is_none());
     }
 
     #[tokio::test]
@@ -179,4 +186,491 @@ mod tests {
         assert!(test_client.has_capability("message-tags"));
         assert!(test_client.has_capability("server-time"));
     }
-} 
\ No newline at end of file
+
+    #[test]
+    fn test_link_retry_delay() {
+        let frequency = Duration::from_secs(60);
+        assert_eq!(retry_delay(1, frequency), Duration::from_secs(2));
+        assert_eq!(retry_delay(2, frequency), Duration::from_secs(4));
+        assert_eq!(retry_delay(4, frequency), Duration::from_secs(16));
+        assert_eq!(retry_delay(10, frequency), frequency);
+        assert_eq!(retry_delay(u32::MAX, frequency), frequency);
+    }
+
+    #[tokio::test]
+    async fn test_flattened_links() {
+        let mut config = test_config(0);
+        config.network.flatten_links = true;
+        let server = Server::new(config).await.unwrap();
+        for (name, sid, hopcount, uplink, description) in [
+            ("hub.server", "002", 1, "test.server", "Hub Server"),
+            ("leaf.server", "003", 2, "hub.server", "Leaf Server"),
+            ("hidden.server", "004", 2, "hub.server", "(H) Hidden Server"),
+        ] {
+            server.add_remote_server(RemoteServer {
+                name: name.to_string(),
+                sid: Some(sid.to_string()),
+                description: description.to_string(),
+                hopcount,
+                uplink: uplink.to_string(),
+            }).await;
+        }
+
+        let links = |reply: Vec<(u16, Vec<String>)>| -> Vec<String> {
+            reply.into_iter().map(|(numeric, params)| format!("{} {}", numeric, params.join(" "))).collect()
+        };
+
+        // Non-opers see every visible server one hop away from us
+        assert_eq!(links(server.links_reply("*", false).await), vec![
+            "364 test.server test.server 0 Test Server",
+            "364 hub.server test.server 1 Hub Server",
+            "364 leaf.server test.server 1 Leaf Server",
+            "365 * End of /LINKS list",
+        ]);
+
+        // Opers see the real topology, hidden servers included
+        assert_eq!(links(server.links_reply("*.server", true).await), vec![
+            "364 test.server test.server 0 Test Server",
+            "364 hub.server test.server 1 Hub Server",
+            "364 hidden.server hub.server 2 (H) Hidden Server",
+            "364 leaf.server hub.server 2 Leaf Server",
+            "365 *.server End of /LINKS list",
+        ]);
+    }
+
+    #[test]
+    fn test_oper_password_verification() {
+        let mut oline = OLine {
+            name: "admin".to_string(),
+            mask: "*!*@*".to_string(),
+            password: hash_password("secret").unwrap(),
+            encrypted: true,
+            flags: vec![],
+            privset: None,
+        };
+        assert!(oline.password.starts_with("$argon2id$"));
+        assert!(verify_password(&oline, "secret"));
+        assert!(!verify_password(&oline, "Secret"));
+
+        // Hashes are never compared as plain text
+        let hash = oline.password.clone();
+        assert!(!verify_password(&oline, &hash));
+        oline.password = "secret".to_string();
+        assert!(!verify_password(&oline, "secret"));
+
+        oline.encrypted = false;
+        assert!(verify_password(&oline, "secret"));
+        assert!(!verify_password(&oline, "secre"));
+    }
+
+    #[test]
+    fn test_unnamed_oline_config() {
+        // O-lines written before OPER took a name
+        let mut config: toml::Value = toml::from_str(&std::fs::read_to_string("config.toml").unwrap()).unwrap();
+        let olines = format!(r#"olines = [{{ mask = "*!*@old.example", password = "{}", flags = ["kill"] }}]"#, hash_password("oldpass").unwrap());
+        config["access"]["olines"] = toml::from_str::<toml::Value>(&olines).unwrap()["olines"].clone();
+        let path = std::env::temp_dir().join("ircd-rs-unnamed-oline.toml");
+        std::fs::write(&path, toml::to_string(&config).unwrap()).unwrap();
+
+        let config = ServerConfig::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(config.access.olines.len(), 1);
+        assert_eq!(config.access.olines[0].name, "*!*@old.example");
+        assert!(verify_password(&config.access.olines[0], "oldpass"));
+    }
+
+    #[tokio::test]
+    async fn test_privilege_sets() {
+        let mut config = test_config(0);
+        for (name, extends, privileges) in [
+            ("oper", None, vec!["kill", "kline"]),
+            ("admin", Some("oper"), vec!["resv", "squit"]),
+            ("loop", Some("loop"), vec!["wallops"]),
+        ] {
+            config.access.privsets.push(PrivSet {
+                name: name.to_string(),
+                extends: extends.map(str::to_string),
+                privileges: privileges.into_iter().map(str::to_string).collect(),
+            });
+        }
+        let server = Server::new(config).await.unwrap();
+
+        let mut oline = OLine {
+            name: "admin".to_string(),
+            mask: "*!*@*".to_string(),
+            password: "secret".to_string(),
+            encrypted: false,
+            flags: vec!["connect".to_string()],
+            privset: Some("admin".to_string()),
+        };
+        let mut privileges: Vec<_> = server.resolve_privileges(&oline).into_iter().collect();
+        privileges.sort();
+        assert_eq!(privileges, vec!["connect", "kill", "kline", "resv", "squit"]);
+
+        // A set extending itself is only applied once
+        oline.privset = Some("loop".to_string());
+        let privileges: Vec<_> = server.resolve_privileges(&oline).into_iter().collect();
+        assert_eq!(privileges.len(), 2);
+
+        let params = |params: &[&str]| -> Vec<String> { params.iter().map(|param| param.to_string()).collect() };
+        assert_eq!(Privilege::for_command("KLINE", &params(&["*@host", ":reason"])), vec![Privilege::Kline]);
+        assert_eq!(Privilege::for_command("KLINE", &params(&["*@host", "ON", "*", ":reason"])), vec![Privilege::Kline, Privilege::RemoteBan]);
+        assert_eq!(Privilege::for_command("CONNECT", &params(&["hub.server", "6667", "leaf.server"])), vec![Privilege::Connect, Privilege::RemoteConnect]);
+        assert_eq!(Privilege::for_command("SQUIT", &params(&["leaf.server", ":reason"])), vec![Privilege::Squit]);
+        assert!(Privilege::for_command("DIE", &[]).is_empty());
+        assert!(Privilege::for_command("PRIVMSG", &params(&["#chan", "text"])).is_empty());
+    }
+
+    // Registers without waiting for the welcome, which banned clients never get
+    async fn send_registration(addr: SocketAddr, nickname: &str, username: &str) -> TestClient {
+        let mut client = TestClient::connect(addr).await.unwrap();
+        client.send_raw(&format!("NICK {}", nickname)).await.unwrap();
+        client.send_raw(&format!("USER {} 0 * :Test User", username)).await.unwrap();
+        client
+    }
+
+    #[tokio::test]
+    async fn test_connection_bans() {
+        let kline = |mask: &str, reason: &str, duration: i64, age: i64| KLine {
+            mask: mask.to_string(),
+            reason: reason.to_string(),
+            set_by: "admin".to_string(),
+            duration,
+            set_time: Utc::now() - chrono::Duration::seconds(age),
+        };
+        let mut config = test_config(PORT_BANS);
+        config.access.klines.push(kline("baduser@*", "No bad users", 0, 0));
+        config.access.klines.push(kline("*!olduser@127.0.0.1", "Served its time", 60, 120));
+        config.access.glines.push(GLine {
+            mask: "guser@127.0.0.*".to_string(),
+            reason: "Banned network-wide".to_string(),
+            set_by: "admin".to_string(),
+            duration: 3600,
+            set_time: Utc::now(),
+        });
+        config.access.dlines.push(DLine {
+            ip: "127.0.0.1".parse().unwrap(),
+            reason: "Expired".to_string(),
+            set_by: "admin".to_string(),
+            duration: 60,
+            set_time: Utc::now() - chrono::Duration::seconds(120),
+        });
+        start_server(Arc::new(Server::new(config).await.unwrap()), PORT_BANS).await;
+        let addr = SocketAddr::from(([127, 0, 0, 1], PORT_BANS));
+
+        let mut client = send_registration(addr, "badnick", "baduser").await;
+        client.expect_line(":test.server 465 badnick :You are banned from this server- No bad users").await.unwrap();
+        client.expect_line("ERROR :Closing Link: 127.0.0.1 (No bad users)").await.unwrap();
+
+        let mut client = send_registration(addr, "gnick", "guser").await;
+        client.expect_line("465 gnick :You are banned from this server- Banned network-wide").await.unwrap();
+        client.expect_line("ERROR :Closing Link").await.unwrap();
+
+        // Expired K-lines and D-lines no longer apply
+        let mut client = send_registration(addr, "oldnick", "olduser").await;
+        client.expect_line(" 001 oldnick ").await.unwrap();
+
+        // D-lines refuse the connection before registration
+        let mut config = test_config(PORT_DLINE);
+        config.access.dlines.push(DLine {
+            ip: "127.0.0.1".parse().unwrap(),
+            reason: "No localhost".to_string(),
+            set_by: "admin".to_string(),
+            duration: 0,
+            set_time: Utc::now(),
+        });
+        start_server(Arc::new(Server::new(config).await.unwrap()), PORT_DLINE).await;
+        let addr = SocketAddr::from(([127, 0, 0, 1], PORT_DLINE));
+        let mut client = TestClient::connect(addr).await.unwrap();
+        client.expect_line(":test.server 465 * :You are banned from this server- No localhost").await.unwrap();
+        client.expect_line("ERROR :Closing Link: 127.0.0.1 (No localhost)").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_cidr_bans() {
+        let net = |s: &str| s.parse::<IpNet>().unwrap();
+        let ip = |s: &str| s.parse::<std::net::IpAddr>().unwrap();
+        assert_eq!(net("10.1.2.3/16").to_string(), "10.1.0.0/16");
+        assert_eq!(net("2001:db8::1").to_string(), "2001:db8::1");
+        assert!("10.0.0.0/33".parse::<IpNet>().is_err());
+        assert!(net("2001:db8::/32").contains(ip("2001:db8:ffff::1")));
+        assert!(!net("2001:db8::/32").contains(ip("2001:db9::1")));
+        assert!(net("0.0.0.0/0").contains(ip("192.0.2.1")));
+        // IPv4-mapped IPv6 addresses are matched as IPv4
+        assert!(net("192.0.2.0/24").contains(ip("::ffff:192.0.2.7")));
+
+        let mut table = CidrTable::new();
+        table.insert(net("10.0.0.0/8"), "wide");
+        table.insert(net("10.1.0.0/16"), "narrow");
+        table.insert(net("2001:db8::/32"), "v6");
+        assert_eq!(table.matches(ip("10.1.2.3")), vec![&"narrow", &"wide"]);
+        assert_eq!(table.matches(ip("10.2.0.1")), vec![&"wide"]);
+        assert!(table.matches(ip("11.0.0.1")).is_empty());
+        assert_eq!(table.matches(ip("2001:db8::5")), vec![&"v6"]);
+
+        let mut config = test_config(0);
+        let dline = |ip: &str, reason: &str, duration: i64| DLine {
+            ip: net(ip),
+            reason: reason.to_string(),
+            set_by: "admin".to_string(),
+            duration,
+            set_time: Utc::now() - chrono::Duration::seconds(120),
+        };
+        config.access.dlines.push(dline("198.51.100.0/24", "Bad network", 0));
+        config.access.dlines.push(dline("198.51.100.128/25", "Expired half", 60));
+        config.access.dlines.push(dline("2001:db8::/48", "Bad v6 network", 0));
+        config.access.elines.push(ELine { ip: net("198.51.100.7"), reason: "Office".to_string() });
+        config.access.klines.push(KLine {
+            mask: "bad@203.0.113.0/28".to_string(),
+            reason: "Bad users".to_string(),
+            set_by: "admin".to_string(),
+            duration: 0,
+            set_time: Utc::now(),
+        });
+        let server = Server::new(config).await.unwrap();
+
+        assert_eq!(server.find_dline(ip("198.51.100.200")).await.unwrap().reason, "Bad network");
+        assert_eq!(server.find_dline(ip("2001:db8:0:1::1")).await.unwrap().reason, "Bad v6 network");
+        assert!(server.find_dline(ip("2001:db8:1::1")).await.is_none());
+        assert!(server.find_dline(ip("198.51.100.7")).await.is_none());
+
+        assert_eq!(server.find_user_ban("nick", "bad", "host.example", ip("203.0.113.9")).await.as_deref(), Some("Bad users"));
+        assert!(server.find_user_ban("nick", "bad", "host.example", ip("203.0.113.16")).await.is_none());
+        assert!(server.find_user_ban("nick", "good", "host.example", ip("203.0.113.9")).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ban_expiry() {
+        let db_path = std::env::temp_dir().join(format!("ircd-rs-expiry-{}.json", PORT_EXPIRY));
+        std::fs::remove_file(&db_path).ok();
+        let mut config = test_config(PORT_EXPIRY);
+        config.database = Some(DatabaseConfig {
+            path: db_path.to_string_lossy().into_owned(),
+            persist_lines: true,
+        });
+        // Lapsed while the server was down
+        config.access.dlines.push(DLine {
+            ip: "192.0.2.0/24".parse().unwrap(),
+            reason: "Old ban".to_string(),
+            set_by: "admin".to_string(),
+            duration: 60,
+            set_time: Utc::now() - chrono::Duration::seconds(120),
+        });
+        let server = Arc::new(Server::new(config.clone()).await.unwrap());
+        start_server(Arc::clone(&server), PORT_EXPIRY).await;
+        server.start_expiry();
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], PORT_EXPIRY));
+        let mut oper = TestClient::connect(addr).await.unwrap();
+        oper.register("opernick", "operuser", "test.com").await.unwrap();
+        oper.send_raw("OPER testoper operpass").await.unwrap();
+        oper.expect_line(" 381 ").await.unwrap();
+        assert!(server.get_dlines().await.is_empty());
+
+        // Each lapses a moment from now
+        server.add_kline(KLine {
+            mask: "temp@*".to_string(),
+            reason: "Short ban".to_string(),
+            set_by: "admin".to_string(),
+            duration: 60,
+            set_time: Utc::now() - chrono::Duration::seconds(59),
+        }).await.unwrap();
+        server.add_resv(Resv {
+            mask: "badnick".to_string(),
+            reason: "Reserved".to_string(),
+            set_by: "admin".to_string(),
+            duration: 60,
+            set_time: Utc::now() - chrono::Duration::seconds(58),
+        }).await.unwrap();
+        assert!(server.find_resv("badnick").await.is_some());
+        assert_eq!(Database::new(&db_path).await.unwrap().get_resvs().await.len(), 1);
+
+        oper.expect_line(":test.server NOTICE opernick :*** Notice -- Temporary K-line for [temp@*] expired").await.unwrap();
+        oper.expect_line(":test.server NOTICE opernick :*** Notice -- Temporary RESV for [badnick] expired").await.unwrap();
+        assert!(server.get_klines().await.is_empty());
+        assert!(server.find_resv("badnick").await.is_none());
+
+        // The database no longer has the expired line either
+        let restarted = Server::new(config).await.unwrap();
+        assert!(restarted.get_klines().await.is_empty());
+        assert!(Database::new(&db_path).await.unwrap().get_resvs().await.is_empty());
+        std::fs::remove_file(&db_path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_ban_removal_persistence() {
+        let db_path = std::env::temp_dir().join("ircd-rs-ban-removal.json");
+        std::fs::remove_file(&db_path).ok();
+        let mut config = test_config(0);
+        config.database = Some(DatabaseConfig {
+            path: db_path.to_string_lossy().into_owned(),
+            persist_lines: true,
+        });
+
+        // Bans are removed by mask whatever its case, from the database as well
+        let server = Server::new(config.clone()).await.unwrap();
+        server.add_kline(KLine {
+            mask: "*@Bad.Host".to_string(),
+            reason: "Bad".to_string(),
+            set_by: "admin".to_string(),
+            duration: 0,
+            set_time: Utc::now(),
+        }).await.unwrap();
+        server.add_gline(GLine {
+            mask: "*@Worse.Host".to_string(),
+            reason: "Worse".to_string(),
+            set_by: "admin".to_string(),
+            duration: 0,
+            set_time: Utc::now(),
+        }).await.unwrap();
+        assert!(server.remove_kline("*@bad.host".to_string()).await.unwrap());
+        assert!(server.remove_gline("*@WORSE.HOST").await.unwrap());
+        server.add_resv(Resv {
+            mask: "BadNick".to_string(),
+            reason: "Reserved".to_string(),
+            set_by: "admin".to_string(),
+            duration: 0,
+            set_time: Utc::now(),
+        }).await.unwrap();
+        assert!(server.remove_resv("BADNICK").await.unwrap());
+        drop(server);
+
+        let restarted = Server::new(config).await.unwrap();
+        assert!(restarted.get_klines().await.is_empty());
+        assert!(restarted.get_glines().await.is_empty());
+        assert!(restarted.find_resv("badnick").await.is_none());
+        std::fs::remove_file(&db_path).ok();
+    }
+
+    #[tokio::test]
+    async fn test_database_persistence() {
+        let db_path = std::env::temp_dir().join(format!("ircd-rs-persistence-{}.json", PORT_PERSISTENCE));
+        std::fs::remove_file(&db_path).ok();
+
+        // Written before the schema was versioned, when O-lines had no name
+        std::fs::write(&db_path, r#"{
+            "klines": [],
+            "dlines": [{ "ip": "198.51.100.7", "reason": "Old ban", "set_by": "admin" }],
+            "glines": [],
+            "ilines": [],
+            "olines": [{ "mask": "*!*@*", "password": "legacypass", "flags": ["kill"] }],
+            "ulines": [],
+            "alines": []
+        }"#).unwrap();
+
+        let db = Database::new(&db_path).await.unwrap();
+        let olines = db.get_olines().await;
+        assert_eq!(olines.len(), 1);
+        assert_eq!(olines[0].name, "*!*@*");
+        assert_eq!(db.get_dlines().await.len(), 1);
+        // The upgraded file is written back, replacing the old one whole
+        let data: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(&db_path).unwrap()).unwrap();
+        assert_eq!(data["version"], 1);
+        assert_eq!(data["olines"][0]["name"], "*!*@*");
+        let mut temp = db_path.clone().into_os_string();
+        temp.push(".tmp");
+        assert!(!std::path::Path::new(&temp).exists());
+
+        db.add_oline(OLine {
+            name: "dbop".to_string(),
+            mask: "*!*@*".to_string(),
+            password: "dbpass".to_string(),
+            encrypted: false,
+            flags: vec!["kill".to_string()],
+            privset: None,
+        }).await.unwrap();
+        db.add_iline(ILine {
+            mask: "*@*".to_string(),
+            password: None,
+            class: "users".to_string(),
+            max_connections: 10,
+        }).await.unwrap();
+        db.add_uline(ULine { server: "services.test".to_string(), flags: vec![] }).await.unwrap();
+        db.add_aline(ALine {
+            mask: "*@trusted".to_string(),
+            password: "authpass".to_string(),
+            class: "users".to_string(),
+        }).await.unwrap();
+        // Adding again by the same key replaces the line
+        db.add_iline(ILine {
+            mask: "*@*".to_string(),
+            password: None,
+            class: "users".to_string(),
+            max_connections: 20,
+        }).await.unwrap();
+        db.remove_oline("*!*@*", "*!*@*").await.unwrap();
+        db.add_resv(Resv {
+            mask: "#reserved".to_string(),
+            reason: "Staff only".to_string(),
+            set_by: "admin".to_string(),
+            duration: 0,
+            set_time: Utc::now(),
+        }).await.unwrap();
+        db.add_eline(ELine { ip: "203.0.113.0/24".parse().unwrap(), reason: "Office".to_string() }).await.unwrap();
+        db.add_eline(ELine { ip: "192.0.2.1".parse().unwrap(), reason: "Gone".to_string() }).await.unwrap();
+        db.remove_eline("192.0.2.1".parse().unwrap()).await.unwrap();
+        db.add_kline(KLine {
+            mask: "*@dup.example".to_string(),
+            reason: "From the database".to_string(),
+            set_by: "admin".to_string(),
+            duration: 0,
+            set_time: Utc::now(),
+        }).await.unwrap();
+        drop(db);
+
+        let db = Database::new(&db_path).await.unwrap();
+        let olines = db.get_olines().await;
+        assert_eq!(olines.len(), 1);
+        assert_eq!(olines[0].name, "dbop");
+        let ilines = db.get_ilines().await;
+        assert_eq!(ilines.len(), 1);
+        assert_eq!(ilines[0].max_connections, 20);
+        assert_eq!(db.get_ulines().await.len(), 1);
+        assert_eq!(db.get_alines().await.len(), 1);
+        db.remove_uline("services.test").await.unwrap();
+        db.remove_aline("*@trusted").await.unwrap();
+        db.remove_iline("*@*").await.unwrap();
+        assert!(db.get_ulines().await.is_empty());
+        assert_eq!(db.get_resvs().await.len(), 1);
+        let elines = db.get_elines().await;
+        assert_eq!(elines.len(), 1);
+        assert_eq!(elines[0].reason, "Office");
+        drop(db);
+
+        // Lines from the database are live once the server starts, replacing config lines they match
+        let mut config = test_config(PORT_PERSISTENCE);
+        config.access.klines.push(KLine {
+            mask: "*@DUP.example".to_string(),
+            reason: "From the config".to_string(),
+            set_by: "config".to_string(),
+            duration: 0,
+            set_time: Utc::now(),
+        });
+        config.database = Some(DatabaseConfig {
+            path: db_path.to_string_lossy().into_owned(),
+            persist_lines: true,
+        });
+        let server = Arc::new(Server::new(config).await.unwrap());
+        assert_eq!(server.find_olines("dbop", "nick!user@host").await.len(), 1);
+        let klines = server.get_klines().await;
+        assert_eq!(klines.len(), 1);
+        assert_eq!(klines[0].reason, "From the database");
+        assert_eq!(server.find_resv("#Reserved").await.unwrap().reason, "Staff only");
+        assert!(server.is_exempt("203.0.113.9".parse().unwrap()).await);
+        start_server(Arc::clone(&server), PORT_PERSISTENCE).await;
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], PORT_PERSISTENCE));
+        let mut oper = TestClient::connect(addr).await.unwrap();
+        oper.register("dbnick", "dbuser", "test.com").await.unwrap();
+        oper.send_raw("OPER dbop dbpass").await.unwrap();
+        oper.expect_line(" 381 ").await.unwrap();
+
+        // A file that can't be read is refused rather than replaced with an empty database
+        std::fs::write(&db_path, "{ not json").unwrap();
+        assert!(Database::new(&db_path).await.is_err());
+        assert_eq!(std::fs::read_to_string(&db_path).unwrap(), "{ not json");
+        std::fs::write(&db_path, r#"{ "version": 99 }"#).unwrap();
+        assert!(Database::new(&db_path).await.is_err());
+        std::fs::remove_file(&db_path).ok();
+    }
+}
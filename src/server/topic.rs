@@ -0,0 +1,136 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use tokio::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::error::{IrcError, IrcResult};
+use crate::link::ServerLink;
+use crate::server::Server;
+use crate::ts6::TS6Message;
+
+impl Server {
+    pub(crate) async fn handle_server_tb(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // :sid TB channel topicTS [setter] :topic
+        if msg.params.len() < 3 {
+            return Err(IrcError::Protocol("Invalid TB parameters".into()));
+        }
+
+        let channel_name = &msg.params[0];
+        let topic_ts = msg.params[1].parse::<i64>()
+            .map_err(|_| IrcError::Protocol("Invalid TB topicTS".into()))?;
+        let topic = &msg.params[msg.params.len() - 1];
+        let setter = match msg.params.len() {
+            3 => self.source_server_name(link, msg.source.as_deref()).await.unwrap_or_default(),
+            _ => msg.params[2].clone(),
+        };
+
+        let Some(channel) = self.get_channel(channel_name).await else {
+            debug!("TB for unknown channel {}", channel_name);
+            return Ok(());
+        };
+
+        // Only an older, different topic replaces the current one
+        {
+            let mut channel = channel.write().await;
+            let current = channel.get_topic();
+            let older = topic_ts < channel.topic_ts() && current.as_deref() != Some(topic.as_str());
+            if current.is_some() && !older {
+                return Ok(());
+            }
+            channel.set_topic_at(topic.clone(), setter.clone(), timestamp(topic_ts));
+        }
+
+        self.announce_topic(channel_name, &setter, topic).await?;
+        self.propagate_topic(link, &msg, None).await;
+        Ok(())
+    }
+
+    pub(crate) async fn handle_server_etb(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // :source ETB channelTS channel topicTS setter [extensions...] :topic
+        if msg.params.len() < 5 {
+            return Err(IrcError::Protocol("Invalid ETB parameters".into()));
+        }
+
+        let channel_ts = msg.params[0].parse::<u64>()
+            .map_err(|_| IrcError::Protocol("Invalid ETB channelTS".into()))?;
+        let channel_name = &msg.params[1];
+        let topic_ts = msg.params[2].parse::<i64>()
+            .map_err(|_| IrcError::Protocol("Invalid ETB topicTS".into()))?;
+        let setter = &msg.params[3];
+        let topic = &msg.params[msg.params.len() - 1];
+
+        let Some(channel) = self.get_channel(channel_name).await else {
+            debug!("ETB for unknown channel {}", channel_name);
+            return Ok(());
+        };
+
+        let changed = {
+            let mut channel = channel.write().await;
+            let current = channel.get_topic();
+            let accept = current.is_none()
+                || channel_ts < channel.created_at()
+                || (channel_ts == channel.created_at() && topic_ts > channel.topic_ts());
+            if !accept {
+                return Ok(());
+            }
+            channel.set_topic_at(topic.clone(), setter.clone(), timestamp(topic_ts));
+            current.as_deref().unwrap_or_default() != topic.as_str()
+        };
+
+        // A new topicTS alone isn't shown to local users
+        if changed {
+            self.announce_topic(channel_name, setter, topic).await?;
+        }
+
+        // Servers without ETB only learn of topic text changes, through TB
+        let tb = TS6Message::with_source(
+            msg.source.clone().unwrap_or_default(),
+            "TB".to_string(),
+            vec![channel_name.clone(), topic_ts.to_string(), setter.clone(), topic.clone()],
+        );
+        self.propagate_topic(link, &msg, changed.then_some(&tb)).await;
+        Ok(())
+    }
+
+    // Shows a topic change from the network to local members
+    async fn announce_topic(&self, channel_name: &str, setter: &str, topic: &str) -> IrcResult<()> {
+        let topic_msg = TS6Message::with_source(
+            setter.to_string(),
+            "TOPIC".to_string(),
+            vec![channel_name.to_string(), topic.to_string()],
+        );
+        self.broadcast_to_channel(channel_name, &topic_msg, None).await
+    }
+
+    // Passes TB on to TB servers, or ETB on to EOPMOD servers with a TB fallback for the others
+    async fn propagate_topic(&self, from: &Arc<Mutex<ServerLink>>, msg: &TS6Message, tb_fallback: Option<&TS6Message>) {
+        let links: Vec<_> = self.linked_servers.read().await.values().cloned().collect();
+        for link in links {
+            if Arc::ptr_eq(from, &link) {
+                continue;
+            }
+            let link = link.lock().await;
+            if !link.is_registered() {
+                continue;
+            }
+
+            let message = if msg.command == "TB" {
+                Some(msg).filter(|_| link.has_capability("TB"))
+            } else if link.has_capability("EOPMOD") {
+                Some(msg)
+            } else {
+                tb_fallback.filter(|_| link.has_capability("TB"))
+            };
+            if let Some(message) = message {
+                if let Err(e) = link.send_message(message).await {
+                    warn!("Failed to send to server {}: {}", link.name(), e);
+                }
+            }
+        }
+    }
+}
+
+fn timestamp(ts: i64) -> DateTime<Utc> {
+    DateTime::from_timestamp(ts, 0).unwrap_or_else(Utc::now)
+}
@@ -0,0 +1,177 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use tracing::{debug, info};
+
+use crate::error::{IrcError, IrcResult};
+use crate::link::ServerLink;
+use crate::server::Server;
+use crate::ts6::{is_valid_sid, TS6Message};
+
+/// A server elsewhere on the network, introduced by a link, SID or SERVER.
+#[derive(Clone, Debug)]
+pub struct RemoteServer {
+    pub name: String,
+    pub sid: Option<String>, // TS5 servers such as jupes have no SID
+    pub description: String,
+    pub hopcount: u32,
+    pub uplink: String, // Name of the server this one is connected to
+}
+
+impl Server {
+    pub(crate) async fn add_remote_server(&self, server: RemoteServer) {
+        info!("Server {} ({}) introduced by {}: {}",
+              server.name, server.sid.as_deref().unwrap_or("no SID"), server.uplink, server.description);
+        let mut servers = self.servers.write().await;
+        servers.insert(server.name.to_lowercase(), server);
+    }
+
+    pub(crate) async fn find_server_by_name(&self, name: &str) -> Option<RemoteServer> {
+        let servers = self.servers.read().await;
+        servers.get(&name.to_lowercase()).cloned()
+    }
+
+    pub(crate) async fn find_server_by_sid(&self, sid: &str) -> Option<RemoteServer> {
+        let servers = self.servers.read().await;
+        servers.values().find(|server| server.sid.as_deref() == Some(sid)).cloned()
+    }
+
+    // Every known remote server, nearest first so uplinks come before the servers behind them
+    pub(crate) async fn get_remote_servers(&self) -> Vec<RemoteServer> {
+        let servers = self.servers.read().await;
+        let mut servers: Vec<_> = servers.values().cloned().collect();
+        servers.sort_by(|a, b| a.hopcount.cmp(&b.hopcount).then_with(|| a.name.cmp(&b.name)));
+        servers
+    }
+
+    pub(crate) async fn remove_remote_server(&self, name: &str) {
+        let mut servers = self.servers.write().await;
+        servers.remove(&name.to_lowercase());
+    }
+
+    // The named server and every server reached through it
+    pub(crate) async fn get_server_subtree(&self, name: &str) -> Vec<RemoteServer> {
+        let servers = self.get_remote_servers().await;
+        let mut subtree: Vec<RemoteServer> = servers.iter()
+            .filter(|server| server.name.eq_ignore_ascii_case(name))
+            .cloned()
+            .collect();
+
+        // Servers are ordered by hopcount, so each uplink is visited before its leaves
+        for server in &servers {
+            if subtree.iter().any(|parent| parent.name.eq_ignore_ascii_case(&server.uplink)) {
+                subtree.push(server.clone());
+            }
+        }
+        subtree
+    }
+
+    // The directly linked server through which the given server is reached
+    pub(crate) async fn route_to_server(&self, name: &str) -> Option<Arc<Mutex<ServerLink>>> {
+        let servers = self.servers.read().await;
+        let mut current = servers.get(&name.to_lowercase())?;
+
+        // Walk uplinks towards us; the hop limit guards against a corrupt topology
+        for _ in 0..servers.len() {
+            if current.uplink.eq_ignore_ascii_case(&self.config.server.name) {
+                let link_name = current.name.clone();
+                drop(servers);
+                return self.get_linked_server(&link_name).await;
+            }
+            current = servers.get(&current.uplink.to_lowercase())?;
+        }
+        None
+    }
+
+    // Name of the server a message came from, given its SID or name source
+    pub(crate) async fn source_server_name(&self, link: &Arc<Mutex<ServerLink>>, source: Option<&str>) -> Option<String> {
+        match source {
+            None => Some(link.lock().await.name().to_string()),
+            Some(source) if is_valid_sid(source) => self.find_server_by_sid(source).await.map(|server| server.name),
+            Some(source) => self.find_server_by_name(source).await.map(|server| server.name),
+        }
+    }
+
+    pub(crate) async fn handle_server_sid(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // :uplink SID name hopcount sid :description
+        if msg.params.len() < 4 {
+            return Err(IrcError::Protocol("Invalid SID parameters".into()));
+        }
+
+        let name = &msg.params[0];
+        let hopcount = msg.params[1].parse::<u32>().unwrap_or(1);
+        let sid = &msg.params[2];
+        let description = &msg.params[3];
+
+        if !is_valid_sid(sid) {
+            return Err(link.lock().await.close(&format!("Invalid SID {} for {}", sid, name)).await);
+        }
+        if !self.introduce_remote_server(link, &msg, name, Some(sid), hopcount, description).await? {
+            return Ok(());
+        }
+
+        let propagated = TS6Message::with_source(
+            msg.source.clone().unwrap_or_default(),
+            "SID".to_string(),
+            vec![name.clone(), (hopcount + 1).to_string(), sid.clone(), description.clone()],
+        );
+        self.send_to_servers(&propagated, Some(link)).await;
+        Ok(())
+    }
+
+    pub(crate) async fn handle_remote_server(&self, link: &Arc<Mutex<ServerLink>>, msg: TS6Message) -> IrcResult<()> {
+        // :uplink SERVER name hopcount :description
+        let name = &msg.params[0];
+        let hopcount = msg.params[1].parse::<u32>().unwrap_or(1);
+        let description = &msg.params[2];
+
+        if !self.introduce_remote_server(link, &msg, name, None, hopcount, description).await? {
+            return Ok(());
+        }
+
+        let propagated = TS6Message::with_source(
+            msg.source.clone().unwrap_or_default(),
+            "SERVER".to_string(),
+            vec![name.clone(), (hopcount + 1).to_string(), description.clone()],
+        );
+        self.send_to_servers(&propagated, Some(link)).await;
+        Ok(())
+    }
+
+    // Records a server introduced behind a link, dropping the link if the server is already known.
+    // Returns whether the server was added and should be propagated.
+    async fn introduce_remote_server(
+        &self,
+        link: &Arc<Mutex<ServerLink>>,
+        msg: &TS6Message,
+        name: &str,
+        sid: Option<&str>,
+        hopcount: u32,
+        description: &str,
+    ) -> IrcResult<bool> {
+        let Some(uplink) = self.source_server_name(link, msg.source.as_deref()).await else {
+            debug!("{} for {} from unknown server {:?}", msg.command, name, msg.source);
+            return Ok(false);
+        };
+
+        let name_taken = name.eq_ignore_ascii_case(&self.config.server.name)
+            || self.find_server_by_name(name).await.is_some();
+        if name_taken {
+            return Err(link.lock().await.close(&format!("Server {} already exists", name)).await);
+        }
+        if let Some(sid) = sid {
+            if sid == self.config.server.sid || self.find_server_by_sid(sid).await.is_some() {
+                return Err(link.lock().await.close(&format!("SID {} already exists", sid)).await);
+            }
+        }
+
+        self.add_remote_server(RemoteServer {
+            name: name.to_string(),
+            sid: sid.map(str::to_string),
+            description: description.to_string(),
+            hopcount,
+            uplink,
+        }).await;
+        Ok(true)
+    }
+}
@@ -1,41 +1,246 @@
+use std::net::IpAddr;
+
+use tracing::info;
+
+use crate::cidr::IpNet;
 use crate::client::Client;
-use crate::config::KLine;
+use crate::config::{DLine, Expiring, GLine, KLine, Resv};
 use crate::database::Database;
-use crate::server::Server;
+use crate::server::{Server, TimedBan};
 
 impl Server {
     pub async fn has_oline(&self, client: &Client) -> bool {
         let mask = client.get_mask();
-        self.config.access.olines.iter()
+        self.olines.read().await.iter()
             .any(|oline| self.mask_match(&mask, &oline.mask))
     }
 
-    pub async fn is_host_klined(&self, host: &str) -> bool {
-        self.config.access.klines.iter()
-            .any(|k| self.mask_match(host, &k.mask))
+    // Whether an E-line exempts an address from bans
+    pub(crate) async fn is_exempt(&self, ip: IpAddr) -> bool {
+        !self.elines.read().await.matches(ip).is_empty()
     }
 
-    pub(crate) async fn load_persisted_lines(&self, db: &Database) -> Result<(), Box<dyn std::error::Error>> {
-        // Load lines from database and merge with config
-        let mut access = self.config.access.clone();
+    // The most specific D-line covering a connecting address, checked before anything is read
+    pub(crate) async fn find_dline(&self, ip: IpAddr) -> Option<DLine> {
+        if self.is_exempt(ip).await {
+            return None;
+        }
+        self.dlines.read().await.matches(ip).into_iter()
+            .find(|dline| !dline.is_expired())
+            .cloned()
+    }
 
-        // Load and merge K-lines
-        let db_klines = db.get_klines().await;
-        access.klines.extend(db_klines);
+    // Reason for the first K-line or G-line matching a registering user by host or IP
+    pub(crate) async fn find_user_ban(&self, nickname: &str, username: &str, host: &str, ip: IpAddr) -> Option<String> {
+        if self.is_exempt(ip).await {
+            return None;
+        }
+        let by_host = format!("{}!{}@{}", nickname, username, host).to_lowercase();
+        let by_ip = format!("{}!{}@{}", nickname, username, ip).to_lowercase();
+        let matches = |mask: &str| {
+            let mask = full_ban_mask(mask);
+            // A host part in CIDR notation is matched against the address as a network
+            if let Some((user_mask, net)) = mask.rsplit_once('@')
+                .and_then(|(user_mask, host_mask)| Some((user_mask, host_mask.parse::<IpNet>().ok()?)))
+            {
+                let (user, _) = by_ip.rsplit_once('@').unwrap_or_default();
+                return net.contains(ip) && self.mask_match(user, user_mask);
+            }
+            self.mask_match(&by_host, &mask) || self.mask_match(&by_ip, &mask)
+        };
 
-        // Load and merge other line types...
+        let kline = self.klines.read().await.iter()
+            .find(|kline| !kline.is_expired() && matches(&kline.mask))
+            .map(|kline| kline.reason.clone());
+        match kline {
+            Some(reason) => Some(reason),
+            None => self.glines.read().await.iter()
+                .find(|gline| !gline.is_expired() && matches(&gline.mask))
+                .map(|gline| gline.reason.clone()),
+        }
+    }
+
+    pub(crate) async fn load_persisted_lines(&self, db: &Database) -> Result<(), Box<dyn std::error::Error>> {
+        // Lines added at runtime join those from the config, replacing any with the same mask or name
+        merge_lines(&mut *self.klines.write().await, db.get_klines().await, |a, b| a.mask.eq_ignore_ascii_case(&b.mask));
+        merge_lines(&mut *self.glines.write().await, db.get_glines().await, |a, b| a.mask.eq_ignore_ascii_case(&b.mask));
+        let mut dlines = self.dlines.write().await;
+        for dline in db.get_dlines().await {
+            dlines.insert(dline.ip, dline);
+        }
+        drop(dlines);
+        merge_lines(&mut *self.ilines.write().await, db.get_ilines().await, |a, b| a.mask.eq_ignore_ascii_case(&b.mask));
+        merge_lines(&mut *self.olines.write().await, db.get_olines().await, |a, b| a.name == b.name && a.mask == b.mask);
+        merge_lines(&mut *self.ulines.write().await, db.get_ulines().await, |a, b| a.server.eq_ignore_ascii_case(&b.server));
+        merge_lines(&mut *self.alines.write().await, db.get_alines().await, |a, b| a.mask.eq_ignore_ascii_case(&b.mask));
+        merge_lines(&mut *self.resvs.write().await, db.get_resvs().await, |a, b| a.mask.eq_ignore_ascii_case(&b.mask));
+        let mut elines = self.elines.write().await;
+        for eline in db.get_elines().await {
+            elines.insert(eline.ip, eline);
+        }
         Ok(())
     }
 
     pub async fn add_kline(&self, kline: KLine) -> Result<(), Box<dyn std::error::Error>> {
-        let mut access = self.config.access.clone();
-        access.klines.push(kline);
+        let mut klines = self.klines.write().await;
+        klines.retain(|existing| !existing.mask.eq_ignore_ascii_case(&kline.mask));
+        klines.push(kline.clone());
+        drop(klines);
+        self.schedule_expiry(TimedBan::KLine(kline.mask.clone()), &kline).await;
+        if let Some(db) = &self.database {
+            db.add_kline(kline).await?;
+        }
+        Ok(())
+    }
+
+    // Returns whether there was a K-line for the mask
+    pub async fn remove_kline(&self, mask: String) -> Result<bool, Box<dyn std::error::Error>> {
+        let mut klines = self.klines.write().await;
+        let before = klines.len();
+        klines.retain(|k| !k.mask.eq_ignore_ascii_case(&mask));
+        let removed = klines.len() < before;
+        drop(klines);
+        if let Some(db) = &self.database {
+            db.remove_kline(&mask).await?;
+        }
+        Ok(removed)
+    }
+
+    pub(crate) async fn add_dline(&self, dline: DLine) -> Result<(), Box<dyn std::error::Error>> {
+        self.dlines.write().await.insert(dline.ip, dline.clone());
+        self.schedule_expiry(TimedBan::DLine(dline.ip), &dline).await;
+        if let Some(db) = &self.database {
+            db.add_dline(dline).await?;
+        }
         Ok(())
     }
 
-    pub async fn remove_kline(&self, mask: String) -> Result<(), Box<dyn std::error::Error>> {
-        let mut access = self.config.access.clone();
-        access.klines.retain(|k| k.mask != mask);
+    pub(crate) async fn remove_dline(&self, ip: IpNet) -> Result<bool, Box<dyn std::error::Error>> {
+        let removed = self.dlines.write().await.remove(&ip).is_some();
+        if let Some(db) = &self.database {
+            db.remove_dline(ip).await?;
+        }
+        Ok(removed)
+    }
+
+    pub(crate) async fn add_gline(&self, gline: GLine) -> Result<(), Box<dyn std::error::Error>> {
+        let mut glines = self.glines.write().await;
+        glines.retain(|existing| !existing.mask.eq_ignore_ascii_case(&gline.mask));
+        glines.push(gline.clone());
+        drop(glines);
+        self.schedule_expiry(TimedBan::GLine(gline.mask.clone()), &gline).await;
+        if let Some(db) = &self.database {
+            db.add_gline(gline).await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn remove_gline(&self, mask: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let mut glines = self.glines.write().await;
+        let before = glines.len();
+        glines.retain(|g| !g.mask.eq_ignore_ascii_case(mask));
+        let removed = glines.len() < before;
+        drop(glines);
+        if let Some(db) = &self.database {
+            db.remove_gline(mask).await?;
+        }
+        Ok(removed)
+    }
+
+    // Active lines for STATS, as the config and operators left them
+    pub(crate) async fn get_klines(&self) -> Vec<KLine> {
+        self.klines.read().await.iter().filter(|k| !k.is_expired()).cloned().collect()
+    }
+
+    pub(crate) async fn get_dlines(&self) -> Vec<DLine> {
+        self.dlines.read().await.values().filter(|d| !d.is_expired()).cloned().collect()
+    }
+
+    pub(crate) async fn get_glines(&self) -> Vec<GLine> {
+        self.glines.read().await.iter().filter(|g| !g.is_expired()).cloned().collect()
+    }
+
+    // Disconnects local users covered by a ban added since they connected, returning their masks.
+    // Every client is locked in turn, so this must not run from a client's own handler.
+    pub(crate) async fn disconnect_banned(&self) -> Vec<String> {
+        let mut banned = Vec::new();
+        for client in self.get_clients().await {
+            let mut client = client.lock().await;
+            let reason = match self.find_dline(client.get_ip()).await {
+                Some(dline) => Some(dline.reason),
+                None => match (client.get_nickname(), client.get_username()) {
+                    (Some(nickname), Some(username)) => {
+                        self.find_user_ban(nickname, username, client.get_hostname(), client.get_ip()).await
+                    }
+                    _ => None,
+                },
+            };
+            let Some(reason) = reason else {
+                continue;
+            };
+
+            info!("Disconnecting banned client {}: {}", client.get_mask(), reason);
+            if client.is_registered() {
+                if let Some(nickname) = client.get_nickname().cloned() {
+                    self.unregister_nickname(&nickname).await;
+                }
+            }
+            client.reject_banned(&reason).await;
+            banned.push(client.get_mask());
+        }
+        banned
+    }
+
+    pub async fn add_resv(&self, resv: Resv) -> Result<(), Box<dyn std::error::Error>> {
+        let mut resvs = self.resvs.write().await;
+        resvs.retain(|existing| !existing.mask.eq_ignore_ascii_case(&resv.mask));
+        resvs.push(resv.clone());
+        drop(resvs);
+        self.schedule_expiry(TimedBan::Resv(resv.mask.clone()), &resv).await;
+        if let Some(db) = &self.database {
+            db.add_resv(resv).await?;
+        }
         Ok(())
     }
-}
\ No newline at end of file
+
+    // Returns whether there was a RESV for the mask
+    pub async fn remove_resv(&self, mask: &str) -> Result<bool, Box<dyn std::error::Error>> {
+        let mut resvs = self.resvs.write().await;
+        let before = resvs.len();
+        resvs.retain(|resv| !resv.mask.eq_ignore_ascii_case(mask));
+        let removed = resvs.len() < before;
+        drop(resvs);
+        if let Some(db) = &self.database {
+            db.remove_resv(mask).await?;
+        }
+        Ok(removed)
+    }
+
+    // The RESV covering a nickname or channel name, if any
+    pub async fn find_resv(&self, name: &str) -> Option<Resv> {
+        let resvs = self.resvs.read().await;
+        resvs.iter()
+            .find(|resv| !resv.is_expired() && self.mask_match(&name.to_lowercase(), &resv.mask.to_lowercase()))
+            .cloned()
+    }
+}
+
+// Adds persisted lines to those already loaded, each replacing any line it is the same as
+fn merge_lines<T>(lines: &mut Vec<T>, persisted: Vec<T>, same: impl Fn(&T, &T) -> bool) {
+    for line in persisted {
+        lines.retain(|existing| !same(existing, &line));
+        lines.push(line);
+    }
+}
+
+// Widens a ban on a host or user@host to a full nick!user@host mask
+fn full_ban_mask(mask: &str) -> String {
+    let mask = mask.to_lowercase();
+    if mask.contains('!') {
+        mask
+    } else if mask.contains('@') {
+        format!("*!{}", mask)
+    } else {
+        format!("*!*@{}", mask)
+    }
+}
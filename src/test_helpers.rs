@@ -21,6 +21,7 @@ pub(crate) mod test {
             network: crate::config::Network {
                 name: "TestNet".to_string(),
                 links: vec![],
+                flatten_links: false,
             },
             limits: crate::config::Limits {
                 max_clients: 100,
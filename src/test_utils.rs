@@ -6,7 +6,7 @@ use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::{tcp::{OwnedReadHalf, OwnedWriteHalf}, TcpStream};
 use tokio::time::{Duration, sleep};
 
-use crate::config::ServerConfig;
+use crate::config::{ServerConfig, ServerLinkConfig};
 use crate::error::{IrcError, IrcResult};
 use crate::server::Server;
 
@@ -24,16 +24,21 @@ pub struct TestClient {
 impl TestClient {
     pub async fn connect(addr: SocketAddr) -> IrcResult<Self> {
         let stream = TcpStream::connect(addr).await?;
+        Ok(Self::from_stream(stream))
+    }
+
+    // Wrap an accepted connection, e.g. when playing the remote end of a server link
+    pub fn from_stream(stream: TcpStream) -> Self {
         let (read, write) = stream.into_split();
 
-        Ok(Self {
+        Self {
             reader: BufReader::new(read),
             writer: write,
             nickname: String::new(),
             username: String::new(),
             hostname: String::new(),
             capabilities: HashSet::new(),
-        })
+        }
     }
 
     // Registration methods
@@ -256,6 +261,20 @@ impl TestClient {
         }
     }
 
+    pub async fn expect_line(&mut self, pattern: &str) -> IrcResult<String> {
+        let timeout = Duration::from_secs(5);
+        loop {
+            let msg = tokio::time::timeout(timeout, self.read_message()).await
+                .map_err(|_| IrcError::Protocol(format!("Timed out waiting for {}", pattern)))??;
+            if msg.is_empty() {
+                return Err(IrcError::Protocol(format!("Connection closed waiting for {}", pattern)));
+            }
+            if msg.contains(pattern) {
+                return Ok(msg);
+            }
+        }
+    }
+
     pub async fn expect_join(&mut self, channel: &str, nickname: &str) -> IrcResult<()> {
         loop {
             let msg = self.read_message().await?;
@@ -282,6 +301,42 @@ pub async fn setup_test_server(port: u16) -> (Arc<Server>, SocketAddr) {
     (server, addr)
 }
 
+// Two servers on port and port + 1, the first ("hub.server") connecting to the second ("leaf.server")
+#[cfg(test)]
+pub async fn setup_linked_servers(port: u16) -> (Arc<Server>, Arc<Server>) {
+    let mut hub_config = test_config(port);
+    hub_config.server.name = "hub.server".to_string();
+    hub_config.links.push(test_link_config("leaf.server", "002", port + 1));
+
+    let mut leaf_config = test_config(port + 1);
+    leaf_config.server.name = "leaf.server".to_string();
+    leaf_config.server.sid = "002".to_string();
+    leaf_config.links.push(test_link_config("hub.server", "001", port));
+
+    let hub = Arc::new(Server::new(hub_config).await.unwrap());
+    let leaf = Arc::new(Server::new(leaf_config).await.unwrap());
+    for server in [&hub, &leaf] {
+        let server = Arc::clone(server);
+        tokio::spawn(async move {
+            server.run().await.unwrap();
+        });
+    }
+    wait_for_server(&format!("127.0.0.1:{}", port + 1).parse().unwrap()).await;
+
+    hub.connect_to_server(&hub.config.links[0]).await.unwrap();
+
+    // Wait for both ends to finish the handshake
+    for _ in 0..50 {
+        if let (Some(hub_link), Some(_)) = (hub.get_linked_server("leaf.server").await, leaf.get_linked_server("hub.server").await) {
+            if hub_link.lock().await.is_registered() {
+                return (hub, leaf);
+            }
+        }
+        sleep(Duration::from_millis(100)).await;
+    }
+    panic!("Servers failed to link within timeout");
+}
+
 // Standard test config
 #[cfg(test)]
 pub fn test_config(port: u16) -> ServerConfig {
@@ -296,19 +351,45 @@ pub fn test_config(port: u16) -> ServerConfig {
         network: crate::config::Network {
             name: "TestNet".to_string(),
             links: vec![],
+            flatten_links: false,
         },
         limits: crate::config::Limits {
             max_clients: 100,
             max_channels: 50,
         },
         hostmask: None,
-        access: crate::config::AccessConfig::default(),
+        access: crate::config::AccessConfig {
+            olines: vec![crate::config::OLine {
+                name: "testoper".to_string(),
+                mask: "*!*@*".to_string(),
+                password: "operpass".to_string(),
+                encrypted: false,
+                flags: ["kill", "remotekill", "kline", "dline", "remoteban"].map(str::to_string).to_vec(),
+                privset: None,
+            }],
+            ..Default::default()
+        },
         database: None,
         timeouts: Default::default(),
         links: vec![],
     }
 }
 
+// Link block pointing at a peer listening on the given port
+#[cfg(test)]
+pub fn test_link_config(name: &str, sid: &str, port: u16) -> ServerLinkConfig {
+    ServerLinkConfig {
+        name: name.to_string(),
+        sid: sid.to_string(),
+        description: "Test Link".to_string(),
+        password: "linkpass".to_string(),
+        address: format!("127.0.0.1:{}", port),
+        autoconnect: false,
+        ssl: false,
+        connect_frequency: 300,
+    }
+}
+
 // Helper functions
 #[cfg(test)]
 pub async fn wait_for_server(addr: &SocketAddr) {
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use std::net::IpAddr;
 use std::time::{SystemTime, UNIX_EPOCH};
 
 pub mod parser;
@@ -12,7 +13,40 @@ pub fn generate_ts() -> u64 {
         .as_secs()
 }
 
-#[derive(Debug)]
+/// A SID is a digit followed by two uppercase alphanumerics.
+pub fn is_valid_sid(sid: &str) -> bool {
+    let chars: Vec<char> = sid.chars().collect();
+    chars.len() == 3
+        && chars[0].is_ascii_digit()
+        && chars[1..].iter().all(|c| c.is_ascii_digit() || c.is_ascii_uppercase())
+}
+
+/// Builds a UID from our SID and a client counter: a letter followed by five alphanumerics.
+pub fn generate_uid(sid: &str, id: u32) -> String {
+    const ALPHANUMERICS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789";
+
+    let mut n = id % (26 * 36u32.pow(5));
+    let mut suffix = [b'A'; 6];
+    for c in suffix[1..].iter_mut().rev() {
+        *c = ALPHANUMERICS[(n % 36) as usize];
+        n /= 36;
+    }
+    suffix[0] = ALPHANUMERICS[n as usize];
+
+    format!("{}{}", sid, String::from_utf8_lossy(&suffix))
+}
+
+/// Formats an IP address for TS6, prepending a zero if it would start with a colon.
+pub fn format_ip(ip: IpAddr) -> String {
+    let ip = ip.to_string();
+    if ip.starts_with(':') {
+        format!("0{}", ip)
+    } else {
+        ip
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct TS6Message {
     pub tags: HashMap<String, String>,
     pub source: Option<String>,
@@ -42,6 +76,15 @@ impl TS6Message {
     pub fn to_string(&self) -> String {
         let mut parts = Vec::new();
 
+        // Add tags if present
+        if !self.tags.is_empty() {
+            let mut tags: Vec<String> = self.tags.iter()
+                .map(|(key, value)| if value.is_empty() { key.clone() } else { format!("{}={}", key, value) })
+                .collect();
+            tags.sort();
+            parts.push(format!("@{}", tags.join(";")));
+        }
+
         // Add source if present
         if let Some(ref source) = self.source {
             parts.push(format!(":{}", source));
@@ -69,3 +112,35 @@ impl TS6Message {
         parts.join(" ")
     }
 }
+
+/// Splits an EUID into the UID and ENCAP REALHOST/LOGIN lines understood by servers without EUID.
+pub fn euid_to_uid(msg: &TS6Message) -> Vec<TS6Message> {
+    let params = &msg.params;
+    let uid = params[7].clone();
+
+    let mut uid_params = params[..8].to_vec();
+    uid_params.push(params[10].clone());
+    let mut messages = vec![TS6Message {
+        tags: Default::default(),
+        source: msg.source.clone(),
+        command: "UID".to_string(),
+        params: uid_params,
+    }];
+
+    if params[8] != params[5] {
+        messages.push(TS6Message::with_source(
+            uid.clone(),
+            "ENCAP".to_string(),
+            vec!["*".to_string(), "REALHOST".to_string(), params[8].clone()],
+        ));
+    }
+    if params[9] != "*" {
+        messages.push(TS6Message::with_source(
+            uid,
+            "ENCAP".to_string(),
+            vec!["*".to_string(), "LOGIN".to_string(), params[9].clone()],
+        ));
+    }
+
+    messages
+}
@@ -12,13 +12,151 @@ mod parser_tests {
     }
 
     // ... keep all the other parser unit tests ...
+
+    #[test]
+    fn test_generate_uid() {
+        use crate::ts6::generate_uid;
+
+        assert_eq!(generate_uid("001", 0), "001AAAAAA");
+        assert_eq!(generate_uid("001", 1), "001AAAAAB");
+        assert_eq!(generate_uid("001", 36), "001AAAABA");
+
+        let uid = generate_uid("42X", u32::MAX);
+        assert_eq!(uid.len(), 9);
+        assert!(uid[3..4].chars().all(|c| c.is_ascii_uppercase()));
+    }
 }
 
 #[cfg(test)]
 mod integration_tests {
-    use crate::test_utils::{setup_test_server, TestClient};
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    use tokio::net::TcpListener;
+    use tokio::time::Duration;
+
+    use crate::config::{ServerConfig, ULine};
+    use crate::server::{LinkState, Server, User};
+    use crate::test_utils::{setup_linked_servers, setup_test_server, test_config, test_link_config, TestClient, wait_for_server};
 
     const PORT_TS6_SERVER_LINK: u16 = 6931;
+    const PORT_TS6_HANDSHAKE: u16 = 6933;
+    const PORT_TS6_BAD_PASSWORD: u16 = 6935;
+    const PORT_TS6_INCOMING_LINK: u16 = 6937;
+    const PORT_TS6_LINKED_SERVERS: u16 = 6938;
+    const PORT_TS6_USER_BURST: u16 = 6940;
+    const PORT_TS6_USER_BURST_UID: u16 = 6942;
+    const PORT_TS6_CHANNEL_BURST: u16 = 6944;
+    const PORT_TS6_SJOIN_LOWER_TS: u16 = 6946;
+    const PORT_TS6_SJOIN_EQUAL_TS: u16 = 6948;
+    const PORT_TS6_SJOIN_HIGHER_TS: u16 = 6950;
+    const PORT_TS6_REMOTE_USER: u16 = 6952;
+    const PORT_TS6_TOPOLOGY: u16 = 6954;
+    const PORT_TS6_TOPOLOGY_BURST: u16 = 6956;
+    const PORT_TS6_SQUIT: u16 = 6958;
+    const PORT_TS6_LINK_LOSS: u16 = 6960;
+    const PORT_TS6_NICK_COLLISION_SAVE: u16 = 6962;
+    const PORT_TS6_NICK_COLLISION_KILL: u16 = 6964;
+    const PORT_TS6_AUTOCONNECT: u16 = 6966;
+    const PORT_TS6_MESSAGE_ROUTING: u16 = 6968;
+    const PORT_TS6_ENCAP: u16 = 6970;
+    const PORT_TS6_TOPIC_BURST: u16 = 6972;
+    const PORT_TS6_TOPIC_FROM_NETWORK: u16 = 6974;
+    const PORT_TS6_END_OF_BURST: u16 = 6976;
+    const PORT_TS6_HUNTED_COMMANDS: u16 = 6978;
+    const PORT_TS6_LINKS_AND_MAP: u16 = 6980;
+    const PORT_TS6_LUSERS: u16 = 6982;
+    const PORT_TS6_KILL: u16 = 6984;
+    const PORT_TS6_REMOTE_BANS: u16 = 6986;
+    const PORT_TS6_CHANNEL_BURST_STATUSES: u16 = 6988;
+    const PORT_TS6_SJOIN_NEW_CHANNEL: u16 = 6990;
+    const PORT_TS6_LOCAL_PROPAGATION: u16 = 6992;
+    const PORT_TS6_OPER_COMMANDS: u16 = 6994;
+
+    // Start a server linked to a fake peer on port + 1 and accept the outgoing connection
+    async fn connect_to_fake_peer(port: u16) -> (Arc<Server>, TestClient) {
+        let mut config = test_config(port);
+        config.links.push(test_link_config("peer.server", "002", port + 1));
+        let server = Arc::new(Server::new(config).await.unwrap());
+        let peer = accept_fake_peer(&server, port + 1).await;
+        (server, peer)
+    }
+
+    async fn accept_fake_peer(server: &Arc<Server>, port: u16) -> TestClient {
+        let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+        server.connect_to_server(&server.config.links[0]).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        TestClient::from_stream(stream)
+    }
+
+    // Start a server with a link block for a fake peer on port + 1, serving clients on port
+    async fn start_peer_server(mut config: ServerConfig, port: u16) -> (Arc<Server>, SocketAddr) {
+        config.links.push(test_link_config("peer.server", "002", port + 1));
+        let server = Arc::new(Server::new(config).await.unwrap());
+        let server_clone = Arc::clone(&server);
+        tokio::spawn(async move {
+            server_clone.run().await.unwrap();
+        });
+        let addr = format!("127.0.0.1:{}", port).parse().unwrap();
+        wait_for_server(&addr).await;
+        (server, addr)
+    }
+
+    // The fake peer's half of the handshake, as peer.server with the given CAPAB
+    async fn send_peer_credentials(peer: &mut TestClient, capab: &str) {
+        peer.send_raw("PASS linkpass TS 6 :002").await.unwrap();
+        peer.send_raw(&format!("CAPAB :{}", capab)).await.unwrap();
+        peer.send_raw("SERVER peer.server 1 :Peer Server").await.unwrap();
+    }
+
+    // Link a running server to a fake peer on port + 1 that answers with its credentials
+    async fn link_fake_peer(server: &Arc<Server>, port: u16, capab: &str) -> TestClient {
+        let mut peer = accept_fake_peer(server, port + 1).await;
+        send_peer_credentials(&mut peer, capab).await;
+        peer
+    }
+
+    // Start a server with a registered local user, then link it to a fake peer sending the given CAPAB
+    async fn burst_to_fake_peer(port: u16, capab: &str) -> TestClient {
+        let (server, addr) = start_peer_server(test_config(port), port).await;
+
+        let mut client = TestClient::connect(addr).await.unwrap();
+        client.register("burstnick", "burstuser", "test.com").await.unwrap();
+        client.join("#burst").await.unwrap();
+        client.expect_line("366").await.unwrap();
+
+        let channel = server.get_channel("#burst").await.unwrap();
+        channel.write().await.add_list_entry('b', "*!*@banned.com".to_string(), "burstnick".to_string());
+        channel.write().await.add_list_entry('e', "*!*@friend.com".to_string(), "burstnick".to_string());
+        channel.write().await.set_topic("Burst topic".to_string(), "burstnick".to_string());
+
+        link_fake_peer(&server, port, capab).await
+    }
+
+    // Burst #ts with a local op to a fake peer and finish the handshake; returns the channel TS
+    async fn fake_peer_with_channel(port: u16) -> (Arc<Server>, TestClient, TestClient, u64) {
+        fake_peer_with_capab(port, "QS ENCAP EUID EX IE").await
+    }
+
+    async fn fake_peer_with_capab(port: u16, capab: &str) -> (Arc<Server>, TestClient, TestClient, u64) {
+        fake_peer_with_config(test_config(port), port, capab).await
+    }
+
+    async fn fake_peer_with_config(config: ServerConfig, port: u16, capab: &str) -> (Arc<Server>, TestClient, TestClient, u64) {
+        let (server, addr) = start_peer_server(config, port).await;
+
+        let mut client = TestClient::connect(addr).await.unwrap();
+        client.register("localnick", "localuser", "test.com").await.unwrap();
+        client.join("#ts").await.unwrap();
+        client.expect_line("366").await.unwrap();
+
+        let mut peer = link_fake_peer(&server, port, capab).await;
+        peer.expect_line("PING test.server").await.unwrap();
+
+        let ts = server.get_channel("#ts").await.unwrap().read().await.created_at();
+        (server, client, peer, ts)
+    }
 
     #[tokio::test]
     async fn test_ts6_server_link() {
@@ -35,5 +173,992 @@ mod integration_tests {
         // TODO: Add verification that client appears on server2
     }
 
+    #[tokio::test]
+    async fn test_ts6_handshake() {
+        let (_server, mut peer) = connect_to_fake_peer(PORT_TS6_HANDSHAKE).await;
+
+        // As the initiator we send our credentials first
+        peer.expect_line("PASS linkpass TS 6 :001").await.unwrap();
+        peer.expect_line("CAPAB").await.unwrap();
+        peer.expect_line("SERVER test.server 1").await.unwrap();
+
+        send_peer_credentials(&mut peer, "QS ENCAP EX IE").await;
+
+        // A valid SERVER is answered with SVINFO and the burst
+        let svinfo = peer.expect_line("SVINFO").await.unwrap();
+        assert!(svinfo.starts_with("SVINFO 6 6 0"));
+    }
+
+    #[tokio::test]
+    async fn test_ts6_handshake_rejects_bad_password() {
+        let (_server, mut peer) = connect_to_fake_peer(PORT_TS6_BAD_PASSWORD).await;
+        peer.expect_line("SERVER test.server").await.unwrap();
+
+        peer.send_raw("PASS wrongpass TS 6 :002").await.unwrap();
+        peer.send_raw("CAPAB :QS ENCAP").await.unwrap();
+        peer.send_raw("SERVER peer.server 1 :Peer Server").await.unwrap();
+
+        let error = peer.expect_line("ERROR").await.unwrap();
+        assert!(error.contains("Closing Link"));
+        assert!(error.contains("Invalid password"));
+    }
+
+    #[tokio::test]
+    async fn test_ts6_incoming_link() {
+        let (server, addr) = start_peer_server(test_config(PORT_TS6_INCOMING_LINK), PORT_TS6_INCOMING_LINK).await;
+
+        // A server connects to the client port and is recognised by its PASS
+        let mut peer = TestClient::connect(addr).await.unwrap();
+        send_peer_credentials(&mut peer, "QS ENCAP EX IE").await;
+
+        // The listener answers with its own credentials, then SVINFO
+        peer.expect_line("PASS linkpass TS 6 :001").await.unwrap();
+        peer.expect_line("CAPAB").await.unwrap();
+        peer.expect_line("SERVER test.server 1").await.unwrap();
+        peer.expect_line("SVINFO").await.unwrap();
+
+        let link = server.get_linked_server("peer.server").await.unwrap();
+        assert!(link.lock().await.is_incoming());
+
+        // Clients are still served on the same port
+        let mut client = TestClient::connect(addr).await.unwrap();
+        client.register("test", "user", "test.com").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ts6_linked_servers() {
+        let (hub, leaf) = setup_linked_servers(PORT_TS6_LINKED_SERVERS).await;
+
+        let hub_link = hub.get_linked_server("leaf.server").await.unwrap();
+        assert!(!hub_link.lock().await.is_incoming());
+        let leaf_link = leaf.get_linked_server("hub.server").await.unwrap();
+        assert!(leaf_link.lock().await.is_incoming());
+        assert_eq!(leaf_link.lock().await.sid(), "001");
+    }
+
+    #[tokio::test]
+    async fn test_ts6_user_burst_euid() {
+        let mut peer = burst_to_fake_peer(PORT_TS6_USER_BURST, "QS ENCAP EUID").await;
+
+        let euid = peer.expect_line("EUID burstnick").await.unwrap();
+        let params: Vec<&str> = euid.split(' ').collect();
+        assert_eq!(params[0], ":001");
+        assert_eq!(params[6], "burstuser");
+        assert_eq!(params[8], "127.0.0.1");
+        assert!(params[9].starts_with("001") && params[9].len() == 9, "Bad UID in {}", euid);
+        assert_eq!(params[11], "*");
+    }
+
+    #[tokio::test]
+    async fn test_ts6_user_burst_uid() {
+        let mut peer = burst_to_fake_peer(PORT_TS6_USER_BURST_UID, "QS ENCAP").await;
+
+        // Without EUID the user is introduced with plain UID
+        let uid = peer.expect_line("UID burstnick").await.unwrap();
+        assert!(uid.starts_with(":001 UID burstnick 1 "));
+        peer.expect_line("PING test.server").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ts6_channel_burst() {
+        let mut peer = burst_to_fake_peer(PORT_TS6_CHANNEL_BURST, "QS ENCAP EUID EX").await;
+
+        let euid = peer.expect_line("EUID burstnick").await.unwrap();
+        let uid = euid.split(' ').nth(9).unwrap().to_string();
+
+        // The creator is sent with op status
+        let sjoin = peer.expect_line("SJOIN").await.unwrap();
+        let params: Vec<&str> = sjoin.split(' ').collect();
+        assert_eq!(params[0], ":001");
+        assert!(params[2].parse::<u64>().is_ok());
+        assert_eq!(params[3], "#burst");
+        assert_eq!(params[4], "+nt");
+        assert_eq!(params[5], format!(":@{}", uid));
+
+        let bmask = peer.expect_line("BMASK").await.unwrap();
+        assert!(bmask.ends_with("#burst b :*!*@banned.com"));
+        let bmask = peer.expect_line("BMASK").await.unwrap();
+        assert!(bmask.ends_with("#burst e :*!*@friend.com"));
+
+        // No topic burst for peers without TB
+        assert_eq!(peer.read_message().await.unwrap(), ":001 PING test.server :002");
+    }
+
+    #[tokio::test]
+    async fn test_ts6_channel_burst_statuses() {
+        let port = PORT_TS6_CHANNEL_BURST_STATUSES;
+        let (server, addr) = start_peer_server(test_config(port), port).await;
+
+        let mut creator = TestClient::connect(addr).await.unwrap();
+        creator.register("creator", "creatoruser", "test.com").await.unwrap();
+        creator.join("#burst").await.unwrap();
+        creator.expect_line("366").await.unwrap();
+        let mut member = TestClient::connect(addr).await.unwrap();
+        member.register("member", "memberuser", "test.com").await.unwrap();
+        member.join("#burst").await.unwrap();
+        member.expect_line("366").await.unwrap();
+
+        // Ops and voices given later are tracked, and ops can be taken away
+        creator.send_raw("MODE #burst +ov member member").await.unwrap();
+        member.expect_line("MODE #burst +ov member :member").await.unwrap();
+        creator.send_raw("MODE #burst -o creator").await.unwrap();
+        member.expect_line("MODE #burst -o :creator").await.unwrap();
+        // Without ops the creator can no longer give them
+        creator.send_raw("MODE #burst +o creator").await.unwrap();
+        creator.expect_line(" 482 creator #burst ").await.unwrap();
+
+        let creator_uid = local_uid(&server, "creator").await;
+        let member_uid = local_uid(&server, "member").await;
+        let mut peer = link_fake_peer(&server, port, "QS ENCAP EUID").await;
+
+        let sjoin = peer.expect_line("SJOIN").await.unwrap();
+        let (_, nicklist) = sjoin.split_once(" :").unwrap();
+        let mut nicklist: Vec<&str> = nicklist.split(' ').collect();
+        nicklist.sort_unstable();
+        let mut expected = vec![format!("@+{}", member_uid), creator_uid];
+        expected.sort_unstable();
+        assert_eq!(nicklist, expected);
+    }
+
+    #[tokio::test]
+    async fn test_ts6_topic_burst() {
+        let mut peer = burst_to_fake_peer(PORT_TS6_TOPIC_BURST, "QS ENCAP EUID TB").await;
+
+        let tb = peer.expect_line(" TB #burst").await.unwrap();
+        let params: Vec<&str> = tb.split(' ').collect();
+        assert_eq!(params[..3], [":001", "TB", "#burst"]);
+        assert!(params[3].parse::<i64>().is_ok());
+        assert_eq!(params[4..], ["burstnick", ":Burst", "topic"]);
+        peer.expect_line("PING test.server").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ts6_topic_from_network() {
+        let (server, mut client, mut peer, ts) = fake_peer_with_channel(PORT_TS6_TOPIC_FROM_NETWORK).await;
+
+        // TB sets a topic on a channel without one
+        peer.send_raw(":002 TB #ts 1000 setter!user@host :First topic").await.unwrap();
+        assert_eq!(client.expect_line("TOPIC").await.unwrap(), ":setter!user@host TOPIC #ts :First topic");
+
+        // After that only an older one wins, set by the server when no setter is given
+        peer.send_raw(":002 TB #ts 2000 newer :Newer topic").await.unwrap();
+        peer.send_raw(":002 TB #ts 500 :Older topic").await.unwrap();
+        assert_eq!(client.expect_line("TOPIC").await.unwrap(), ":peer.server TOPIC #ts :Older topic");
+
+        // ETB needs an older channel, or the same channel and a newer topic
+        peer.send_raw(&format!(":002 ETB {} #ts 3000 later :Later channel", ts + 1)).await.unwrap();
+        peer.send_raw(&format!(":002 ETB {} #ts 3000 etbsetter :ETB topic", ts)).await.unwrap();
+        assert_eq!(client.expect_line("TOPIC").await.unwrap(), ":etbsetter TOPIC #ts :ETB topic");
+
+        let channel = server.get_channel("#ts").await.unwrap();
+        let channel = channel.read().await;
+        assert_eq!(channel.get_topic().as_deref(), Some("ETB topic"));
+        assert_eq!(channel.topic_ts(), 3000);
+    }
+
+    #[tokio::test]
+    async fn test_ts6_sjoin_lower_ts() {
+        let (server, mut client, mut peer, ts) = fake_peer_with_channel(PORT_TS6_SJOIN_LOWER_TS).await;
+        let local = server.find_client_id_by_nick("localnick").await.unwrap();
+
+        // An older channel wins: our modes and statuses are wiped and theirs applied
+        peer.send_raw(":002 EUID remotenick 1 1000 +i ruser remote.host 10.0.0.1 002AAAAAA remote.host * :Remote User").await.unwrap();
+        peer.send_raw(&format!(":002 SJOIN {} #ts +ims :@002AAAAAA", ts - 100)).await.unwrap();
+
+        client.expect_line("NOTICE #ts :*** Notice -- TS for #ts changed").await.unwrap();
+        let removed = client.expect_line("MODE #ts -").await.unwrap();
+        assert!(removed.contains("localnick"), "Op not removed in {}", removed);
+        client.expect_line(":remotenick!ruser@remote.host JOIN :#ts").await.unwrap();
+        client.expect_line("MODE #ts +imso :remotenick").await.unwrap();
+
+        let remote = server.find_client_id_by_nick("remotenick").await.unwrap();
+        let channel = server.get_channel("#ts").await.unwrap();
+        let channel = channel.read().await;
+        assert_eq!(channel.created_at(), ts - 100);
+        assert_eq!(channel.get_simple_modes(), vec!["+ims"]);
+        assert!(channel.is_operator(remote));
+        assert!(!channel.is_operator(local));
+    }
+
+    #[tokio::test]
+    async fn test_ts6_sjoin_equal_ts() {
+        let (server, mut client, mut peer, ts) = fake_peer_with_channel(PORT_TS6_SJOIN_EQUAL_TS).await;
+
+        // Equal TS merges both sides' modes and statuses
+        peer.send_raw(":002 EUID remotenick 1 1000 +i ruser remote.host 10.0.0.1 002AAAAAA remote.host * :Remote User").await.unwrap();
+        peer.send_raw(&format!(":002 SJOIN {} #ts +s :@+002AAAAAA", ts)).await.unwrap();
+
+        client.expect_line(":remotenick!ruser@remote.host JOIN :#ts").await.unwrap();
+        client.expect_line("MODE #ts +sov remotenick :remotenick").await.unwrap();
+        let channel = server.get_channel("#ts").await.unwrap();
+        let channel = channel.read().await;
+        assert_eq!(channel.created_at(), ts);
+        assert_eq!(channel.get_simple_modes(), vec!["+nst"]);
+        assert_eq!(channel.get_members().len(), 2);
+        assert!(channel.get_members().iter().all(|&id| channel.is_operator(id)));
+        let remote = server.find_client_id_by_nick("remotenick").await.unwrap();
+        assert!(channel.is_voiced(remote));
+    }
+
+    #[tokio::test]
+    async fn test_ts6_sjoin_higher_ts() {
+        let (server, _client, mut peer, ts) = fake_peer_with_channel(PORT_TS6_SJOIN_HIGHER_TS).await;
+
+        // A younger channel loses: its modes and statuses are ignored, its members still join
+        peer.send_raw(":002 EUID remotenick 1 1000 +i ruser remote.host 10.0.0.1 002AAAAAA remote.host * :Remote User").await.unwrap();
+        peer.send_raw(&format!(":002 SJOIN {} #ts +ims :@002AAAAAA", ts + 100)).await.unwrap();
+        peer.send_raw(&format!(":002 BMASK {} #ts b :*!*@ignored.com", ts + 100)).await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+
+        let local = server.find_client_id_by_nick("localnick").await.unwrap();
+        let remote = server.find_client_id_by_nick("remotenick").await.unwrap();
+        let channel = server.get_channel("#ts").await.unwrap();
+        let channel = channel.read().await;
+        assert_eq!(channel.created_at(), ts);
+        assert_eq!(channel.get_simple_modes(), vec!["+nt"]);
+        assert!(channel.get_list('b').is_empty());
+        assert!(channel.get_members().contains(&remote));
+        assert!(!channel.is_operator(remote));
+        assert!(channel.is_operator(local));
+    }
+
+    #[tokio::test]
+    async fn test_ts6_sjoin_new_channel() {
+        let (server, _client, mut peer, _) = fake_peer_with_channel(PORT_TS6_SJOIN_NEW_CHANNEL).await;
+
+        // A new channel takes only the modes it was sent with
+        peer.send_raw(":002 EUID remotenick 1 1000 +i ruser remote.host 10.0.0.1 002AAAAAA remote.host * :Remote User").await.unwrap();
+        peer.send_raw(":002 SJOIN 1000 #remote +s :@002AAAAAA").await.unwrap();
+        // Nobody it names is known, so there is nothing to create
+        peer.send_raw(":002 SJOIN 1000 #empty +nt :@002ZZZZZZ").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+
+        let channel = server.get_channel("#remote").await.unwrap();
+        let channel = channel.read().await;
+        assert_eq!(channel.created_at(), 1000);
+        assert_eq!(channel.get_simple_modes(), vec!["+s"]);
+        assert_eq!(channel.get_members().len(), 1);
+        assert!(server.get_channel("#empty").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ts6_remote_user() {
+        let (server, mut client, mut peer, ts) = fake_peer_with_channel(PORT_TS6_REMOTE_USER).await;
+
+        peer.send_raw(":002 EUID remotenick 1 1000 +i ruser remote.host 10.0.0.1 002AAAAAA remote.host * :Remote User").await.unwrap();
+        peer.send_raw(&format!(":002 SJOIN {} #ts + :002AAAAAA", ts)).await.unwrap();
+        client.expect_line(":remotenick!ruser@remote.host JOIN").await.unwrap();
+
+        // Remote users show up in WHOIS and WHO like local ones
+        client.send_raw("WHOIS remotenick").await.unwrap();
+        let whois = client.expect_line("311").await.unwrap();
+        assert!(whois.contains("remotenick ruser remote.host * :Remote User"), "Bad WHOIS in {}", whois);
+        client.expect_line("312 localnick remotenick peer.server").await.unwrap();
+        client.send_raw("WHO #ts").await.unwrap();
+        client.expect_line("352 localnick #ts ruser remote.host peer.server remotenick").await.unwrap();
+
+        // Private messages are routed to the owning server by UID
+        client.privmsg("remotenick", "hello").await.unwrap();
+        let privmsg = peer.expect_line("PRIVMSG").await.unwrap();
+        assert!(privmsg.starts_with(":001"), "Not sourced from a UID: {}", privmsg);
+        assert!(privmsg.ends_with("PRIVMSG 002AAAAAA :hello"), "Bad PRIVMSG {}", privmsg);
+
+        peer.send_raw(":002AAAAAA QUIT :Gone").await.unwrap();
+        client.expect_line(":remotenick!ruser@remote.host QUIT :Gone").await.unwrap();
+        assert!(server.find_client_by_nick("remotenick").await.is_none());
+        assert!(server.find_client_by_uid("002AAAAAA").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ts6_topology() {
+        let (server, mut client, mut peer, _) = fake_peer_with_channel(PORT_TS6_TOPOLOGY).await;
+
+        // A server behind the peer, with a user on it
+        peer.send_raw(":002 SID far.server 2 003 :Far Server").await.unwrap();
+        peer.send_raw(":003 EUID farnick 2 1000 + far farhost 10.0.0.3 003AAAAAA farhost * :Far User").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+        client.send_raw("WHOIS farnick").await.unwrap();
+        client.expect_line("312 localnick farnick far.server :Far Server").await.unwrap();
+
+        let far = server.find_server_by_sid("003").await.unwrap();
+        assert_eq!(far.name, "far.server");
+        assert_eq!(far.uplink, "peer.server");
+        assert_eq!(far.hopcount, 2);
+        let peer_link = server.get_linked_server("peer.server").await.unwrap();
+        let route = server.route_to_server("far.server").await.unwrap();
+        assert!(Arc::ptr_eq(&route, &peer_link));
+
+        let subtree: Vec<String> = server.get_server_subtree("peer.server").await.into_iter().map(|s| s.name).collect();
+        assert_eq!(subtree, vec!["peer.server", "far.server"]);
+
+        // Reintroducing a known SID drops the link
+        peer.send_raw(":002 SID other.server 2 003 :Duplicate").await.unwrap();
+        let error = peer.expect_line("ERROR").await.unwrap();
+        assert!(error.contains("SID 003 already exists"), "Unexpected {}", error);
+    }
+
+    #[tokio::test]
+    async fn test_ts6_topology_burst() {
+        let mut config = test_config(PORT_TS6_TOPOLOGY_BURST);
+        config.links.push(test_link_config("other.server", "004", PORT_TS6_TOPOLOGY_BURST + 2));
+        let (_server, addr) = start_peer_server(config, PORT_TS6_TOPOLOGY_BURST).await;
+
+        let mut peer = TestClient::connect(addr).await.unwrap();
+        send_peer_credentials(&mut peer, "QS ENCAP EUID").await;
+        peer.expect_line("PING test.server").await.unwrap();
+        peer.send_raw(":002 SID far.server 2 003 :Far Server").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+
+        // A second server learns the whole network in its burst
+        let mut other = TestClient::connect(addr).await.unwrap();
+        other.send_raw("PASS linkpass TS 6 :004").await.unwrap();
+        other.send_raw("CAPAB :QS ENCAP EUID").await.unwrap();
+        other.send_raw("SERVER other.server 1 :Other Server").await.unwrap();
+        other.expect_line(":001 SID peer.server 2 002 :Peer Server").await.unwrap();
+        other.expect_line(":002 SID far.server 3 003 :Far Server").await.unwrap();
+
+        // And the first server hears about the new one
+        peer.expect_line(":001 SID other.server 2 004 :Other Server").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ts6_squit() {
+        let (server, mut client, mut peer, ts) = fake_peer_with_channel(PORT_TS6_SQUIT).await;
+
+        peer.send_raw(":002 SID far.server 2 003 :Far Server").await.unwrap();
+        peer.send_raw(":003 EUID farnick 2 1000 + far farhost 10.0.0.3 003AAAAAA farhost * :Far User").await.unwrap();
+        peer.send_raw(":002 EUID nearnick 1 1000 + near nearhost 10.0.0.2 002AAAAAA nearhost * :Near User").await.unwrap();
+        peer.send_raw(&format!(":002 SJOIN {} #ts + :003AAAAAA 002AAAAAA", ts)).await.unwrap();
+        client.expect_line(":nearnick!near@nearhost JOIN").await.unwrap();
+
+        // Only the squit server's users quit, with the names of both sides of the split
+        peer.send_raw(":002 SQUIT 003 :Far away").await.unwrap();
+        client.expect_line(":farnick!far@farhost QUIT :peer.server far.server").await.unwrap();
+
+        assert!(server.find_server_by_sid("003").await.is_none());
+        assert!(server.find_client_by_nick("farnick").await.is_none());
+        assert!(server.find_client_by_nick("nearnick").await.is_some());
+        assert!(server.find_server_by_sid("002").await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_ts6_link_loss() {
+        let (server, mut client, mut peer, ts) = fake_peer_with_channel(PORT_TS6_LINK_LOSS).await;
+
+        peer.send_raw(":002 SID far.server 2 003 :Far Server").await.unwrap();
+        peer.send_raw(":003 EUID farnick 2 1000 + far farhost 10.0.0.3 003AAAAAA farhost * :Far User").await.unwrap();
+        peer.send_raw(":002 EUID nearnick 1 1000 + near nearhost 10.0.0.2 002AAAAAA nearhost * :Near User").await.unwrap();
+        peer.send_raw(&format!(":002 SJOIN {} #ts + :003AAAAAA 002AAAAAA", ts)).await.unwrap();
+        client.expect_line(":nearnick!near@nearhost JOIN").await.unwrap();
+
+        // Losing the link splits everything behind it, as a batch for clients supporting one
+        drop(peer);
+        let start = client.expect_line("BATCH +").await.unwrap();
+        assert!(start.ends_with("netsplit test.server :peer.server"), "Unexpected {}", start);
+        let reference = start.split(' ').nth(2).unwrap()[1..].to_string();
+        let mut quits = vec![
+            client.expect_line("QUIT").await.unwrap(),
+            client.expect_line("QUIT").await.unwrap(),
+        ];
+        quits.sort();
+        assert_eq!(quits, vec![
+            format!("@batch={} :farnick!far@farhost QUIT :test.server peer.server", reference),
+            format!("@batch={} :nearnick!near@nearhost QUIT :test.server peer.server", reference),
+        ]);
+        client.expect_line(&format!("BATCH :-{}", reference)).await.unwrap();
+
+        assert!(server.get_linked_server("peer.server").await.is_none());
+        assert!(server.get_remote_servers().await.is_empty());
+        assert!(server.find_client_by_nick("farnick").await.is_none());
+        assert!(server.find_client_by_nick("nearnick").await.is_none());
+    }
+
+    // UID of the local user holding the nickname
+    async fn local_uid(server: &Server, nickname: &str) -> String {
+        match server.find_client_by_nick(nickname).await {
+            Some(User::Local(client)) => client.lock().await.uid().to_string(),
+            _ => panic!("{} is not a local user", nickname),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ts6_nick_collision_save() {
+        let (server, mut client, mut peer, _) = fake_peer_with_capab(PORT_TS6_NICK_COLLISION_SAVE, "QS ENCAP EUID SAVE").await;
+        let uid = local_uid(&server, "localnick").await;
+
+        // An older user from elsewhere takes the nickname; ours is saved to its UID
+        peer.send_raw(":002 EUID localnick 1 1000 + ruser remote.host 10.0.0.1 002AAAAAA remote.host * :Remote User").await.unwrap();
+        let nick = client.expect_line(" NICK ").await.unwrap();
+        assert!(nick.starts_with(":localnick!") && nick.ends_with(&format!("NICK :{}", uid)), "Unexpected {}", nick);
+        peer.expect_line(&format!(":001 SAVE {}", uid)).await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+        assert!(matches!(server.find_client_by_nick("localnick").await, Some(User::Remote(user)) if user.uid == "002AAAAAA"));
+        assert!(matches!(server.find_client_by_nick(&uid).await, Some(User::Local(_))));
+
+        // A newer user from the same user@host takes over and the older one is saved
+        peer.send_raw(":002 EUID localnick 1 2000 + ruser remote.host 10.0.0.1 002AAAAAB remote.host * :Remote User").await.unwrap();
+        peer.expect_line(":001 SAVE 002AAAAAA :1000").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+        assert!(matches!(server.find_client_by_nick("localnick").await, Some(User::Remote(user)) if user.uid == "002AAAAAB"));
+
+        // SAVE from the network renames the user, unless its nick TS is stale
+        peer.send_raw(":002 SAVE 002AAAAAB 1").await.unwrap();
+        peer.send_raw(":002 SAVE 002AAAAAB 2000").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+        let saved = server.find_remote_user_by_uid("002AAAAAB").await.unwrap();
+        assert_eq!(saved.nickname, "002AAAAAB");
+        assert_eq!(saved.nick_ts, 100);
+        assert!(server.find_client_by_nick("localnick").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ts6_nick_collision_kill() {
+        let (server, mut client, mut peer, _) = fake_peer_with_capab(PORT_TS6_NICK_COLLISION_KILL, "QS ENCAP EUID").await;
+        let uid = local_uid(&server, "localnick").await;
+
+        // Without SAVE a newer user from a different user@host is killed
+        peer.send_raw(&format!(":002 EUID localnick 1 {} + ruser remote.host 10.0.0.1 002AAAAAA remote.host * :Remote User", u32::MAX)).await.unwrap();
+        peer.expect_line(":001 KILL 002AAAAAA :test.server (Nick collision)").await.unwrap();
+        assert!(server.find_client_by_uid("002AAAAAA").await.is_none());
+
+        // And an older one wins, killing our user
+        peer.send_raw(":002 EUID localnick 1 1000 + ruser remote.host 10.0.0.1 002AAAAAB remote.host * :Remote User").await.unwrap();
+        peer.expect_line(&format!(":001 KILL {} :test.server (Nick collision)", uid)).await.unwrap();
+        let error = client.expect_line("ERROR").await.unwrap();
+        assert!(error.contains("Nick collision"), "Unexpected {}", error);
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+        assert!(matches!(server.find_client_by_nick("localnick").await, Some(User::Remote(user)) if user.uid == "002AAAAAB"));
+    }
+
+    #[tokio::test]
+    async fn test_ts6_autoconnect() {
+        let mut config = test_config(PORT_TS6_AUTOCONNECT);
+        let mut link_config = test_link_config("peer.server", "002", PORT_TS6_AUTOCONNECT + 1);
+        link_config.autoconnect = true;
+        link_config.connect_frequency = 1;
+        config.links.push(link_config);
+        let server = Arc::new(Server::new(config).await.unwrap());
+        let listener = TcpListener::bind(("127.0.0.1", PORT_TS6_AUTOCONNECT + 1)).await.unwrap();
+        let server_clone = Arc::clone(&server);
+        tokio::spawn(async move {
+            server_clone.run().await.unwrap();
+        });
+
+        // The supervisor connects on startup
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut peer = TestClient::from_stream(stream);
+        peer.expect_line("PASS").await.unwrap();
+        assert_eq!(server.get_link_status("peer.server").await.unwrap().state, LinkState::Connecting);
+
+        send_peer_credentials(&mut peer, "QS ENCAP EUID").await;
+        peer.expect_line("PING test.server").await.unwrap();
+        assert_eq!(server.get_link_status("peer.server").await.unwrap().state, LinkState::Bursting);
+        peer.send_raw(":002 EUID remotenick 1 1000 + ruser remote.host 10.0.0.1 002AAAAAA remote.host * :Remote User").await.unwrap();
+        peer.send_raw(":002 SJOIN 1000 #burst + :002AAAAAA").await.unwrap();
+        peer.send_raw(":002 PONG peer.server :001").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+        assert_eq!(server.get_link_status("peer.server").await.unwrap().state, LinkState::Established);
+
+        // Operators can see the link's state and the peer's burst
+        let addr = format!("127.0.0.1:{}", PORT_TS6_AUTOCONNECT).parse().unwrap();
+        let mut oper = TestClient::connect(addr).await.unwrap();
+        oper.register("opernick", "operuser", "test.com").await.unwrap();
+        oper.send_raw("STATS l").await.unwrap();
+        oper.expect_line(" 481 opernick ").await.unwrap();
+        oper.send_raw("OPER testoper operpass").await.unwrap();
+        oper.expect_line(" 381 ").await.unwrap();
+        oper.send_raw("STATS l").await.unwrap();
+        let link = format!("peer.server[127.0.0.1:{}]", PORT_TS6_AUTOCONNECT + 1);
+        let stats = oper.expect_line(" 211 ").await.unwrap();
+        assert!(stats.starts_with(&format!(":test.server 211 opernick {} established 0 1 1 160 ", link)), "Unexpected {}", stats);
+        assert!(stats.ends_with(" :-"), "Unexpected {}", stats);
+        oper.expect_line(" 219 opernick l ").await.unwrap();
+
+        // No second connection while the link is up
+        assert!(tokio::time::timeout(Duration::from_secs(2), listener.accept()).await.is_err());
+
+        // Losing the link fails it, then the supervisor reconnects
+        drop(peer);
+        let (stream, _) = tokio::time::timeout(Duration::from_secs(5), listener.accept()).await.unwrap().unwrap();
+        let statuses = server.get_link_statuses().await;
+        assert_eq!(statuses.len(), 1);
+        assert_eq!(statuses[0].1.failures, 1);
+        assert!(statuses[0].1.last_error.is_some());
+        let mut peer = TestClient::from_stream(stream);
+        peer.expect_line("PASS").await.unwrap();
+        oper.send_raw("STATS l").await.unwrap();
+        let stats = oper.expect_line(" 211 ").await.unwrap();
+        assert!(stats.starts_with(&format!(":test.server 211 opernick {} connecting 1 0 0 0 - :", link)), "Unexpected {}", stats);
+    }
+
+    #[tokio::test]
+    async fn test_ts6_message_routing() {
+        let (server, mut client, mut peer, ts) = fake_peer_with_channel(PORT_TS6_MESSAGE_ROUTING).await;
+        let uid = local_uid(&server, "localnick").await;
+
+        peer.send_raw(":002 SID far.server 2 003 :Far Server").await.unwrap();
+        peer.send_raw(":002 EUID remotenick 1 1000 + ruser remote.host 10.0.0.1 002AAAAAA remote.host * :Remote User").await.unwrap();
+        peer.send_raw(":003 EUID farnick 2 1000 + far farhost 10.0.0.3 003AAAAAA farhost * :Far User").await.unwrap();
+        peer.send_raw(&format!(":002 SJOIN {} #ts + :002AAAAAA 003AAAAAA", ts)).await.unwrap();
+        client.expect_line(":farnick!far@farhost JOIN").await.unwrap();
+
+        // A channel message goes down the link once, however many members are behind it
+        client.privmsg("#ts", "hello all").await.unwrap();
+        client.send_raw("NOTICE farnick :direct").await.unwrap();
+        assert_eq!(peer.expect_line("PRIVMSG").await.unwrap(), format!(":{} PRIVMSG #ts :hello all", uid));
+        assert_eq!(peer.read_message().await.unwrap(), format!(":{} NOTICE 003AAAAAA :direct", uid));
+
+        // Messages from the network reach local users with the sender's full prefix
+        peer.send_raw(":002AAAAAA PRIVMSG #ts :hi there").await.unwrap();
+        client.expect_line(":remotenick!ruser@remote.host PRIVMSG #ts :hi there").await.unwrap();
+        peer.send_raw(&format!(":003AAAAAA NOTICE {} :psst", uid)).await.unwrap();
+        client.expect_line(":farnick!far@farhost NOTICE localnick :psst").await.unwrap();
+        peer.send_raw(&format!(":002 NOTICE {} :Server notice", uid)).await.unwrap();
+        client.expect_line(":peer.server NOTICE localnick :Server notice").await.unwrap();
+
+        // A user we can no longer route to is reported missing without dropping the sender
+        server.remove_remote_server("far.server").await;
+        client.send_raw("NOTICE farnick :lost").await.unwrap();
+        client.privmsg("farnick", "anyone there").await.unwrap();
+        client.expect_line("401 localnick farnick").await.unwrap();
+        client.send_raw("PING :still.here").await.unwrap();
+        client.expect_line("PONG").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ts6_encap() {
+        let mut config = test_config(PORT_TS6_ENCAP);
+        config.access.ulines.push(ULine { server: "peer.server".to_string(), flags: Vec::new() });
+        let (server, mut client, mut peer, _) = fake_peer_with_config(config, PORT_TS6_ENCAP, "QS ENCAP EUID EX IE").await;
+
+        peer.send_raw(":002 EUID remotenick 1 1000 +o ruser remote.host 10.0.0.1 002AAAAAA remote.host * :Remote User").await.unwrap();
+        peer.send_raw(":002AAAAAA ENCAP * LOGIN remoteacct").await.unwrap();
+        peer.send_raw(":002AAAAAA ENCAP * CERTFP 0123abcd").await.unwrap();
+        peer.send_raw(":002AAAAAA ENCAP * REALHOST real.host").await.unwrap();
+        peer.send_raw(":002 ENCAP * CHGHOST 002AAAAAA vhost.example").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+
+        let user = server.find_remote_user_by_uid("002AAAAAA").await.unwrap();
+        assert_eq!(user.account.as_deref(), Some("remoteacct"));
+        assert_eq!(user.certfp.as_deref(), Some("0123abcd"));
+        assert_eq!(user.realhost, "real.host");
+        assert_eq!(user.hostname, "vhost.example");
+
+        // SU without an account logs the user out
+        peer.send_raw(":002 ENCAP * SU 002AAAAAA").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+        assert_eq!(server.find_remote_user_by_uid("002AAAAAA").await.unwrap().account, None);
+
+        // Subcommands only run on servers matching the target mask
+        peer.send_raw(":002AAAAAA ENCAP other.server RESV 0 #elsewhere 0 :Not here").await.unwrap();
+        peer.send_raw(":002AAAAAA ENCAP test.* RESV 0 bad* 0 :Reserved nick").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+
+        client.send_raw("NICK badnick").await.unwrap();
+        client.expect_line("432 localnick badnick :Reserved nick").await.unwrap();
+        client.join("#elsewhere").await.unwrap();
+        client.expect_line("JOIN :#elsewhere").await.unwrap();
+        let resv = server.find_resv("BADNICK").await.unwrap();
+        assert_eq!(resv.set_by, "remotenick!ruser@vhost.example");
+    }
+
+    #[tokio::test]
+    async fn test_ts6_end_of_burst() {
+        let (server, addr) = start_peer_server(test_config(PORT_TS6_END_OF_BURST), PORT_TS6_END_OF_BURST).await;
+
+        let mut oper = TestClient::connect(addr).await.unwrap();
+        oper.register("opernick", "operuser", "test.com").await.unwrap();
+        oper.send_raw("OPER testoper operpass").await.unwrap();
+        oper.expect_line(" 381 ").await.unwrap();
+
+        let mut peer = link_fake_peer(&server, PORT_TS6_END_OF_BURST, "QS ENCAP EUID").await;
+        peer.send_raw(":002 EUID remotenick 1 1000 + ruser remote.host 10.0.0.1 002AAAAAA remote.host * :Remote User").await.unwrap();
+        peer.send_raw(":002 SJOIN 1000 #remote + :@002AAAAAA").await.unwrap();
+
+        // Our burst ends with a PING; the peer's answer marks the end of its own burst
+        let ping = peer.expect_line("PING test.server").await.unwrap();
+        assert_eq!(ping, ":001 PING test.server :002");
+        let link = server.get_linked_server("peer.server").await.unwrap();
+        assert!(!link.lock().await.is_synced());
+        peer.send_raw(":002 PONG peer.server :001").await.unwrap();
+
+        let notice = oper.expect_line("End of burst").await.unwrap();
+        assert!(notice.contains("NOTICE opernick :*** Notice -- End of burst from peer.server (1 users, 1 channels, "), "Unexpected {}", notice);
+        let stats = link.lock().await.burst_stats().clone();
+        assert_eq!((stats.users, stats.channels), (1, 1));
+        assert!(stats.bytes > 0);
+        assert!(stats.duration.is_some());
+        assert_eq!(server.get_link_status("peer.server").await.unwrap().state, LinkState::Established);
+    }
+
+    #[tokio::test]
+    async fn test_ts6_hunted_commands() {
+        let (server, mut client, mut peer, _) = fake_peer_with_channel(PORT_TS6_HUNTED_COMMANDS).await;
+        let uid = local_uid(&server, "localnick").await;
+        peer.send_raw(":002 EUID remotenick 1 1000 + ruser remote.host 10.0.0.1 002AAAAAA remote.host * :Remote User").await.unwrap();
+
+        // A named server gets the request with its SID, and its numerics come back to the user
+        client.send_raw("VERSION peer.server").await.unwrap();
+        assert_eq!(peer.expect_line("VERSION").await.unwrap(), format!(":{} VERSION :002", uid));
+        peer.send_raw(&format!(":002 351 {} peer-1.0 peer.server :Peer version", uid)).await.unwrap();
+        client.expect_line(":peer.server 351 localnick peer-1.0 peer.server :Peer version").await.unwrap();
+
+        // WHOIS nick nick asks the server the user is on
+        client.send_raw("WHOIS remotenick remotenick").await.unwrap();
+        assert_eq!(peer.expect_line("WHOIS").await.unwrap(), format!(":{} WHOIS 002 :remotenick", uid));
+
+        // Requests for this server are answered to the remote user
+        peer.send_raw(":002AAAAAA VERSION :001").await.unwrap();
+        assert_eq!(peer.expect_line(" 351 ").await.unwrap(), ":001 351 002AAAAAA ircd-rs-0.1.0 test.server :Available on GitHub");
+        peer.send_raw(":002AAAAAA WHOIS test.server :localnick").await.unwrap();
+        let idle = peer.expect_line(" 317 ").await.unwrap();
+        assert!(idle.starts_with(":001 317 002AAAAAA localnick "), "Unexpected {}", idle);
+        peer.expect_line(":001 318 002AAAAAA localnick :End of /WHOIS list").await.unwrap();
+
+        // Masks match this server first, and unknown servers are reported
+        client.send_raw("LUSERS * test.*").await.unwrap();
+        client.expect_line(":test.server 251 localnick").await.unwrap();
+        client.send_raw("TIME *.nowhere").await.unwrap();
+        client.expect_line("402 localnick *.nowhere :No such server").await.unwrap();
+    }
+
+    // Reads replies up to and including the one containing the end marker
+    async fn read_until(client: &mut TestClient, end: &str) -> Vec<String> {
+        let mut lines = Vec::new();
+        loop {
+            let line = client.read_message().await.unwrap();
+            let done = line.contains(end);
+            lines.push(line);
+            if done {
+                return lines;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ts6_links_and_map() {
+        let (server, mut client, mut peer, _) = fake_peer_with_channel(PORT_TS6_LINKS_AND_MAP).await;
+        let uid = local_uid(&server, "localnick").await;
+        peer.send_raw(":002 SID far.server 2 003 :(H) Far Server").await.unwrap();
+        peer.send_raw(":002 EUID remotenick 1 1000 + ruser remote.host 10.0.0.1 002AAAAAA remote.host * :Remote User").await.unwrap();
+        peer.send_raw(":003 EUID farnick 2 1000 + far farhost 10.0.0.3 003AAAAAA farhost * :Far User").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+
+        // Hidden servers are left out for non-opers, who can't see the map
+        client.send_raw("LINKS").await.unwrap();
+        assert_eq!(read_until(&mut client, " 365 ").await, vec![
+            ":test.server 364 localnick test.server test.server :0 Test Server",
+            ":test.server 364 localnick peer.server test.server :1 Peer Server",
+            ":test.server 365 localnick * :End of /LINKS list",
+        ]);
+        client.send_raw("MAP").await.unwrap();
+        client.expect_line("481 localnick").await.unwrap();
+
+        // Operator status is announced to the network, and followed for remote users
+        client.send_raw("OPER testoper operpass").await.unwrap();
+        client.expect_line(" 381 ").await.unwrap();
+        assert_eq!(peer.expect_line(" MODE ").await.unwrap(), format!(":{} MODE {} :+o", uid, uid));
+        peer.send_raw(":002AAAAAA MODE 002AAAAAA :+o").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+        assert!(server.find_remote_user_by_uid("002AAAAAA").await.unwrap().modes.contains(&'o'));
+
+        client.send_raw("LINKS far.*").await.unwrap();
+        assert_eq!(read_until(&mut client, " 365 ").await, vec![
+            ":test.server 364 localnick far.server peer.server :2 (H) Far Server",
+            ":test.server 365 localnick far.* :End of /LINKS list",
+        ]);
+
+        // Connections that haven't registered aren't users
+        let addr = format!("127.0.0.1:{}", PORT_TS6_LINKS_AND_MAP).parse().unwrap();
+        let mut unregistered = TestClient::connect(addr).await.unwrap();
+        unregistered.send_raw("NICK pending").await.unwrap();
+        for _ in 0..50 {
+            if server.get_stats(None).await.unknown_connections == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(server.get_stats(None).await.unknown_connections, 1);
+
+        client.send_raw("MAP").await.unwrap();
+        let map = read_until(&mut client, " 017 ").await;
+        assert_eq!(map.len(), 4);
+        assert!(map[0].contains(":test.server[001] ---"), "Unexpected {}", map[0]);
+        assert!(map[0].ends_with("| Users:     1 ( 33.3%)"), "Unexpected {}", map[0]);
+        assert!(map[1].contains(":`- peer.server[002] ---"), "Unexpected {}", map[1]);
+        assert!(map[2].contains(":   `- far.server[003] ---"), "Unexpected {}", map[2]);
+        assert_eq!(map[3], ":test.server 017 localnick :End of /MAP");
+    }
+
+    #[tokio::test]
+    async fn test_ts6_lusers() {
+        let (_server, mut client, mut peer, _) = fake_peer_with_channel(PORT_TS6_LUSERS).await;
+        peer.send_raw(":002 EUID remotenick 1 1000 +i ruser remote.host 10.0.0.1 002AAAAAA remote.host * :Remote User").await.unwrap();
+        peer.send_raw(":002 EUID remoteoper 1 1000 +o oper oper.host 10.0.0.2 002AAAAAB oper.host * :Remote Oper").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+
+        // A connection that hasn't registered yet
+        let addr = format!("127.0.0.1:{}", PORT_TS6_LUSERS).parse().unwrap();
+        let mut unknown = TestClient::connect(addr).await.unwrap();
+        unknown.send_raw("PING :unknown").await.unwrap();
+        unknown.expect_line("PONG").await.unwrap();
+
+        client.send_raw("LUSERS").await.unwrap();
+        assert_eq!(read_until(&mut client, " 266 ").await, vec![
+            ":test.server 251 localnick :There are 2 users and 1 invisible on 2 servers",
+            ":test.server 252 localnick 1 :operator(s) online",
+            ":test.server 253 localnick 1 :unknown connection(s)",
+            ":test.server 254 localnick 1 :channels formed",
+            ":test.server 255 localnick :I have 1 clients and 1 servers",
+            ":test.server 265 localnick 1 1 :Current local users 1, max 1",
+            ":test.server 266 localnick 3 3 :Current global users 3, max 3",
+        ]);
+
+        // Registration sends the same counters
+        unknown.register("newnick", "newuser", "test.com").await.unwrap();
+        unknown.expect_line(":test.server 265 newnick 2 2 :Current local users 2, max 2").await.unwrap();
+        unknown.expect_line(":test.server 266 newnick 4 4 :Current global users 4, max 4").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ts6_kill() {
+        let (server, mut client, mut peer, _) = fake_peer_with_channel(PORT_TS6_KILL).await;
+        let uid = local_uid(&server, "localnick").await;
+        peer.send_raw(":002 EUID remotenick 1 1000 + ruser remote.host 10.0.0.1 002AAAAAA remote.host * :Remote User").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+
+        let addr = format!("127.0.0.1:{}", PORT_TS6_KILL).parse().unwrap();
+        let mut victim = TestClient::connect(addr).await.unwrap();
+        victim.register("victim", "vuser", "test.com").await.unwrap();
+        let victim_uid = local_uid(&server, "victim").await;
+        let mut target = TestClient::connect(addr).await.unwrap();
+        target.register("target", "tuser", "test.com").await.unwrap();
+        let target_uid = local_uid(&server, "target").await;
+
+        client.send_raw("KILL remotenick :Go away").await.unwrap();
+        client.expect_line("481 localnick").await.unwrap();
+        client.send_raw("OPER testoper operpass").await.unwrap();
+        client.expect_line(" 381 ").await.unwrap();
+
+        client.send_raw("KILL").await.unwrap();
+        client.expect_line(":test.server 461 localnick KILL :Not enough parameters").await.unwrap();
+
+        // Remote users are killed over the link with the full path
+        client.send_raw("KILL remotenick :Go away").await.unwrap();
+        let kill = peer.expect_line(" KILL ").await.unwrap();
+        assert!(kill.starts_with(&format!(":{} KILL 002AAAAAA :test.server!", uid)), "Unexpected {}", kill);
+        assert!(kill.ends_with("!localuser!localnick (Go away)"), "Unexpected {}", kill);
+        client.expect_line("Received KILL message for remotenick!ruser@remote.host. From localnick").await.unwrap();
+        assert!(server.find_remote_user_by_uid("002AAAAAA").await.is_none());
+        client.send_raw("KILL remotenick").await.unwrap();
+        client.expect_line("401 localnick remotenick").await.unwrap();
+
+        // Local users are disconnected, and the network is told
+        client.send_raw("KILL victim :Bye").await.unwrap();
+        victim.expect_line("KILL victim :Bye").await.unwrap();
+        victim.expect_line("ERROR :Closing Link").await.unwrap();
+        assert!(peer.expect_line(" KILL ").await.unwrap().starts_with(&format!(":{} KILL {} :", uid, victim_uid)));
+
+        // KILLs from the network disconnect local users without echoing back
+        peer.send_raw(&format!(":002 KILL {} :peer.server (Services)", target_uid)).await.unwrap();
+        target.expect_line(":peer.server KILL target :Services").await.unwrap();
+        let error = target.expect_line("ERROR").await.unwrap();
+        assert!(error.contains("Killed (peer.server (Services))"), "Unexpected {}", error);
+        client.expect_line("Received KILL message for target!tuser@").await.unwrap();
+        assert!(server.find_client_by_nick("target").await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ts6_remote_bans() {
+        let mut config = test_config(PORT_TS6_REMOTE_BANS);
+        config.access.ulines.push(ULine { server: "peer.server".to_string(), flags: Vec::new() });
+        config.access.olines[0].flags.push("gline".to_string());
+        let (server, mut client, mut peer, _) = fake_peer_with_config(config, PORT_TS6_REMOTE_BANS, "QS ENCAP EUID EX IE").await;
+        let uid = local_uid(&server, "localnick").await;
+        client.send_raw("OPER testoper operpass").await.unwrap();
+        client.expect_line(" 381 ").await.unwrap();
+
+        // Bans ON another server are sent there instead of being set here
+        client.send_raw("KLINE 5 bad@host.example ON peer.server :Remote ban").await.unwrap();
+        let encap = peer.expect_line(" ENCAP ").await.unwrap();
+        assert_eq!(encap, format!(":{} ENCAP peer.server KLINE 300 bad host.example :Remote ban", uid));
+        client.send_raw("UNDLINE 192.0.2.1 ON peer.server").await.unwrap();
+        let encap = peer.expect_line(" ENCAP ").await.unwrap();
+        assert_eq!(encap, format!(":{} ENCAP peer.server UNDLINE :192.0.2.1", uid));
+        assert!(server.get_klines().await.is_empty());
+
+        // G-lines are set here and on every other server
+        client.send_raw("GLINE spammer@host.example :Spam").await.unwrap();
+        let encap = peer.expect_line(" ENCAP ").await.unwrap();
+        assert_eq!(encap, format!(":{} ENCAP * GLINE 0 spammer host.example :Spam", uid));
+        assert_eq!(server.get_glines().await.len(), 1);
+        client.send_raw("UNGLINE spammer@host.example").await.unwrap();
+        let encap = peer.expect_line(" ENCAP ").await.unwrap();
+        assert_eq!(encap, format!(":{} ENCAP * UNGLINE spammer :host.example", uid));
+        assert!(server.get_glines().await.is_empty());
+
+        // Bans from the network need an operator on a U-lined server
+        peer.send_raw(":002 SID far.server 2 003 :Far Server").await.unwrap();
+        peer.send_raw(":002 EUID remoteoper 1 1000 +o ruser remote.host 10.0.0.1 002AAAAAA remote.host * :Remote Oper").await.unwrap();
+        peer.send_raw(":002 EUID remoteuser 1 1000 +i ruser remote.host 10.0.0.2 002AAAAAB remote.host * :Remote User").await.unwrap();
+        peer.send_raw(":003 EUID faroper 2 1000 +o fuser far.host 10.0.0.3 003AAAAAA far.host * :Far Oper").await.unwrap();
+        peer.send_raw(":002 ENCAP * DLINE 0 198.51.100.0/24 :From a server").await.unwrap();
+        peer.send_raw(":002AAAAAB ENCAP * DLINE 0 198.51.100.0/24 :From a user").await.unwrap();
+        peer.send_raw(":003AAAAAA ENCAP * DLINE 0 198.51.100.0/24 :From elsewhere").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+        assert!(server.get_dlines().await.is_empty());
+
+        // and those are set here
+        peer.send_raw(":002AAAAAA ENCAP * DLINE 0 198.51.100.0/24 :Bad network").await.unwrap();
+        peer.send_raw(":002AAAAAA ENCAP test.server KLINE 0 bad host.example :Network ban").await.unwrap();
+        peer.send_raw(":002AAAAAA ENCAP * GLINE 0 spammer host.example :Network spam").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+        let dlines = server.get_dlines().await;
+        assert_eq!(dlines.len(), 1);
+        assert_eq!(dlines[0].ip.to_string(), "198.51.100.0/24");
+        assert_eq!(dlines[0].set_by, "remoteoper!ruser@remote.host");
+        assert_eq!(server.get_klines().await[0].mask, "bad@host.example");
+        assert_eq!(server.get_glines().await[0].mask, "spammer@host.example");
+
+        peer.send_raw(":002AAAAAB ENCAP * UNDLINE 198.51.100.0/24").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+        assert_eq!(server.get_dlines().await.len(), 1);
+
+        peer.send_raw(":002AAAAAA ENCAP * UNDLINE 198.51.100.0/24").await.unwrap();
+        peer.send_raw(":002AAAAAA ENCAP * UNKLINE bad host.example").await.unwrap();
+        peer.send_raw(":002AAAAAA ENCAP * UNGLINE spammer host.example").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+        assert!(server.get_dlines().await.is_empty());
+        assert!(server.get_klines().await.is_empty());
+        assert!(server.get_glines().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ts6_local_propagation() {
+        let (server, _client, mut peer, ts) = fake_peer_with_channel(PORT_TS6_LOCAL_PROPAGATION).await;
+        let addr = format!("127.0.0.1:{}", PORT_TS6_LOCAL_PROPAGATION).parse().unwrap();
+
+        // Users registering after the burst are introduced
+        let mut user = TestClient::connect(addr).await.unwrap();
+        user.register("newnick", "newuser", "test.com").await.unwrap();
+        let euid = peer.expect_line(" EUID newnick ").await.unwrap();
+        let uid = local_uid(&server, "newnick").await;
+        assert!(euid.starts_with(":001 EUID newnick 1 ") && euid.contains(&format!(" newuser 127.0.0.1 127.0.0.1 {} ", uid)), "Unexpected {}", euid);
+
+        // as are their nick changes, joins, parts and quits
+        user.send_raw("NICK othernick").await.unwrap();
+        let nick = peer.expect_line(" NICK ").await.unwrap();
+        assert!(nick.starts_with(&format!(":{} NICK othernick :", uid)), "Unexpected {}", nick);
+        user.send_raw("JOIN #ts").await.unwrap();
+        peer.expect_line(&format!(":{} JOIN {} #ts :+", uid, ts)).await.unwrap();
+        user.send_raw("JOIN #new").await.unwrap();
+        let sjoin = peer.expect_line(" SJOIN ").await.unwrap();
+        assert!(sjoin.starts_with(":001 SJOIN ") && sjoin.ends_with(&format!(" #new +nt :@{}", uid)), "Unexpected {}", sjoin);
+        user.send_raw("PART #ts :Bye").await.unwrap();
+        peer.expect_line(&format!(":{} PART #ts :Bye", uid)).await.unwrap();
+        user.send_raw("QUIT :Later").await.unwrap();
+        peer.expect_line(&format!(":{} QUIT :Quit: Later", uid)).await.unwrap();
+
+        // A user killed from the network isn't quit back to it
+        let mut target = TestClient::connect(addr).await.unwrap();
+        target.register("target", "tuser", "test.com").await.unwrap();
+        peer.expect_line(" EUID target ").await.unwrap();
+        let target_uid = local_uid(&server, "target").await;
+        peer.send_raw(&format!(":002 KILL {} :peer.server (Services)", target_uid)).await.unwrap();
+        target.expect_line("ERROR").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        loop {
+            let line = peer.read_message().await.unwrap();
+            assert!(!line.contains(" QUIT "), "Unexpected {}", line);
+            if line.contains("PONG") {
+                break;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ts6_oper_commands() {
+        let mut config = test_config(PORT_TS6_OPER_COMMANDS);
+        config.access.olines[0].flags.extend(["wallops", "resv", "connect", "squit"].map(str::to_string));
+        let mut netadmin = config.access.olines[0].clone();
+        netadmin.name = "netadmin".to_string();
+        netadmin.flags.push("remotesquit".to_string());
+        config.access.olines.push(netadmin);
+        let (server, mut client, mut peer, _) = fake_peer_with_config(config, PORT_TS6_OPER_COMMANDS, "QS ENCAP EUID EX IE").await;
+        let uid = local_uid(&server, "localnick").await;
+        client.send_raw("OPER testoper operpass").await.unwrap();
+        client.expect_line(" 381 ").await.unwrap();
+        client.send_raw("MODE localnick +w").await.unwrap();
+        client.send_raw("MODE localnick").await.unwrap();
+        client.expect_line(" 221 localnick ").await.unwrap();
+
+        // WALLOPS reaches local +w users and the network
+        client.send_raw("WALLOPS :Maintenance soon").await.unwrap();
+        peer.expect_line(&format!(":{} WALLOPS :Maintenance soon", uid)).await.unwrap();
+        client.expect_line("WALLOPS :Maintenance soon").await.unwrap();
+        peer.send_raw(":002 WALLOPS :From the peer").await.unwrap();
+        client.expect_line(":peer.server WALLOPS :From the peer").await.unwrap();
+
+        // RESV reserves a nick here, or is sent to the server it is ON
+        client.send_raw("RESV badnick :Reserved").await.unwrap();
+        client.send_raw("NICK badnick").await.unwrap();
+        client.expect_line("432 localnick badnick :Reserved").await.unwrap();
+        client.send_raw("UNRESV badnick").await.unwrap();
+        client.send_raw("UNRESV badnick").await.unwrap();
+        client.expect_line("No RESV for badnick").await.unwrap();
+        client.send_raw("RESV #bad ON peer.server :No").await.unwrap();
+        peer.expect_line(&format!(":{} ENCAP peer.server RESV 0 #bad 0 :No", uid)).await.unwrap();
+        assert!(server.find_resv("#bad").await.is_none());
+
+        // CONNECT only reaches configured servers that aren't linked yet
+        client.send_raw("CONNECT unknown.server").await.unwrap();
+        client.expect_line("Connect: Host unknown.server not listed in the configuration").await.unwrap();
+        client.send_raw("CONNECT peer.server").await.unwrap();
+        client.expect_line("Connect: Server peer.server already exists").await.unwrap();
+
+        // SQUIT for servers behind a link needs remotesquit and goes to their uplink
+        peer.send_raw(":002 SID far.server 2 003 :Far Server").await.unwrap();
+        peer.send_raw("PING :peer.server").await.unwrap();
+        peer.expect_line("PONG").await.unwrap();
+        client.send_raw("SQUIT far.server :Too far").await.unwrap();
+        client.expect_line("723 localnick remotesquit").await.unwrap();
+        client.send_raw("OPER netadmin operpass").await.unwrap();
+        client.expect_line(" 381 ").await.unwrap();
+        client.send_raw("SQUIT far.server :Too far").await.unwrap();
+        peer.expect_line(&format!(":{} SQUIT 003 :Too far", uid)).await.unwrap();
+
+        // and closes our own links directly
+        client.send_raw("SQUIT peer.server :Goodbye").await.unwrap();
+        peer.expect_line("ERROR :Closing Link: peer.server (Goodbye)").await.unwrap();
+        for _ in 0..50 {
+            if server.get_linked_server("peer.server").await.is_none() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(server.get_linked_server("peer.server").await.is_none());
+        assert!(server.find_server_by_name("far.server").await.is_none());
+    }
+
     // Add more TS6 tests...
 } 
\ No newline at end of file